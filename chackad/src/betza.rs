@@ -0,0 +1,243 @@
+use crate::error::Error;
+
+// Piece movement described in Betza notation. A piece is a list of atoms,
+// each one a leap like W (one step orthogonally) or N (the knight jump).
+// Doubling an atom or adding a range makes it a rider, and the modifiers in
+// front of an atom restrict it:
+//
+//   m, c          only move, only capture
+//   f, b, l, r    forward, backward, left, right
+//   s, v          sideways (left and right), vertical (forward and backward)
+//   ff, bb, ...   the narrow oblique moves of that direction, like ffN
+//   fs, bs, ...   the wide oblique moves of that direction, like fsN
+//   fl, fr, ...   the diagonal or oblique moves between two directions
+//
+// Directions are seen from the side owning the piece.
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Mode {
+    Move,
+    Capture,
+    Both,
+}
+
+impl Mode {
+    pub fn can_move(self) -> bool {
+        self != Mode::Capture
+    }
+
+    pub fn can_capture(self) -> bool {
+        self != Mode::Move
+    }
+}
+
+// One leap repeated up to range times in the same direction. Leapers have a
+// range of 1, riders of 0 which means unlimited.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Pattern {
+    pub mode: Mode,
+    // Offsets for white, black mirrors them vertically
+    pub leaps: Vec<(i32, i32)>,
+    pub range: usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PieceDef {
+    pub name: String,
+    pub symbol: char,
+    pub value: i32,
+    pub betza: String,
+    pub patterns: Vec<Pattern>,
+}
+
+impl PieceDef {
+    pub fn new(name: &str, symbol: char, value: i32, betza: &str) -> Result<PieceDef, Error> {
+        Ok(PieceDef {
+            name: name.to_string(),
+            symbol: symbol.to_ascii_lowercase(),
+            value,
+            betza: betza.to_string(),
+            patterns: parse(betza)?,
+        })
+    }
+}
+
+fn atom(c: char) -> Option<(i32, i32)> {
+    Some(match c {
+        'W' => (1, 0),
+        'F' => (1, 1),
+        'D' => (2, 0),
+        'N' => (2, 1),
+        'A' => (2, 2),
+        'H' => (3, 0),
+        'C' => (3, 1),
+        'Z' => (3, 2),
+        'G' => (3, 3),
+        _ => return None,
+    })
+}
+
+// Shorthands for common pieces, the last field tells whether they ride
+fn shorthand(c: char) -> Option<(&'static str, bool)> {
+    Some(match c {
+        'K' => ("WF", false),
+        'R' => ("W", true),
+        'B' => ("F", true),
+        'Q' => ("WF", true),
+        _ => return None,
+    })
+}
+
+// All eight symmetric versions of a leap
+fn symmetries((x, y): (i32, i32)) -> Vec<(i32, i32)> {
+    let mut leaps = Vec::new();
+    for (a, b) in [(x, y), (y, x)].iter() {
+        for sx in [1, -1].iter() {
+            for sy in [1, -1].iter() {
+                let leap = (a * sx, b * sy);
+                if !leaps.contains(&leap) {
+                    leaps.push(leap);
+                }
+            }
+        }
+    }
+    leaps
+}
+
+fn matches_direction(group: &str, (x, y): (i32, i32)) -> bool {
+    let single = |c: char| match c {
+        'f' => y > 0,
+        'b' => y < 0,
+        'l' => x < 0,
+        'r' => x > 0,
+        's' => x != 0,
+        'v' => y != 0,
+        _ => false,
+    };
+    let mut chars = group.chars();
+    match (chars.next(), chars.next()) {
+        (Some(a), None) => single(a),
+        // Doubled letters pick the narrow moves along that direction
+        (Some(a), Some(b)) if a == b => {
+            single(a)
+                && if a == 'f' || a == 'b' {
+                    y.abs() > x.abs()
+                } else {
+                    x.abs() > y.abs()
+                }
+        }
+        (Some(a), Some('s')) => single(a) && x.abs() > y.abs(),
+        (Some(a), Some('v')) => single(a) && y.abs() > x.abs(),
+        (Some(a), Some(b)) => single(a) && single(b),
+        _ => true,
+    }
+}
+
+// Splits modifiers like "fslb" into direction groups, pairing a vertical
+// letter with the letter after it
+fn direction_groups(directions: &str) -> Vec<String> {
+    let chars: Vec<char> = directions.chars().collect();
+    let mut groups = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let pairs = match chars[i] {
+            'f' | 'b' => chars
+                .get(i + 1)
+                .map(|c| "lrs".contains(*c) || *c == chars[i])
+                .unwrap_or(false),
+            'l' | 'r' => chars
+                .get(i + 1)
+                .map(|c| *c == 'v' || *c == chars[i])
+                .unwrap_or(false),
+            _ => false,
+        };
+        if pairs {
+            groups.push(chars[i..i + 2].iter().collect());
+            i += 2;
+        } else {
+            groups.push(chars[i].to_string());
+            i += 1;
+        }
+    }
+    groups
+}
+
+pub fn parse(betza: &str) -> Result<Vec<Pattern>, Error> {
+    let chars: Vec<char> = betza.chars().collect();
+    let mut patterns = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let mut mode = Mode::Both;
+        let mut directions = String::new();
+        while i < chars.len() && chars[i].is_ascii_lowercase() {
+            match chars[i] {
+                'm' => mode = Mode::Move,
+                'c' => mode = Mode::Capture,
+                'f' | 'b' | 'l' | 'r' | 's' | 'v' => directions.push(chars[i]),
+                c => {
+                    return Err(Error::ParseError(format!(
+                        "unknown modifier '{}' in {}",
+                        c, betza
+                    )))
+                }
+            }
+            i += 1;
+        }
+
+        let c = match chars.get(i) {
+            Some(c) => *c,
+            None => {
+                return Err(Error::ParseError(format!(
+                    "modifiers without an atom in {}",
+                    betza
+                )))
+            }
+        };
+        i += 1;
+        let (atoms, mut range): (Vec<(i32, i32)>, usize) = if let Some(leap) = atom(c) {
+            // A doubled atom rides
+            if chars.get(i) == Some(&c) {
+                i += 1;
+                (vec![leap], 0)
+            } else {
+                (vec![leap], 1)
+            }
+        } else if let Some((atoms, rides)) = shorthand(c) {
+            let atoms = atoms.chars().filter_map(atom).collect();
+            (atoms, if rides { 0 } else { 1 })
+        } else {
+            return Err(Error::ParseError(format!(
+                "unknown atom '{}' in {}",
+                c, betza
+            )));
+        };
+
+        // A number limits or, with 0, removes the range
+        let start = i;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i > start {
+            let digits: String = chars[start..i].iter().collect();
+            range = digits
+                .parse()
+                .map_err(|_| Error::ParseError(format!("bad range in {}", betza)))?;
+        }
+
+        let groups = direction_groups(&directions);
+        for leap in atoms {
+            let leaps: Vec<(i32, i32)> = symmetries(leap)
+                .into_iter()
+                .filter(|l| groups.is_empty() || groups.iter().any(|g| matches_direction(g, *l)))
+                .collect();
+            if leaps.is_empty() {
+                return Err(Error::ParseError(format!(
+                    "directions {} leave no moves in {}",
+                    directions, betza
+                )));
+            }
+            patterns.push(Pattern { mode, leaps, range });
+        }
+    }
+    Ok(patterns)
+}
@@ -1,106 +1,484 @@
-mod tile {
-    #[derive(Clone)]
-    pub enum Color {
-        Black,
-        White,
-    }
+use crate::betza::PieceDef;
+use crate::error::Error;
+use crate::piece::{Color, PieceType};
+use crate::square::{Rank, Square};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+// Boards with more tiles than this only store the occupied ones
+const SPARSE_AREA: usize = 1 << 16;
 
+// Largest width and height of a board
+pub const MAX_SIZE: usize = 1_000_000;
+
+mod tile {
     #[derive(Clone)]
     pub struct Tile {
-        pub color: Color,
-
-        pub pos_x: usize,
-        pub pos_y: usize,
+        pub square: crate::square::Square,
         pub is_empty: bool,
-        pub piece_is_white: bool,
+        pub piece_color: crate::piece::Color,
         pub has_orig_piece: bool,
     }
 }
 
+// Where pawns may double step and promote. Ranks are counted from each
+// side's own back rank, so 0 is rank 1 for white and the last rank for black.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PawnRules {
+    pub double_step_ranks: Vec<usize>,
+    // Promotion is optional on all but the last of these and forced there
+    pub promotion_ranks: Vec<usize>,
+    pub promotion_pieces: Vec<PieceType>,
+    // Limits how many of a piece type a side may have through promotion, so
+    // pawns can only promote to pieces that have been captured
+    pub promotion_limits: Vec<(PieceType, usize)>,
+}
+
+impl PawnRules {
+    pub fn standard(size_y: usize) -> PawnRules {
+        PawnRules {
+            double_step_ranks: vec![1],
+            promotion_ranks: vec![size_y.saturating_sub(1)],
+            promotion_pieces: vec![
+                PieceType::Queen,
+                PieceType::Rook,
+                PieceType::Bishop,
+                PieceType::Knight,
+            ],
+            promotion_limits: Vec::new(),
+        }
+    }
+}
+
 #[derive(Clone)]
+enum Tiles {
+    // One file after the other, file x starts at index x * size_y
+    Dense(Vec<tile::Tile>),
+    // Colour of the piece on every occupied square, and the squares that
+    // lost their original piece
+    Sparse {
+        occupied: HashMap<(usize, usize), Color>,
+        moved: HashSet<(usize, usize)>,
+    },
+}
+
+// Number of whole steps from a square to another, if the other one lies on
+// the line of steps
+pub(crate) fn steps_along(offset: (i64, i64), step: (i64, i64)) -> Option<usize> {
+    let along = |offset: i64, step: i64| -> Option<Option<i64>> {
+        if step == 0 {
+            if offset == 0 {
+                Some(None)
+            } else {
+                None
+            }
+        } else if offset % step == 0 {
+            Some(Some(offset / step))
+        } else {
+            None
+        }
+    };
+    match (along(offset.0, step.0)?, along(offset.1, step.1)?) {
+        (Some(a), Some(b)) if a == b && a > 0 => Some(a as usize),
+        (Some(k), None) | (None, Some(k)) if k > 0 => Some(k as usize),
+        _ => None,
+    }
+}
+
 pub struct Board {
-    tiles: Vec<Vec<tile::Tile>>,
+    tiles: Tiles,
     pub size_x: usize,
     pub size_y: usize,
+    // Square with the signed coordinates (0, 0), which is a1 unless the
+    // position is set up in the middle of a huge board
+    pub origin: (usize, usize),
+    pub pawn_rules: PawnRules,
+    // Definitions of the PieceType::Custom pieces used on this board
+    pub custom_pieces: Arc<Vec<PieceDef>>,
+    // Tiles cut out of the rectangle, like the corners of the cross shaped
+    // four player board. Nothing stands on them or moves across them.
+    holes: Arc<HashSet<(usize, usize)>>,
+    // Direction the pawns of each Color::Player move in
+    pub player_directions: Vec<(i64, i64)>,
+    // How far past the outermost pieces a piece may move, a rule of infinite
+    // chess. Nothing outside that area can be moved to or is attacked. None
+    // lets pieces use the whole board.
+    pub move_margin: Option<usize>,
+}
+
+impl Clone for Board {
+    fn clone(&self) -> Board {
+        Board {
+            tiles: self.tiles.clone(),
+            size_x: self.size_x,
+            size_y: self.size_y,
+            origin: self.origin,
+            pawn_rules: self.pawn_rules.clone(),
+            custom_pieces: self.custom_pieces.clone(),
+            holes: self.holes.clone(),
+            player_directions: self.player_directions.clone(),
+            move_margin: self.move_margin,
+        }
+    }
+
+    // Copies the tiles into the ones already there, the search does this
+    // for every move it looks at
+    fn clone_from(&mut self, source: &Board) {
+        match (&mut self.tiles, &source.tiles) {
+            (Tiles::Dense(tiles), Tiles::Dense(from)) => tiles.clone_from(from),
+            (tiles, from) => *tiles = from.clone(),
+        }
+        self.size_x = source.size_x;
+        self.size_y = source.size_y;
+        self.origin = source.origin;
+        if self.pawn_rules != source.pawn_rules {
+            self.pawn_rules = source.pawn_rules.clone();
+        }
+        self.custom_pieces.clone_from(&source.custom_pieces);
+        self.holes.clone_from(&source.holes);
+        self.player_directions.clone_from(&source.player_directions);
+        self.move_margin = source.move_margin;
+    }
 }
 
 impl Board {
-    pub fn new(size_x: usize, size_y: usize) -> Board {
-        if size_x <= 0 || size_y <= 0 {
-            panic!("Size of board must be positive integers");
-        }
-
-        if size_x > 1_000_000 || size_y > 1_000_000 {
-            panic!("Size of board must be below 1'000'000");
-        }
-
-        let mut tiles: Vec<Vec<tile::Tile>> = Vec::new();
-
-        for i in 0..size_y {
-            tiles.push(Vec::new());
-            for j in 0..size_x {
-                let next;
-                if (i + j) % 2 == 0 {
-                    next = tile::Tile {
-                        color: tile::Color::White,
-                        pos_x: i,
-                        pos_y: j,
-                        is_empty: true,
-                        piece_is_white: false,
-                        has_orig_piece: true,
-                    };
-                } else {
-                    next = tile::Tile {
-                        color: tile::Color::Black,
-                        pos_x: i,
-                        pos_y: j,
-                        is_empty: true,
-                        piece_is_white: false,
-                        has_orig_piece: true,
-                    };
-                }
+    // An empty board. Both sides must be between 1 and 1'000'000 squares.
+    pub fn new(size_x: usize, size_y: usize) -> Result<Board, Error> {
+        if size_x == 0 || size_y == 0 || size_x > MAX_SIZE || size_y > MAX_SIZE {
+            return Err(Error::InvalidDimensions(size_x, size_y));
+        }
+        Ok(Board::sized(size_x, size_y))
+    }
 
-                tiles[i].push(next);
+    // An empty board of a size known to be valid
+    pub(crate) fn sized(size_x: usize, size_y: usize) -> Board {
+        if size_x * size_y > SPARSE_AREA {
+            return Board::with_tiles(
+                Tiles::Sparse {
+                    occupied: HashMap::new(),
+                    moved: HashSet::new(),
+                },
+                size_x,
+                size_y,
+            );
+        }
+
+        let mut tiles = Vec::with_capacity(size_x * size_y);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                tiles.push(tile::Tile {
+                    square: Square::at(x, y),
+                    is_empty: true,
+                    piece_color: Color::Neutral,
+                    has_orig_piece: true,
+                });
             }
         }
 
-        let tiles = tiles;
+        Board::with_tiles(Tiles::Dense(tiles), size_x, size_y)
+    }
+
+    fn with_tiles(tiles: Tiles, size_x: usize, size_y: usize) -> Board {
         Board {
-            tiles: tiles,
-            size_x: size_x,
-            size_y: size_y,
+            tiles,
+            size_x,
+            size_y,
+            origin: (0, 0),
+            pawn_rules: PawnRules::standard(size_y),
+            custom_pieces: Arc::new(Vec::new()),
+            holes: Arc::new(HashSet::new()),
+            player_directions: Vec::new(),
+            move_margin: None,
         }
     }
 
-    pub fn is_valid_tile(&self, pos_x: usize, pos_y: usize) -> bool {
-        if pos_x >= self.size_x || pos_y >= self.size_y {
-            return false;
-        } else {
-            return true;
+    // Whether only the occupied squares are stored, which Board::new picks
+    // for boards too large to allocate tile by tile
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.tiles, Tiles::Sparse { .. })
+    }
+
+    // Coordinates relative to the origin, negative below and left of it
+    pub fn signed(&self, square: Square) -> (i64, i64) {
+        (
+            square.file.0 as i64 - self.origin.0 as i64,
+            square.rank.0 as i64 - self.origin.1 as i64,
+        )
+    }
+
+    pub fn from_signed(&self, x: i64, y: i64) -> Option<Square> {
+        let origin = Square::at(self.origin.0, self.origin.1);
+        self.offset(origin, (x, y))
+    }
+
+    // The square the given number of tiles away, if it is on the board
+    pub fn offset(&self, from: Square, by: (i64, i64)) -> Option<Square> {
+        let x = (from.file.0 as i64).checked_add(by.0)?;
+        let y = (from.rank.0 as i64).checked_add(by.1)?;
+        if x < 0 || y < 0 || !self.is_valid_tile(Square::at(x as usize, y as usize)) {
+            return None;
         }
+        Some(Square::at(x as usize, y as usize))
     }
 
-    pub fn is_empty_tile(&self, pos_x: usize, pos_y: usize) -> bool {
-        self.tiles[pos_x][pos_y].is_empty
+    // Steps from the square in the direction of step to the first occupied
+    // square, looking at most max steps ahead. Sparse boards go through the
+    // pieces instead of the squares.
+    pub fn blocker_distance(&self, from: Square, step: (i64, i64), max: usize) -> Option<usize> {
+        let (from_x, from_y) = from.coords();
+        match &self.tiles {
+            Tiles::Dense(_) => {
+                let (mut x, mut y) = (from_x as i64, from_y as i64);
+                for k in 1..=max {
+                    x += step.0;
+                    y += step.1;
+                    if x < 0 || y < 0 || x as usize >= self.size_x || y as usize >= self.size_y {
+                        return None;
+                    }
+                    // Holes block the line like pieces do
+                    let tile = Square::at(x as usize, y as usize);
+                    if !self.is_valid_tile(tile) || !self.is_empty_tile(tile) {
+                        return Some(k);
+                    }
+                }
+                None
+            }
+            Tiles::Sparse { occupied, .. } => occupied
+                .keys()
+                .chain(self.holes.iter())
+                .filter_map(|(x, y)| {
+                    let offset = (*x as i64 - from_x as i64, *y as i64 - from_y as i64);
+                    steps_along(offset, step)
+                })
+                .filter(|k| *k <= max)
+                .min(),
+        }
     }
 
-    pub fn set_emptiness(&mut self, pos_x: usize, pos_y: usize, to_empty: bool) {
-        self.tiles[pos_x][pos_y].is_empty = to_empty;
+    // Squares a rider reaches from the square in steps: the empty ones and
+    // the first occupied one. A range of 0 means no limit.
+    pub fn ray(&self, from: Square, step: (i64, i64), range: usize) -> Vec<Square> {
+        let (min_x, min_y, max_x, max_y) = self.move_area();
+        let mut squares = Vec::new();
+        let mut square = from;
+        while range == 0 || squares.len() < range {
+            square = match self.offset(square, step) {
+                Some(square) => square,
+                None => break,
+            };
+            let (x, y) = square.coords();
+            if x < min_x || x > max_x || y < min_y || y > max_y {
+                break;
+            }
+            squares.push(square);
+            if !self.is_empty_tile(square) {
+                break;
+            }
+        }
+        squares
     }
 
-    pub fn set_piece_is_white(&mut self, pos_x: usize, pos_y: usize, is_white: bool) {
-        self.tiles[pos_x][pos_y].piece_is_white = is_white;
+    // Corners of the area moves are generated in, which is the whole board
+    // unless there is a move margin
+    pub fn move_area(&self) -> (usize, usize, usize, usize) {
+        match self.move_margin {
+            Some(margin) => self.piece_area(margin),
+            None => (0, 0, self.size_x - 1, self.size_y - 1),
+        }
     }
 
-    pub fn is_piece_white(&self, pos_x: usize, pos_y: usize) -> bool {
-        self.tiles[pos_x][pos_y].piece_is_white
+    pub fn in_move_area(&self, square: Square) -> bool {
+        let (min_x, min_y, max_x, max_y) = self.move_area();
+        let (x, y) = square.coords();
+        (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y)
     }
 
-    pub fn false_orig_piece(&mut self, x: usize, y: usize) {
-        self.tiles[x][y].has_orig_piece = false;
+    // Corners of the smallest area holding the origin and all pieces, grown
+    // by the margin on every side
+    pub fn piece_area(&self, margin: usize) -> (usize, usize, usize, usize) {
+        let occupied: Vec<(usize, usize)> = match &self.tiles {
+            Tiles::Dense(_) => self.occupied().into_iter().map(|(s, _)| s).collect(),
+            Tiles::Sparse { occupied, .. } => occupied.keys().copied().collect(),
+        };
+        let mut area = (self.origin.0, self.origin.1, self.origin.0, self.origin.1);
+        for (x, y) in occupied.iter() {
+            area = (
+                area.0.min(*x),
+                area.1.min(*y),
+                area.2.max(*x),
+                area.3.max(*y),
+            );
+        }
+        (
+            area.0.saturating_sub(margin),
+            area.1.saturating_sub(margin),
+            (area.2 + margin).min(self.size_x - 1),
+            (area.3 + margin).min(self.size_y - 1),
+        )
     }
 
-    pub fn check_orig(&self, x: usize, y: usize) -> bool {
-        self.tiles[x][y].has_orig_piece
+    pub fn is_valid_tile(&self, square: Square) -> bool {
+        let (pos_x, pos_y) = square.coords();
+        pos_x < self.size_x && pos_y < self.size_y && !self.holes.contains(&(pos_x, pos_y))
+    }
+
+    // Cuts the tile out of the board
+    pub fn remove_tile(&mut self, square: Square) {
+        Arc::make_mut(&mut self.holes).insert(square.coords());
+    }
+
+    // Tiles cut out of the board, in order
+    pub fn holes(&self) -> Vec<(usize, usize)> {
+        let mut holes: Vec<_> = self.holes.iter().cloned().collect();
+        holes.sort();
+        holes
+    }
+
+    // Occupied squares with the colour of what stands there, in order
+    pub fn occupied(&self) -> Vec<((usize, usize), Color)> {
+        let mut occupied: Vec<_> = match &self.tiles {
+            Tiles::Dense(tiles) => tiles
+                .iter()
+                .filter(|tile| !tile.is_empty)
+                .map(|tile| (tile.square.coords(), tile.piece_color))
+                .collect(),
+            Tiles::Sparse { occupied, .. } => occupied.iter().map(|(s, c)| (*s, *c)).collect(),
+        };
+        occupied.sort();
+        occupied
+    }
+
+    // Squares that lost their original piece, in order
+    pub fn moved(&self) -> Vec<(usize, usize)> {
+        let mut moved: Vec<_> = match &self.tiles {
+            Tiles::Dense(tiles) => tiles
+                .iter()
+                .filter(|tile| !tile.has_orig_piece)
+                .map(|tile| tile.square.coords())
+                .collect(),
+            Tiles::Sparse { moved, .. } => moved.iter().cloned().collect(),
+        };
+        moved.sort();
+        moved
+    }
+
+    // Direction pawns of the colour move in, up the board for white and
+    // down for black
+    pub fn forward(&self, color: Color) -> (i64, i64) {
+        match color {
+            Color::White => (0, 1),
+            Color::Black => (0, -1),
+            Color::Player(i) => self
+                .player_directions
+                .get(i as usize)
+                .cloned()
+                .unwrap_or((0, 1)),
+            Color::Neutral => (0, 0),
+        }
+    }
+
+    // Turns a step seen from white's side to the side of the colour, so
+    // pieces that move differently forwards and backwards work for everyone
+    pub fn orient(&self, color: Color, (dx, dy): (i64, i64)) -> (i64, i64) {
+        match self.forward(color) {
+            (0, -1) => (dx, -dy),
+            (1, 0) => (dy, dx),
+            (-1, 0) => (-dy, dx),
+            _ => (dx, dy),
+        }
+    }
+
+    // Rank of the square counted from the edge the colour starts at
+    pub fn rank_for(&self, square: Square, color: Color) -> usize {
+        let (pos_x, pos_y) = square.coords();
+        match self.forward(color) {
+            (0, -1) => self.size_y - 1 - pos_y,
+            (1, 0) => pos_x,
+            (-1, 0) => self.size_x - 1 - pos_x,
+            _ => pos_y,
+        }
+    }
+
+    pub fn is_empty_tile(&self, square: Square) -> bool {
+        match &self.tiles {
+            Tiles::Dense(tiles) => tiles[square.file.0 * self.size_y + square.rank.0].is_empty,
+            Tiles::Sparse { occupied, .. } => !occupied.contains_key(&square.coords()),
+        }
+    }
+
+    pub fn set_emptiness(&mut self, square: Square, to_empty: bool) {
+        match &mut self.tiles {
+            Tiles::Dense(tiles) => {
+                tiles[square.file.0 * self.size_y + square.rank.0].is_empty = to_empty
+            }
+            Tiles::Sparse { occupied, .. } => {
+                if to_empty {
+                    occupied.remove(&square.coords());
+                } else {
+                    occupied.entry(square.coords()).or_insert(Color::Neutral);
+                }
+            }
+        }
+    }
+
+    pub fn set_piece_color(&mut self, square: Square, color: Color) {
+        match &mut self.tiles {
+            Tiles::Dense(tiles) => {
+                tiles[square.file.0 * self.size_y + square.rank.0].piece_color = color
+            }
+            Tiles::Sparse { occupied, .. } => {
+                if let Some(piece_color) = occupied.get_mut(&square.coords()) {
+                    *piece_color = color;
+                }
+            }
+        }
+    }
+
+    // Colour of the piece on the square, only meaningful if there is one
+    pub fn piece_color(&self, square: Square) -> Color {
+        match &self.tiles {
+            Tiles::Dense(tiles) => tiles[square.file.0 * self.size_y + square.rank.0].piece_color,
+            Tiles::Sparse { occupied, .. } => occupied
+                .get(&square.coords())
+                .cloned()
+                .unwrap_or(Color::Neutral),
+        }
+    }
+
+    pub fn piece_def(&self, piece_type: PieceType) -> Option<&PieceDef> {
+        match piece_type {
+            PieceType::Custom(i) => self.custom_pieces.get(i as usize),
+            _ => None,
+        }
+    }
+
+    // Rank counted from the back rank of white or black
+    pub fn relative_rank(&self, rank: Rank, color: Color) -> usize {
+        if color == Color::Black {
+            self.size_y - 1 - rank.0
+        } else {
+            rank.0
+        }
+    }
+
+    pub fn false_orig_piece(&mut self, square: Square) {
+        match &mut self.tiles {
+            Tiles::Dense(tiles) => {
+                tiles[square.file.0 * self.size_y + square.rank.0].has_orig_piece = false
+            }
+            Tiles::Sparse { moved, .. } => {
+                moved.insert(square.coords());
+            }
+        }
+    }
+
+    pub fn check_orig(&self, square: Square) -> bool {
+        match &self.tiles {
+            Tiles::Dense(tiles) => {
+                tiles[square.file.0 * self.size_y + square.rank.0].has_orig_piece
+            }
+            Tiles::Sparse { moved, .. } => !moved.contains(&square.coords()),
+        }
     }
 }
@@ -0,0 +1,236 @@
+use crate::error::Error;
+use crate::game::{Game, Move};
+use crate::piece::{Color, PieceType};
+use crate::san;
+pub use crate::time::Clock;
+use crate::uci;
+use crate::variant::{Bughouse, Outcome};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Board letters of the record, upper case for white and lower case for black
+const BOARD_NAMES: [char; 2] = ['A', 'B'];
+
+// The seats of each team written like the boards in the record
+const TEAM_SEATS: [&str; 2] = ["A and b", "a and B"];
+
+// Team one plays white on board A and black on board B, team two the other
+// two colours. Partners sit on different boards with opposite colours.
+pub fn team(board: usize, white: bool) -> usize {
+    if (board == 0) == white {
+        0
+    } else {
+        1
+    }
+}
+
+// The match is over as soon as one of the boards is
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct MatchEnd {
+    pub board: usize,
+    // Result on that board
+    pub outcome: Outcome,
+    pub on_time: bool,
+}
+
+impl MatchEnd {
+    // None for a draw
+    pub fn winning_team(&self) -> Option<usize> {
+        match self.outcome {
+            Outcome::WhiteWins => Some(team(self.board, true)),
+            Outcome::BlackWins => Some(team(self.board, false)),
+            Outcome::Draw => None,
+        }
+    }
+
+    // Which team won and where, like "team 2 (a and B) wins on time on
+    // board A"
+    pub fn describe(&self) -> String {
+        let board = BOARD_NAMES[self.board];
+        let how = if self.on_time { "on time " } else { "" };
+        match self.winning_team() {
+            Some(team) => format!(
+                "team {} ({}) wins {}on board {}",
+                team + 1,
+                TEAM_SEATS[team],
+                how,
+                board
+            ),
+            None => format!("draw on board {}", board),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BughouseMove {
+    pub board: usize,
+    pub white: bool,
+    pub number: u32,
+    pub san: String,
+    // Left on the mover's clock after the move
+    pub remaining: Duration,
+}
+
+// Two games of bughouse played side by side. Pieces captured on one board go
+// to the pocket of the capturer's partner on the other.
+#[derive(Clone)]
+pub struct BughouseMatch {
+    boards: [Game; 2],
+    clocks: [Clock; 2],
+    time: Duration,
+    increment: Duration,
+    record: Vec<BughouseMove>,
+    end: Option<MatchEnd>,
+}
+
+impl BughouseMatch {
+    pub fn new(time: Duration, increment: Duration) -> BughouseMatch {
+        BughouseMatch {
+            boards: [
+                Game::with_variant(Arc::new(Bughouse)),
+                Game::with_variant(Arc::new(Bughouse)),
+            ],
+            clocks: [Clock::new(time, increment); 2],
+            time,
+            increment,
+            record: Vec::new(),
+            end: None,
+        }
+    }
+
+    pub fn board(&self, board: usize) -> &Game {
+        &self.boards[board]
+    }
+
+    pub fn clock(&self, board: usize) -> &Clock {
+        &self.clocks[board]
+    }
+
+    pub fn record(&self) -> &[BughouseMove] {
+        &self.record
+    }
+
+    pub fn end(&self) -> Option<MatchEnd> {
+        self.end
+    }
+
+    // Plays a move on one board after the mover spent the given time on it
+    pub fn play(&mut self, board: usize, m: Move, spent: Duration) -> Result<(), Error> {
+        if self.end.is_some() {
+            return Err(Error::GameOver);
+        }
+        let name = BOARD_NAMES[board];
+        let game = &self.boards[board];
+        if !game.legal_moves().contains(&m) {
+            let m = uci::move_to_uci(game, &m, false);
+            return Err(Error::IllegalMove(format!("{} on board {}", m, name)));
+        }
+        let white = game.is_whites_turn();
+        if !self.clocks[board].spend(white, spent) {
+            self.end_on_time(board, white);
+            return Err(Error::OutOfTime(format!("on board {}", name)));
+        }
+
+        // En passant captures leave the target square empty, and promoted
+        // pieces are handed over as pawns
+        let captured = if game.is_capture(&m) {
+            match game.piece_at(m.to) {
+                Some(piece) if !game.is_promoted(m.to) => Some(piece.piece_type),
+                _ => Some(PieceType::Pawn),
+            }
+        } else {
+            None
+        };
+        let number = game.fullmove_number();
+        let san = san::move_to_san(game, &m);
+
+        let game = &mut self.boards[board];
+        game.play_legal(m);
+        if let Some(piece_type) = captured {
+            self.boards[1 - board].add_to_pocket(Color::from_white(!white), piece_type);
+        }
+        self.record.push(BughouseMove {
+            board,
+            white,
+            number,
+            san,
+            remaining: self.clocks[board].remaining(white),
+        });
+        if let Some(outcome) = self.boards[board].outcome() {
+            self.end = Some(MatchEnd {
+                board,
+                outcome,
+                on_time: false,
+            });
+        }
+        Ok(())
+    }
+
+    // Ends the match if the side to move on the board has thought for
+    // longer than its clock allows, returns whether it did
+    pub fn check_flag(&mut self, board: usize, thinking: Duration) -> bool {
+        let white = self.boards[board].is_whites_turn();
+        if self.end.is_none() && thinking >= self.clocks[board].remaining(white) {
+            self.end_on_time(board, white);
+        }
+        self.end.is_some()
+    }
+
+    fn end_on_time(&mut self, board: usize, white: bool) {
+        self.end = Some(MatchEnd {
+            board,
+            outcome: Outcome::win_for(Color::from_white(!white)),
+            on_time: true,
+        });
+    }
+
+    // Writes the match in the style of BPGN. Moves are numbered per board
+    // like 1A. for white and 1a. for black on board A, followed by the time
+    // left on the clock. The result is a win of team one, white on A and
+    // black on B, with 1-0 and of team two with 0-1, and a comment before it
+    // names the winning team.
+    pub fn to_bpgn(&self) -> String {
+        let result = match self.end {
+            Some(end) => match end.winning_team() {
+                Some(0) => "1-0",
+                Some(_) => "0-1",
+                None => "1/2-1/2",
+            },
+            None => "*",
+        };
+        let mut bpgn = format!(
+            "[Event \"Bughouse\"]\n[TimeControl \"{}+{}\"]\n[Result \"{}\"]\n\n",
+            self.time.as_secs(),
+            self.increment.as_secs(),
+            result
+        );
+        let moves: Vec<String> = self
+            .record
+            .iter()
+            .map(|m| {
+                let name = BOARD_NAMES[m.board];
+                format!(
+                    "{}{}. {} {{{:.1}}}",
+                    m.number,
+                    if m.white {
+                        name
+                    } else {
+                        name.to_ascii_lowercase()
+                    },
+                    m.san,
+                    m.remaining.as_secs_f64()
+                )
+            })
+            .collect();
+        bpgn.push_str(&moves.join(" "));
+        if !moves.is_empty() {
+            bpgn.push(' ');
+        }
+        if let Some(end) = self.end {
+            bpgn.push_str(&format!("{{{}}} ", end.describe()));
+        }
+        bpgn.push_str(result);
+        bpgn.push('\n');
+        bpgn
+    }
+}
@@ -0,0 +1,223 @@
+use crate::board::Board;
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::piece::{Color, PieceType};
+use crate::square::{File, Rank, Square};
+use std::fmt;
+
+const LIGHT_SQUARE: &str = "\x1b[48;5;180m";
+const DARK_SQUARE: &str = "\x1b[48;5;137m";
+const HIGHLIGHTED_SQUARE: &str = "\x1b[48;5;185m";
+const WHITE_PIECE: &str = "\x1b[1;97m";
+const BLACK_PIECE: &str = "\x1b[1;30m";
+const NEUTRAL_PIECE: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+// Squares drawn past the outermost pieces of a sparse board
+const DRAW_MARGIN: usize = 8;
+
+// How a board is drawn as text. The defaults, which Display uses, are
+// letters as in FEN with a1 in the bottom left corner and coordinates
+// along the edges.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DiagramStyle {
+    // Chess figurines instead of letters for the standard pieces
+    pub unicode: bool,
+    // Coloured squares and pieces for terminals that understand ANSI codes
+    pub ansi: bool,
+    // Seen from black's side, with a1 in the top right corner
+    pub flipped: bool,
+    pub coordinates: bool,
+    // Squares drawn in brackets, or in another colour with ANSI codes
+    pub highlights: Vec<Square>,
+}
+
+impl DiagramStyle {
+    pub fn new() -> DiagramStyle {
+        DiagramStyle {
+            unicode: false,
+            ansi: false,
+            flipped: false,
+            coordinates: true,
+            highlights: Vec::new(),
+        }
+    }
+
+    // Highlights the squares the move goes from and to
+    pub fn highlight_move(&mut self, m: &Move) {
+        if !m.is_drop() {
+            self.highlights.push(m.from);
+        }
+        self.highlights.push(m.to);
+    }
+}
+
+impl Default for DiagramStyle {
+    fn default() -> DiagramStyle {
+        DiagramStyle::new()
+    }
+}
+
+// Unicode chess symbol of a standard piece
+pub fn figurine(piece_type: PieceType, white: bool) -> Option<char> {
+    let figurines = match piece_type {
+        PieceType::King => ('♔', '♚'),
+        PieceType::Queen => ('♕', '♛'),
+        PieceType::Rook => ('♖', '♜'),
+        PieceType::Bishop => ('♗', '♝'),
+        PieceType::Knight => ('♘', '♞'),
+        PieceType::Pawn => ('♙', '♟'),
+        _ => return None,
+    };
+    Some(if white { figurines.0 } else { figurines.1 })
+}
+
+// The position as a grid of pieces
+pub fn game_diagram(game: &Game, style: &DiagramStyle) -> String {
+    let board = game.board();
+    draw(board, style, |square| {
+        let piece = game.piece_at(square)?;
+        let letter = fen::piece_type_char(board, piece.piece_type);
+        let c = match piece.color {
+            Color::White => match figurine(piece.piece_type, true) {
+                Some(c) if style.unicode => c,
+                _ => letter.to_ascii_uppercase(),
+            },
+            Color::Black => match figurine(piece.piece_type, false) {
+                Some(c) if style.unicode => c,
+                _ => letter,
+            },
+            _ => letter,
+        };
+        Some((c, piece.color))
+    })
+}
+
+// The tiles of the board, which only know the colour of what stands on
+// them: w and b for white and black, * for neutral pieces and the player
+// number for the ones of multiplayer games
+pub fn board_diagram(board: &Board, style: &DiagramStyle) -> String {
+    draw(board, style, |square| {
+        if board.is_empty_tile(square) {
+            return None;
+        }
+        let color = board.piece_color(square);
+        let c = match color {
+            Color::White => 'w',
+            Color::Black => 'b',
+            Color::Neutral => '*',
+            Color::Player(i) => std::char::from_digit(i as u32 + 1, 36).unwrap_or('?'),
+        };
+        Some((c, color))
+    })
+}
+
+// Sparse boards are drawn as far as pieces may move, or a few squares past
+// the outermost pieces, the rest of them is empty anyway
+fn draw<F>(board: &Board, style: &DiagramStyle, piece: F) -> String
+where
+    F: Fn(Square) -> Option<(char, Color)>,
+{
+    let (min_x, min_y, max_x, max_y) = if board.is_sparse() {
+        board.piece_area(board.move_margin.unwrap_or(DRAW_MARGIN))
+    } else {
+        board.move_area()
+    };
+    let mut files: Vec<usize> = (min_x..=max_x).collect();
+    let mut ranks: Vec<usize> = (min_y..=max_y).rev().collect();
+    if style.flipped {
+        files.reverse();
+        ranks.reverse();
+    }
+    let width = files
+        .iter()
+        .map(|x| File(*x).to_string().len())
+        .max()
+        .unwrap_or(1);
+    let label_width = Rank(max_y).to_string().len();
+
+    let mut out = String::new();
+    for y in ranks.iter() {
+        if style.coordinates {
+            out.push_str(&format!("{:>w$} ", Rank(*y).to_string(), w = label_width));
+        }
+        let mut bracket_open = false;
+        for x in files.iter() {
+            let square = Square::at(*x, *y);
+            let highlighted = style.highlights.contains(&square);
+            let (c, color) = match piece(square) {
+                Some((c, color)) => (c, Some(color)),
+                None if board.is_valid_tile(square) => ('.', None),
+                None => (' ', None),
+            };
+            let cell = format!("{:<w$}", c, w = width);
+            if style.ansi {
+                if board.is_valid_tile(square) {
+                    out.push_str(if highlighted {
+                        HIGHLIGHTED_SQUARE
+                    } else if (x + y) % 2 == 0 {
+                        DARK_SQUARE
+                    } else {
+                        LIGHT_SQUARE
+                    });
+                    out.push_str(match color {
+                        Some(Color::White) => WHITE_PIECE,
+                        Some(Color::Black) => BLACK_PIECE,
+                        Some(_) => NEUTRAL_PIECE,
+                        None => "",
+                    });
+                }
+                out.push(' ');
+                out.push_str(&cell);
+                out.push_str(RESET);
+            } else {
+                // Brackets replace the spaces around a highlighted square
+                out.push(if highlighted {
+                    '['
+                } else if bracket_open {
+                    ']'
+                } else {
+                    ' '
+                });
+                out.push_str(&cell);
+                bracket_open = highlighted;
+            }
+        }
+        if bracket_open {
+            out.push(']');
+        }
+        out.push('\n');
+    }
+    if style.coordinates {
+        out.push_str(&" ".repeat(label_width + 1));
+        for x in files.iter() {
+            out.push_str(&format!(" {:<w$}", File(*x).to_string(), w = width));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", game_diagram(self, &DiagramStyle::new()))
+    }
+}
+
+// The FEN and the diagram, so failed assertions on games show the position.
+// The FEN of a sparse board has a run of digits for each of its many ranks,
+// so there only the diagram around the pieces is shown.
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.board().is_sparse() {
+            return write!(f, "{}", self);
+        }
+        write!(f, "{}\n{}", fen::to_fen(self), self)
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", board_diagram(self, &DiagramStyle::new()))
+    }
+}
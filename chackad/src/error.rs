@@ -0,0 +1,40 @@
+use std::fmt;
+
+// Everything that can go wrong with input handed to the library. Malformed
+// or illegal input is reported with one of these instead of a panic.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Error {
+    // A square name like "e4" that doesn't parse or lies off the board
+    InvalidSquare(String),
+    // A move that is not legal in the position, written as in UCI
+    IllegalMove(String),
+    // Width and height of a board that can't be created
+    InvalidDimensions(usize, usize),
+    // Malformed FEN, Betza or other notation
+    ParseError(String),
+    // A piece definition that can't be added to a game
+    InvalidPiece(String),
+    // A position that can't be set up
+    InvalidPosition(String),
+    // A move played after the end of the game
+    GameOver,
+    // A move that came after the flag fell
+    OutOfTime(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidSquare(s) => write!(f, "invalid square '{}'", s),
+            Error::IllegalMove(m) => write!(f, "illegal move {}", m),
+            Error::InvalidDimensions(x, y) => write!(f, "unsupported board size {}x{}", x, y),
+            Error::ParseError(s) | Error::InvalidPiece(s) | Error::InvalidPosition(s) => {
+                write!(f, "{}", s)
+            }
+            Error::GameOver => write!(f, "the game is over"),
+            Error::OutOfTime(s) => write!(f, "time is up {}", s),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,512 @@
+use crate::betza::PieceDef;
+use crate::board::{self, Board};
+use crate::error::Error;
+use crate::game::{CastlingRight, Game};
+use crate::piece::{Color, Piece, PieceType};
+use crate::square::{File, Rank, Square};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Checks needed to win when a FEN counts the remaining ones
+const THREE_CHECKS: u32 = 3;
+
+// Piece type of a FEN letter of either case, including custom pieces
+pub fn piece_type_from_char(board: &Board, c: char) -> Option<PieceType> {
+    let c = c.to_ascii_lowercase();
+    Some(match c {
+        'p' => PieceType::Pawn,
+        'r' => PieceType::Rook,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        'a' => PieceType::Archbishop,
+        'c' => PieceType::Chancellor,
+        '*' => PieceType::Duck,
+        _ => {
+            let i = board.custom_pieces.iter().position(|d| d.symbol == c)?;
+            PieceType::Custom(i as u8)
+        }
+    })
+}
+
+// Lower case FEN letter of a piece type
+pub fn piece_type_char(board: &Board, piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'p',
+        PieceType::Rook => 'r',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+        PieceType::Archbishop => 'a',
+        PieceType::Chancellor => 'c',
+        PieceType::Duck => '*',
+        PieceType::Custom(_) => board.piece_def(piece_type).map(|d| d.symbol).unwrap_or('?'),
+    }
+}
+
+// Parses a FEN string. The castling field may use KQkq, Shredder-FEN file
+// letters or a mix of both (X-FEN), so Chess960 positions are supported.
+// Crazyhouse pockets follow the board in brackets as in "...RNBQKBNR[QRp]"
+// and promoted pieces are marked with a tilde like "Q~". The duck of Duck
+// chess is written as "*". Runs of empty squares may take several digits,
+// so boards of any size can be written.
+pub fn from_fen(fen: &str) -> Result<Game, Error> {
+    from_fen_with_pieces(fen, &[])
+}
+
+// Parses a FEN string that may contain the given custom pieces
+pub fn from_fen_with_pieces(fen: &str, custom_pieces: &[PieceDef]) -> Result<Game, Error> {
+    let mut symbols = Board::sized(1, 1);
+    symbols.custom_pieces = Arc::new(custom_pieces.to_vec());
+
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(Error::ParseError(format!(
+            "expected at least 4 fields in FEN '{}'",
+            fen
+        )));
+    }
+
+    let (placement, pocket) = match fields[0].find('[') {
+        Some(i) if fields[0].ends_with(']') => (
+            &fields[0][..i],
+            Some(&fields[0][i + 1..fields[0].len() - 1]),
+        ),
+        Some(_) => {
+            return Err(Error::ParseError(format!(
+                "unterminated pocket in '{}'",
+                fields[0]
+            )))
+        }
+        None => (fields[0], None),
+    };
+
+    // Ranks are listed from the top, so the first one is the last rank
+    let ranks: Vec<&str> = placement.split('/').collect();
+    let size_y = ranks.len();
+    let mut placed: Vec<(PieceType, usize, usize, Color)> = Vec::new();
+    let mut promoted = Vec::new();
+    let mut size_x = None;
+    for (i, rank) in ranks.iter().enumerate() {
+        let y = size_y - 1 - i;
+        let mut x = 0;
+        let mut empty: usize = 0;
+        for c in rank.chars() {
+            if let Some(d) = c.to_digit(10) {
+                empty = empty
+                    .checked_mul(10)
+                    .and_then(|e| e.checked_add(d as usize))
+                    .filter(|e| *e <= board::MAX_SIZE)
+                    .ok_or_else(|| {
+                        Error::ParseError(format!("too many empty squares in '{}'", rank))
+                    })?;
+                continue;
+            }
+            if c == '~' {
+                match placed.last() {
+                    Some((_, px, py, _)) if empty == 0 && *py == y => {
+                        promoted.push(Square::at(*px, *py))
+                    }
+                    _ => {
+                        return Err(Error::ParseError(format!(
+                            "promotion marker without a piece in '{}'",
+                            rank
+                        )))
+                    }
+                }
+                continue;
+            }
+            x += empty;
+            empty = 0;
+            match piece_type_from_char(&symbols, c) {
+                Some(PieceType::Duck) => placed.push((PieceType::Duck, x, y, Color::Neutral)),
+                Some(piece_type) => {
+                    placed.push((piece_type, x, y, Color::from_white(c.is_ascii_uppercase())))
+                }
+                None => return Err(Error::ParseError(format!("unknown piece '{}'", c))),
+            }
+            x += 1;
+        }
+        x += empty;
+        match size_x {
+            None => size_x = Some(x),
+            Some(size) if size != x => {
+                return Err(Error::ParseError(format!(
+                    "rank {} has {} files instead of {}",
+                    y + 1,
+                    x,
+                    size
+                )))
+            }
+            _ => {}
+        }
+    }
+    let size_x = size_x.unwrap_or(0);
+
+    let mut pockets = (Vec::new(), Vec::new());
+    for c in pocket.unwrap_or("").chars() {
+        match piece_type_from_char(&symbols, c) {
+            Some(piece_type) if c.is_ascii_uppercase() => pockets.0.push(piece_type),
+            Some(piece_type) => pockets.1.push(piece_type),
+            None => {
+                return Err(Error::ParseError(format!(
+                    "unknown piece '{}' in pocket",
+                    c
+                )))
+            }
+        }
+    }
+
+    let mut board = Board::new(size_x, size_y)?;
+    board.custom_pieces = symbols.custom_pieces;
+    let pieces: Vec<Piece> = placed
+        .iter()
+        .map(|(piece_type, x, y, color)| {
+            Piece::new(&mut board, *piece_type, Square::at(*x, *y), *color)
+        })
+        .collect();
+
+    let whites_turn = match fields[1] {
+        "w" => true,
+        "b" => false,
+        other => {
+            return Err(Error::ParseError(format!(
+                "unknown side to move '{}'",
+                other
+            )))
+        }
+    };
+
+    let mut castling = Vec::new();
+    if fields[2] != "-" {
+        for c in castling_tokens(fields[2], size_x) {
+            let is_white = c.starts_with(|c: char| c.is_ascii_uppercase());
+            let rank = if is_white { 0 } else { size_y - 1 };
+            let king_x = pieces
+                .iter()
+                .find(|p| {
+                    p.piece_type == PieceType::King
+                        && p.is_white() == is_white
+                        && p.square.rank.0 == rank
+                })
+                .map(|p| p.square.file.0)
+                .ok_or_else(|| {
+                    Error::ParseError(format!(
+                        "castling right '{}' without a king on the back rank",
+                        c
+                    ))
+                })?;
+            let is_rook = |x: &usize| {
+                pieces.iter().any(|p| {
+                    p.piece_type == PieceType::Rook
+                        && p.is_white() == is_white
+                        && p.square == Square::at(*x, rank)
+                })
+            };
+            // K and Q mean the outermost rook on that side of the king
+            let rook_x = match c.as_str() {
+                "K" | "k" => (king_x + 1..size_x).rev().find(is_rook),
+                "Q" | "q" => (0..king_x).find(is_rook),
+                name => name
+                    .parse::<File>()
+                    .ok()
+                    .map(|file| file.0)
+                    .filter(|x| *x < size_x)
+                    .filter(is_rook),
+            };
+            let right = rook_x.map(|x| CastlingRight {
+                color: Color::from_white(is_white),
+                rook_file: File(x),
+            });
+            match right {
+                Some(right) if castling.contains(&right) => {
+                    return Err(Error::ParseError(format!(
+                        "castling right '{}' is given twice",
+                        c
+                    )))
+                }
+                Some(right) => castling.push(right),
+                None => {
+                    return Err(Error::ParseError(format!(
+                        "castling right '{}' without a rook",
+                        c
+                    )))
+                }
+            }
+        }
+    }
+
+    // The en passant square is empty, with the pawn that skipped it in
+    // front and the square it came from behind
+    let en_passant = match fields[3] {
+        "-" => None,
+        s => {
+            let mover = Color::from_white(!whites_turn);
+            let forward = board.forward(mover);
+            let skipped = s.parse::<Square>().ok().filter(|square| {
+                let pawn = square.offset(forward, &board);
+                let from = square.offset((-forward.0, -forward.1), &board);
+                board.is_valid_tile(*square)
+                    && board.is_empty_tile(*square)
+                    && from.is_some_and(|from| board.is_empty_tile(from))
+                    && pawn.is_some_and(|pawn| {
+                        pieces.iter().any(|p| {
+                            p.square == pawn && p.piece_type == PieceType::Pawn && p.color == mover
+                        })
+                    })
+            });
+            match skipped {
+                Some(square) => Some(square),
+                None => {
+                    return Err(Error::ParseError(format!(
+                        "invalid en passant square '{}'",
+                        s
+                    )))
+                }
+            }
+        }
+    };
+
+    // Three-check counters, either the checks still needed as in "3+3"
+    // after the en passant field or the checks given as in "+0+0" at the end
+    let mut counters: Vec<&str> = fields[4..].to_vec();
+    let mut checks_given = [0, 0];
+    let parse_checks = |s: &str| -> Result<[u32; 2], Error> {
+        let numbers: Vec<&str> = s.trim_start_matches('+').split('+').collect();
+        match numbers.as_slice() {
+            [white, black] => match (white.parse(), black.parse()) {
+                (Ok(white), Ok(black)) => Ok([white, black]),
+                _ => Err(Error::ParseError(format!("invalid check counter '{}'", s))),
+            },
+            _ => Err(Error::ParseError(format!("invalid check counter '{}'", s))),
+        }
+    };
+    if let Some(first) = counters.first() {
+        if first.contains('+') && !first.starts_with('+') {
+            let remaining = parse_checks(first)?;
+            checks_given = [
+                THREE_CHECKS.saturating_sub(remaining[0]),
+                THREE_CHECKS.saturating_sub(remaining[1]),
+            ];
+            counters.remove(0);
+        }
+    }
+    if let Some(last) = counters.last() {
+        if last.starts_with('+') {
+            checks_given = parse_checks(last)?;
+            counters.pop();
+        }
+    }
+
+    let number = |i: usize, default: u32| match counters.get(i) {
+        Some(s) => s
+            .parse::<u32>()
+            .map_err(|_| Error::ParseError(format!("invalid move counter '{}'", s))),
+        None => Ok(default),
+    };
+    let halfmove_clock = number(0, 0)?;
+    let fullmove_number = number(1, 1)?;
+
+    let mut game = Game::from_parts(
+        board,
+        pieces,
+        whites_turn,
+        castling,
+        en_passant,
+        halfmove_clock,
+        fullmove_number,
+    );
+    if checks_given != [0, 0] {
+        game.set_checks_given(checks_given[0], checks_given[1]);
+    }
+    if !pockets.0.is_empty() || !pockets.1.is_empty() {
+        game.set_pockets(pockets.0, pockets.1);
+    }
+    if !promoted.is_empty() {
+        game.set_promoted(promoted);
+    }
+    Ok(game)
+}
+
+// Writes X-FEN, which is regular FEN except for Chess960 castling rights
+// that can't be told apart by KQkq alone
+pub fn to_fen(game: &Game) -> String {
+    write_fen(game, false)
+}
+
+// Writes Shredder-FEN, which always names the rook files in the castling field
+pub fn to_shredder_fen(game: &Game) -> String {
+    write_fen(game, true)
+}
+
+fn write_fen(game: &Game, shredder: bool) -> String {
+    let board = game.board();
+    let drops = game.variant().drops();
+    // The ranks are written from the pieces on them, so the empty ranks of
+    // a sparse board cost no more than the number they are written as
+    let mut by_rank: BTreeMap<usize, Vec<&Piece>> = BTreeMap::new();
+    for piece in game.all_pieces() {
+        by_rank.entry(piece.square.rank.0).or_default().push(piece);
+    }
+    let empty_rank = board.size_x.to_string();
+    let mut ranks = Vec::with_capacity(board.size_y);
+    for y in (0..board.size_y).rev() {
+        let pieces = match by_rank.get_mut(&y) {
+            Some(pieces) => pieces,
+            None => {
+                ranks.push(empty_rank.clone());
+                continue;
+            }
+        };
+        pieces.sort_by_key(|p| p.square.file);
+        let mut rank = String::new();
+        let mut x = 0;
+        for piece in pieces.iter() {
+            let empty = piece.square.file.0 - x;
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            let c = piece_type_char(board, piece.piece_type);
+            rank.push(if piece.is_white() {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            });
+            if drops && game.is_promoted(piece.square) {
+                rank.push('~');
+            }
+            x = piece.square.file.0 + 1;
+        }
+        if x < board.size_x {
+            rank.push_str(&(board.size_x - x).to_string());
+        }
+        ranks.push(rank);
+    }
+
+    let mut placement = ranks.join("/");
+    if drops {
+        placement.push('[');
+        for color in [Color::White, Color::Black].iter() {
+            for piece_type in game.pocket(*color) {
+                let c = piece_type_char(board, *piece_type);
+                placement.push(if color.is_white() {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                });
+            }
+        }
+        placement.push(']');
+    }
+
+    let mut castling = String::new();
+    for color in [Color::White, Color::Black].iter() {
+        // White rights first, each colour from the h side to the a side
+        for right in game
+            .castling_rights()
+            .iter()
+            .rev()
+            .filter(|r| r.color == *color)
+        {
+            let name = if shredder {
+                right.rook_file.to_string()
+            } else {
+                castling_name(game, right)
+            };
+            castling.push_str(&if color.is_white() {
+                name.to_ascii_uppercase()
+            } else {
+                name
+            });
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+
+    let en_passant = match game.en_passant() {
+        Some(square) => square.to_string(),
+        None => "-".to_string(),
+    };
+
+    // Variants that count checks write the ones still needed to win
+    let checks = match game.variant().check_limit() {
+        Some(limit) => format!(
+            " {}+{}",
+            limit.saturating_sub(game.checks_given(Color::White)),
+            limit.saturating_sub(game.checks_given(Color::Black))
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        "{} {} {} {}{} {} {}",
+        placement,
+        if game.is_whites_turn() { "w" } else { "b" },
+        castling,
+        en_passant,
+        checks,
+        game.halfmove_clock(),
+        game.fullmove_number()
+    )
+}
+
+// K or Q when the rook is the outermost one on its side, the file otherwise
+fn castling_name(game: &Game, right: &CastlingRight) -> String {
+    let rank = game.board().relative_rank(Rank(0), right.color);
+    let files = |piece_type: PieceType| -> Vec<usize> {
+        let own = if right.color.is_white() {
+            game.white_pieces_iter()
+        } else {
+            game.black_pieces_iter()
+        };
+        own.filter(|p| p.piece_type == piece_type && p.square.rank.0 == rank)
+            .map(|p| p.square.file.0)
+            .collect()
+    };
+    let king_x = files(PieceType::King).first().cloned().unwrap_or(0);
+    let rooks = files(PieceType::Rook);
+    let rook_x = right.rook_file.0;
+    let outer = if rook_x > king_x {
+        rooks.iter().filter(|x| **x > king_x).max()
+    } else {
+        rooks.iter().filter(|x| **x < king_x).min()
+    };
+    match outer {
+        Some(x) if *x == rook_x && rook_x > king_x => "k".to_string(),
+        Some(x) if *x == rook_x => "q".to_string(),
+        _ => right.rook_file.to_string(),
+    }
+}
+
+// Splits the castling field into its rights. Files of boards wider than 26
+// have names of several letters that run together, so a right is K, Q or
+// the longest run of letters of one case that names a file of the board.
+fn castling_tokens(field: &str, size_x: usize) -> Vec<String> {
+    let chars: Vec<char> = field.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let upper = chars[i].is_ascii_uppercase();
+        let run = chars[i..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic() && c.is_ascii_uppercase() == upper)
+            .count();
+        let is_file = |n: &usize| {
+            let name: String = chars[i..i + n].iter().collect();
+            name.parse::<File>().is_ok_and(|file| file.0 < size_x)
+        };
+        let len = if "KQkq".contains(chars[i]) {
+            1
+        } else {
+            (1..=run).rev().find(is_file).unwrap_or(1)
+        };
+        tokens.push(chars[i..i + len].iter().collect());
+        i += len;
+    }
+    tokens
+}
@@ -0,0 +1,200 @@
+use crate::game::{Game, Move};
+use crate::piece::{Color, Piece, PieceType};
+use crate::square::Square;
+use crate::variant::Outcome;
+use std::collections::HashSet;
+use std::fmt;
+
+// How much of the position a player gets to see
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Visibility {
+    Full,
+    // Own pieces and every square they can move or capture to
+    Dark,
+    // Own pieces only
+    OwnPieces,
+}
+
+// The part of a position one side can see, made by Game::view_for
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct View {
+    pub color: Color,
+    pieces: Vec<Piece>,
+    // None when everything is visible
+    visible: Option<HashSet<Square>>,
+}
+
+impl View {
+    pub(crate) fn new(color: Color, pieces: Vec<Piece>, visible: Option<HashSet<Square>>) -> View {
+        View {
+            color,
+            pieces,
+            visible,
+        }
+    }
+
+    pub fn is_visible(&self, square: Square) -> bool {
+        match &self.visible {
+            Some(visible) => visible.contains(&square),
+            None => true,
+        }
+    }
+
+    // The pieces on visible squares, own and enemy
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+
+    pub fn piece_at(&self, square: Square) -> Option<&Piece> {
+        self.pieces.iter().find(|p| p.square == square)
+    }
+}
+
+// Line along which a check is given, as seen from the checked king
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CheckDirection {
+    File,
+    Rank,
+    // The longer and the shorter of the two diagonals through the king
+    LongDiagonal,
+    ShortDiagonal,
+    Knight,
+    // Leaps of fairy pieces that are none of the above
+    Other,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Announcement {
+    Illegal,
+    // Square the captured piece stood on
+    Capture(Square),
+    Check(CheckDirection),
+    GameOver(Outcome),
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Announcement::Illegal => write!(f, "illegal"),
+            Announcement::Capture(square) => write!(f, "capture on {}", square),
+            Announcement::Check(direction) => match direction {
+                CheckDirection::File => write!(f, "check along the file"),
+                CheckDirection::Rank => write!(f, "check along the rank"),
+                CheckDirection::LongDiagonal => write!(f, "check along the long diagonal"),
+                CheckDirection::ShortDiagonal => write!(f, "check along the short diagonal"),
+                CheckDirection::Knight => write!(f, "knight check"),
+                CheckDirection::Other => write!(f, "check"),
+            },
+            Announcement::GameOver(Outcome::WhiteWins) => write!(f, "white wins"),
+            Announcement::GameOver(Outcome::BlackWins) => write!(f, "black wins"),
+            Announcement::GameOver(Outcome::Draw) => write!(f, "draw"),
+        }
+    }
+}
+
+// Referee of a Kriegspiel game. Players only know their own pieces and try
+// moves, the umpire plays the legal ones and tells both players what they
+// are allowed to know about them.
+#[derive(Clone)]
+pub struct Umpire {
+    game: Game,
+}
+
+impl Umpire {
+    pub fn new(game: Game) -> Umpire {
+        Umpire { game }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn view(&self, color: Color) -> View {
+        self.game.view_for(color)
+    }
+
+    // Judges an attempt of the side to move. Illegal attempts leave the
+    // position as it was so the player can try again.
+    pub fn attempt(&mut self, m: Move) -> Vec<Announcement> {
+        if !self.game.legal_moves().contains(&m) {
+            return vec![Announcement::Illegal];
+        }
+        let mut announcements = Vec::new();
+        if self.game.is_capture(&m) {
+            // En passant takes the pawn beside the target square
+            let square = if self.game.board().is_empty_tile(m.to) {
+                Square::new(m.to.file, m.from.rank)
+            } else {
+                m.to
+            };
+            announcements.push(Announcement::Capture(square));
+        }
+        self.game.play_legal(m);
+
+        let color = self.game.side_to_move();
+        if let Some(king) = self.game.king(color) {
+            let mut directions: Vec<CheckDirection> = self
+                .game
+                .attackers(king.square, color.opponent())
+                .iter()
+                .map(|p| self.check_direction(king.square, p))
+                .collect();
+            directions.sort_by_key(|d| *d as u8);
+            directions.dedup();
+            announcements.extend(directions.into_iter().map(Announcement::Check));
+        }
+        if let Some(outcome) = self.game.outcome() {
+            announcements.push(Announcement::GameOver(outcome));
+        }
+        announcements
+    }
+
+    // Number of captures the pawns of the side to move could try
+    pub fn pawn_tries(&self) -> usize {
+        self.game
+            .legal_moves()
+            .iter()
+            .filter(|m| !m.is_drop() && m.from.file != m.to.file && self.game.is_capture(m))
+            .filter(|m| {
+                self.game
+                    .piece_at(m.from)
+                    .map(|p| p.piece_type == PieceType::Pawn)
+                    .unwrap_or(false)
+            })
+            .count()
+    }
+
+    fn check_direction(&self, king: Square, attacker: &Piece) -> CheckDirection {
+        let board = self.game.board();
+        let (x, y) = king.coords();
+        let dx = attacker.square.file.0 as i64 - x as i64;
+        let dy = attacker.square.rank.0 as i64 - y as i64;
+        if dx == 0 {
+            return CheckDirection::File;
+        }
+        if dy == 0 {
+            return CheckDirection::Rank;
+        }
+        if dx.abs() == dy.abs() {
+            let (w, h) = (board.size_x - 1, board.size_y - 1);
+            // Squares on the diagonal through the king in the attacker's
+            // direction and on the other one
+            let rising = x.min(y) + (w - x).min(h - y);
+            let falling = x.min(h - y) + (w - x).min(y);
+            let (this, other) = if dx.signum() == dy.signum() {
+                (rising, falling)
+            } else {
+                (falling, rising)
+            };
+            return if this >= other {
+                CheckDirection::LongDiagonal
+            } else {
+                CheckDirection::ShortDiagonal
+            };
+        }
+        if (dx.abs(), dy.abs()) == (1, 2) || (dx.abs(), dy.abs()) == (2, 1) {
+            return CheckDirection::Knight;
+        }
+        CheckDirection::Other
+    }
+}
@@ -1,16 +1,243 @@
-use crate::board::Board;
-use crate::piece::Piece;
+use crate::betza::PieceDef;
+use crate::board::{self, Board, PawnRules};
+use crate::error::Error;
+use crate::fen;
+use crate::fog::{View, Visibility};
 use crate::piece::PieceType;
+use crate::piece::{Color, Piece};
+use crate::square::{File, Rank, Square};
+use crate::time::Clock;
+use crate::uci;
+use crate::variant::{Outcome, Standard, Variant};
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::io;
+use std::sync::Arc;
+
+const STANDARD_BACK_RANK: [PieceType; 8] = [
+    PieceType::Rook,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::King,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Rook,
+];
+
+const CAPABLANCA_BACK_RANK: [PieceType; 10] = [
+    PieceType::Rook,
+    PieceType::Knight,
+    PieceType::Archbishop,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::King,
+    PieceType::Bishop,
+    PieceType::Chancellor,
+    PieceType::Knight,
+    PieceType::Rook,
+];
+
+const GOTHIC_BACK_RANK: [PieceType; 10] = [
+    PieceType::Rook,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::Chancellor,
+    PieceType::King,
+    PieceType::Archbishop,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Rook,
+];
+
+// Second rank of Grand chess, the rooks stand alone in the corners
+const GRAND_PIECES: [PieceType; 8] = [
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::King,
+    PieceType::Chancellor,
+    PieceType::Archbishop,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+// Width and height of the board of infinite chess, the largest Board::new
+// allows
+const INFINITE_SIZE: usize = board::MAX_SIZE;
+
+// How far past the outermost pieces moves go in infinite chess. The board
+// is empty from there to the far away edge, so a rider gains nothing by
+// going further and the move lists stay short.
+const INFINITE_MOVE_MARGIN: usize = 8;
+
+// How far past the outermost pieces drops and the duck may go on a sparse
+// board without a move margin, which would otherwise offer every one of its
+// empty squares
+const DROP_MARGIN: usize = 8;
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Hashes the fields one after another, so a large piece type or colour
+// can't run into the next field. The tag tells the kinds of fields apart.
+fn hash_fields(tag: u64, fields: &[u64]) -> u64 {
+    fields
+        .iter()
+        .fold(splitmix64(tag), |hash, field| splitmix64(hash ^ field))
+}
+
+// Drops are moves from this square off the board, the piece type of the
+// target says which piece from the pocket is dropped. Placing the duck of
+// Duck chess is written the same way.
+pub const DROP: Square = Square {
+    file: File(usize::MAX),
+    rank: Rank(usize::MAX),
+};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    // What stands on the target square after the move: the moving piece
+    // itself, what a pawn promotes to or the piece that is dropped
+    pub piece: PieceType,
+}
+
+impl Move {
+    pub fn new(from: Square, to: Square, piece: PieceType) -> Move {
+        Move { from, to, piece }
+    }
+
+    pub fn is_drop(&self) -> bool {
+        self.from == DROP
+    }
+
+    pub fn places_duck(&self) -> bool {
+        self.from == DROP && self.piece == PieceType::Duck
+    }
+}
+
+// The moves from every square that has any
+type MoveMap = HashMap<Square, Vec<Move>>;
+
+// Right to castle with the rook standing on the given file of the back
+// rank. Storing the file instead of a king/queen side flag covers Chess960
+// too.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CastlingRight {
+    pub color: Color,
+    pub rook_file: File,
+}
 
 pub struct Game {
     white_pieces: HashSet<Piece>,
     black_pieces: HashSet<Piece>,
+    // Pieces of neither side, like the duck
+    neutral_pieces: HashSet<Piece>,
     board: Board,
-    all_moves: HashMap<(usize, usize), Vec<(usize, usize, PieceType)>>,
+    // Shared between clones, which are mostly made to play a move on and
+    // then find their own moves
+    all_moves: Arc<MoveMap>,
     whites_turn: bool,
+    castling: Vec<CastlingRight>,
+    en_passant: Option<Square>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    variant: Arc<dyn Variant>,
+    // Checks given by white and black
+    checks_given: [u32; 2],
+    // Captured pieces white and black can drop, sorted
+    pockets: [Vec<PieceType>; 2],
+    // Squares of pieces that were pawns, they go back to the pocket as pawns
+    promoted: HashSet<Square>,
+    // Whether the side to move has made its move and still has to place
+    // the duck before the turn passes
+    placing_duck: bool,
+    // Moves played since the position was set up, the latest first
+    history: Option<Arc<Played>>,
+    // Time left on both sides, for whoever runs the game with a clock
+    clock: Option<Clock>,
+}
+
+// One link of the list of played moves. Clones of a game share the moves
+// before them, so playing a move only adds a link.
+#[derive(Debug)]
+struct Played {
+    m: Move,
+    before: Option<Arc<Played>>,
+}
+
+// Unlinks one move after the other, a long game would otherwise be dropped
+// recursively
+impl Drop for Played {
+    fn drop(&mut self) {
+        let mut before = self.before.take();
+        while let Some(link) = before {
+            match Arc::try_unwrap(link) {
+                Ok(mut played) => before = played.before.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Clone for Game {
+    fn clone(&self) -> Game {
+        Game {
+            white_pieces: self.white_pieces.clone(),
+            black_pieces: self.black_pieces.clone(),
+            neutral_pieces: self.neutral_pieces.clone(),
+            board: self.board.clone(),
+            all_moves: self.all_moves.clone(),
+            whites_turn: self.whites_turn,
+            castling: self.castling.clone(),
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            variant: self.variant.clone(),
+            checks_given: self.checks_given,
+            pockets: self.pockets.clone(),
+            promoted: self.promoted.clone(),
+            placing_duck: self.placing_duck,
+            history: self.history.clone(),
+            clock: self.clock,
+        }
+    }
+
+    // Reuses the allocations of this game, which makes copying a position
+    // into the same scratch game over and over cheap
+    fn clone_from(&mut self, source: &Game) {
+        self.white_pieces.clone_from(&source.white_pieces);
+        self.black_pieces.clone_from(&source.black_pieces);
+        self.neutral_pieces.clone_from(&source.neutral_pieces);
+        self.board.clone_from(&source.board);
+        self.all_moves.clone_from(&source.all_moves);
+        self.whites_turn = source.whites_turn;
+        self.castling.clone_from(&source.castling);
+        self.en_passant = source.en_passant;
+        self.halfmove_clock = source.halfmove_clock;
+        self.fullmove_number = source.fullmove_number;
+        self.variant.clone_from(&source.variant);
+        self.checks_given = source.checks_given;
+        self.pockets[0].clone_from(&source.pockets[0]);
+        self.pockets[1].clone_from(&source.pockets[1]);
+        self.promoted.clone_from(&source.promoted);
+        self.placing_duck = source.placing_duck;
+        self.history.clone_from(&source.history);
+        self.clock = source.clock;
+    }
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game::new()
+    }
 }
 
 impl Game {
@@ -18,64 +245,268 @@ impl Game {
         Game {
             white_pieces: HashSet::new(),
             black_pieces: HashSet::new(),
-            board: Board::new(8, 8),
-            all_moves: HashMap::new(),
+            neutral_pieces: HashSet::new(),
+            board: Board::sized(8, 8),
+            all_moves: Arc::new(HashMap::new()),
             whites_turn: true,
+            castling: Vec::new(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            variant: Arc::new(Standard),
+            checks_given: [0, 0],
+            pockets: [Vec::new(), Vec::new()],
+            promoted: HashSet::new(),
+            placing_duck: false,
+            history: None,
+            clock: None,
         }
     }
 
+    // A game of the variant in its start position
+    pub fn with_variant(variant: Arc<dyn Variant>) -> Game {
+        let mut game = Game::new();
+        game.variant = variant.clone();
+        variant.setup(&mut game);
+        game
+    }
+
+    pub fn variant(&self) -> &Arc<dyn Variant> {
+        &self.variant
+    }
+
+    // Plays the current position under the rules of another variant
+    pub fn set_variant(&mut self, variant: Arc<dyn Variant>) {
+        self.variant = variant;
+        self.variant.pawn_rules(&mut self.board.pawn_rules);
+        self.calc_all_moves();
+    }
+
+    // Replaces the position with the one in the FEN, keeping the variant and
+    // the registered custom pieces
+    pub fn load_fen(&mut self, fen: &str) -> Result<(), Error> {
+        let mut game = fen::from_fen_with_pieces(fen, &self.board.custom_pieces)?;
+        game.set_variant(self.variant.clone());
+        *self = game;
+        Ok(())
+    }
+
     pub fn regular_chess_setup(&mut self) {
-        self.board = Board::new(8, 8);
+        let back_rank = STANDARD_BACK_RANK.iter().map(|p| Some(*p)).collect();
+        self.setup(&[back_rank], 8, PawnRules::standard(8));
+    }
+
+    // Capablanca chess on a 10x8 board with archbishop and chancellor
+    pub fn capablanca_setup(&mut self) {
+        let back_rank = CAPABLANCA_BACK_RANK.iter().map(|p| Some(*p)).collect();
+        self.setup(&[back_rank], 8, Game::compound_pawn_rules(8));
+    }
 
-        // White pieces
-        for i in 0..8 {
-            self.white_pieces
-                .insert(Piece::new(&mut self.board, PieceType::Pawn, i, 1, true));
+    // Gothic chess, Capablanca chess with another start position
+    pub fn gothic_setup(&mut self) {
+        let back_rank = GOTHIC_BACK_RANK.iter().map(|p| Some(*p)).collect();
+        self.setup(&[back_rank], 8, Game::compound_pawn_rules(8));
+    }
+
+    // Grand chess on a 10x10 board. Pawns start on the third rank, may
+    // promote from the eighth and must promote on the last, but only to a
+    // piece that has been captured. There is no castling.
+    pub fn grand_setup(&mut self) {
+        let mut rooks = vec![None; 10];
+        rooks[0] = Some(PieceType::Rook);
+        rooks[9] = Some(PieceType::Rook);
+        let mut pieces = vec![None];
+        pieces.extend(GRAND_PIECES.iter().map(|p| Some(*p)));
+        pieces.push(None);
+
+        let mut pawn_rules = Game::compound_pawn_rules(10);
+        pawn_rules.double_step_ranks = vec![2];
+        pawn_rules.promotion_ranks = vec![7, 8, 9];
+        pawn_rules.promotion_limits = vec![
+            (PieceType::Queen, 1),
+            (PieceType::Chancellor, 1),
+            (PieceType::Archbishop, 1),
+            (PieceType::Rook, 2),
+            (PieceType::Bishop, 2),
+            (PieceType::Knight, 2),
+        ];
+        self.setup(&[rooks, pieces], 10, pawn_rules);
+    }
+
+    // The regular armies in the middle of a board so large it is as good as
+    // endless. Pawns promote on the opponent's back rank, and there is no
+    // castling since the back ranks are far from the edges.
+    pub fn infinite_chess_setup(&mut self) {
+        let mut board = Board::sized(INFINITE_SIZE, INFINITE_SIZE);
+        let origin = (INFINITE_SIZE - 8) / 2;
+        board.origin = (origin, origin);
+        board.move_margin = Some(INFINITE_MOVE_MARGIN);
+        board.pawn_rules.double_step_ranks = vec![origin + 1];
+        board.pawn_rules.promotion_ranks = vec![origin + 7];
+        let back_rank = STANDARD_BACK_RANK.iter().map(|p| Some(*p)).collect();
+        self.setup_on(board, &[back_rank]);
+    }
+
+    // Adds a piece defined in Betza notation, which can then be placed with
+    // the returned type or by its symbol in FEN. Registered pieces survive
+    // setting up a new game.
+    pub fn register_piece(&mut self, def: PieceDef) -> Result<PieceType, Error> {
+        let custom_pieces = &self.board.custom_pieces;
+        if fen::piece_type_from_char(&self.board, def.symbol).is_some() {
+            return Err(Error::InvalidPiece(format!(
+                "symbol '{}' is already used",
+                def.symbol
+            )));
         }
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Rook, 0, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Knight, 1, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Bishop, 2, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Queen, 3, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::King, 4, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Bishop, 5, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Knight, 6, 0, true));
-        self.white_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Rook, 7, 0, true));
-
-        // Black pieces
-        for i in 0..8 {
-            self.black_pieces
-                .insert(Piece::new(&mut self.board, PieceType::Pawn, i, 6, false));
-        }
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Rook, 0, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Knight, 1, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Bishop, 2, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Queen, 3, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::King, 4, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Bishop, 5, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Knight, 6, 7, false));
-        self.black_pieces
-            .insert(Piece::new(&mut self.board, PieceType::Rook, 7, 7, false));
+        if custom_pieces.len() > u8::MAX as usize {
+            return Err(Error::InvalidPiece("too many custom pieces".to_string()));
+        }
+        let piece_type = PieceType::Custom(custom_pieces.len() as u8);
+        Arc::make_mut(&mut self.board.custom_pieces).push(def);
+        Ok(piece_type)
+    }
+
+    // Uses these pawn rules in the current position
+    pub fn set_pawn_rules(&mut self, pawn_rules: PawnRules) {
+        self.board.pawn_rules = pawn_rules;
+        self.calc_all_moves();
+    }
+
+    fn compound_pawn_rules(size_y: usize) -> PawnRules {
+        let mut pawn_rules = PawnRules::standard(size_y);
+        pawn_rules.promotion_pieces.push(PieceType::Chancellor);
+        pawn_rules.promotion_pieces.push(PieceType::Archbishop);
+        pawn_rules
+    }
+
+    // Sets up one of the 960 Fischer Random start positions using the
+    // Scharnagl numbering, where 518 is the regular start position
+    pub fn chess960_setup(&mut self, index: usize) -> Result<(), Error> {
+        if index >= 960 {
+            return Err(Error::InvalidPosition(format!(
+                "Chess960 positions are numbered 0 to 959, not {}",
+                index
+            )));
+        }
+
+        let mut back_rank: [Option<PieceType>; 8] = [None; 8];
+        let mut n = index;
+        back_rank[(n % 4) * 2 + 1] = Some(PieceType::Bishop);
+        n /= 4;
+        back_rank[(n % 4) * 2] = Some(PieceType::Bishop);
+        n /= 4;
+
+        let mut place_on_empty = |nth: usize, piece_type: PieceType| {
+            let x = (0..8).filter(|x| back_rank[*x].is_none()).nth(nth).unwrap();
+            back_rank[x] = Some(piece_type);
+        };
+        place_on_empty(n % 6, PieceType::Queen);
+        n /= 6;
+
+        const KNIGHTS: [(usize, usize); 10] = [
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (0, 4),
+            (1, 2),
+            (1, 3),
+            (1, 4),
+            (2, 3),
+            (2, 4),
+            (3, 4),
+        ];
+        let (first, second) = KNIGHTS[n];
+        // The second knight is counted before the first one is placed
+        place_on_empty(second, PieceType::Knight);
+        place_on_empty(first, PieceType::Knight);
+
+        place_on_empty(0, PieceType::Rook);
+        place_on_empty(0, PieceType::King);
+        place_on_empty(0, PieceType::Rook);
+
+        self.setup(&[back_rank.to_vec()], 8, PawnRules::standard(8));
+        Ok(())
+    }
+
+    // Places the given ranks counted from white's side, mirrored for black,
+    // with a row of pawns in front of them. Castling is allowed with every
+    // rook on the back rank if the king stands there too.
+    fn setup(&mut self, ranks: &[Vec<Option<PieceType>>], size_y: usize, pawn_rules: PawnRules) {
+        let mut board = Board::sized(ranks[0].len(), size_y);
+        board.pawn_rules = pawn_rules;
+        self.setup_on(board, ranks);
+    }
+
+    // Like setup but with the white army starting at the origin of the
+    // board and black's mirrored around the centre. Castling needs the back
+    // ranks to be the edges of the board.
+    fn setup_on(&mut self, mut board: Board, ranks: &[Vec<Option<PieceType>>]) {
+        let (size_x, size_y) = (ranks[0].len(), board.size_y);
+        let (origin_x, origin_y) = board.origin;
+        self.variant.pawn_rules(&mut board.pawn_rules);
+        board.custom_pieces = self.board.custom_pieces.clone();
+        self.board = board;
+        self.checks_given = [0, 0];
+        self.pockets = [Vec::new(), Vec::new()];
+        self.promoted.clear();
+        self.white_pieces.clear();
+        self.black_pieces.clear();
+        self.neutral_pieces.clear();
+        self.placing_duck = false;
+        self.whites_turn = true;
+        self.en_passant = None;
+        self.halfmove_clock = 0;
+        self.fullmove_number = 1;
+        self.castling.clear();
+        self.history = None;
+
+        let pawns = vec![Some(PieceType::Pawn); size_x];
+        for (rank, row) in ranks.iter().chain(std::iter::once(&pawns)).enumerate() {
+            for (x, piece_type) in row.iter().enumerate() {
+                if let Some(piece_type) = piece_type {
+                    let (x, y) = (origin_x + x, origin_y + rank);
+                    let square = Square::at(x, y);
+                    let white = Piece::new(&mut self.board, *piece_type, square, Color::White);
+                    self.insert_piece(white);
+                    let square = Square::at(x, size_y - 1 - y);
+                    let black = Piece::new(&mut self.board, *piece_type, square, Color::Black);
+                    self.insert_piece(black);
+                }
+            }
+        }
+
+        if origin_y == 0 && ranks[0].contains(&Some(PieceType::King)) {
+            for (x, piece_type) in ranks[0].iter().enumerate() {
+                if *piece_type == Some(PieceType::Rook) {
+                    for color in [Color::White, Color::Black].iter() {
+                        self.castling.push(CastlingRight {
+                            color: *color,
+                            rook_file: File(origin_x + x),
+                        });
+                    }
+                }
+            }
+        }
+        self.castling.sort();
 
         self.calc_all_moves();
     }
 
     fn calc_all_moves(&mut self) {
-        self.all_moves.clear();
+        self.all_moves = Arc::new(self.find_moves());
+    }
+
+    fn find_moves(&self) -> MoveMap {
+        let mut moves = MoveMap::new();
+        if self.variant.early_outcome(self).is_some() {
+            return moves;
+        }
+        if self.placing_duck {
+            for m in self.duck_moves() {
+                moves.entry(m.from).or_default().push(m);
+            }
+            return moves;
+        }
         let turn = if self.whites_turn {
             &self.white_pieces
         } else {
@@ -83,151 +514,572 @@ impl Game {
         };
 
         for a in turn.iter() {
-            for i in 0..self.board.size_x {
-                for j in 0..self.board.size_y {
-                    if a.check_to(i, j, &self.board, PieceType::Pawn) {
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::Pawn));
-                    }
-                    if a.check_to(i, j, &self.board, PieceType::Rook) {
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::Rook));
-                    }
-                    if a.check_to(i, j, &self.board, PieceType::Knight) {
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::Knight));
-                    }
-                    if a.check_to(i, j, &self.board, PieceType::Bishop) {
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::Bishop));
-                    }
-                    if a.check_to(i, j, &self.board, PieceType::Queen) {
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::Queen));
-                    }
-                    if a.check_to(i, j, &self.board, PieceType::King) {
-                        //TODO fix rockad check check
-                        self.all_moves
-                            .entry((a.pos_x, a.pos_y))
-                            .or_insert_with(Vec::new)
-                            .push((i, j, PieceType::King));
+            // Only pawns can turn into something else
+            let mut types = vec![a.piece_type];
+            if a.piece_type == PieceType::Pawn {
+                types.extend(
+                    self.board
+                        .pawn_rules
+                        .promotion_pieces
+                        .iter()
+                        .filter(|p| self.may_promote_to(**p, a.color)),
+                );
+            }
+            for to in a.targets(&self.board) {
+                for promotion in types.iter() {
+                    if a.check_to(to, &self.board, *promotion) {
+                        moves
+                            .entry(a.square)
+                            .or_default()
+                            .push(Move::new(a.square, to, *promotion));
                     }
                 }
             }
         }
 
-        self.all_moves = self.remove_checks();
+        for m in self.en_passant_moves() {
+            moves.entry(m.from).or_default().push(m);
+        }
+        for m in self.castling_moves() {
+            moves.entry(m.from).or_default().push(m);
+        }
+        if self.variant.drops() {
+            for m in self.drop_moves() {
+                moves.entry(m.from).or_default().push(m);
+            }
+        }
+
+        self.remove_checks(&moves)
+    }
+
+    fn may_promote_to(&self, piece_type: PieceType, color: Color) -> bool {
+        let own = self.pieces_of(color);
+        match self
+            .board
+            .pawn_rules
+            .promotion_limits
+            .iter()
+            .find(|(p, _)| *p == piece_type)
+        {
+            Some((_, limit)) => own.iter().filter(|p| p.piece_type == piece_type).count() < *limit,
+            None => true,
+        }
     }
 
-    fn remove_checks(&self) -> HashMap<(usize, usize), Vec<(usize, usize, PieceType)>> {
-        let (turn, turn_next) = if self.whites_turn {
-            (&self.white_pieces, &self.black_pieces)
+    fn en_passant_moves(&self) -> Vec<Move> {
+        let to = match self.en_passant {
+            Some(square) => square,
+            None => return Vec::new(),
+        };
+        let (x, y) = to.coords();
+        let (turn, from_y) = if self.whites_turn {
+            (&self.white_pieces, y.wrapping_sub(1))
         } else {
-            (&self.black_pieces, &self.white_pieces)
+            (&self.black_pieces, y + 1)
         };
 
-        let mut out: HashMap<(usize, usize), Vec<(usize, usize, PieceType)>> = HashMap::new();
+        turn.iter()
+            .filter(|p| p.piece_type == PieceType::Pawn && p.square.rank.0 == from_y)
+            .filter(|p| p.square.file.0 + 1 == x || p.square.file.0 == x + 1)
+            .map(|p| Move::new(p.square, to, PieceType::Pawn))
+            .collect()
+    }
 
-        for a in turn.iter() {
-            for b in self.all_moves.iter() {
-                for f in b.1.iter() {
-                    let mut new_board = self.board.clone();
-                    let mut c = a.clone();
-                    c.move_to((f).0, (f).1, &mut new_board, (f).2);
-                    let mut king_pos_x: usize = 0;
-                    let mut king_pos_y: usize = 0;
-
-                    if c.piece_type == PieceType::King {
-                        king_pos_x = (f).0;
-                        king_pos_y = (f).1;
-                    } else {
-                        for e in turn.iter() {
-                            if e.piece_type == PieceType::King {
-                                king_pos_x = e.pos_x;
-                                king_pos_y = e.pos_y;
-                            }
-                        }
+    // Pieces from the pocket may go to any empty square, except pawns on the
+    // first rank and the ranks they could promote on
+    fn drop_moves(&self) -> Vec<Move> {
+        let mut pocket = self.pocket(self.side_to_move()).to_vec();
+        pocket.dedup();
+        let mut moves = Vec::new();
+        let (min_x, min_y, max_x, max_y) = self.drop_area();
+        for piece_type in pocket {
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    let square = Square::at(x, y);
+                    if !self.board.is_valid_tile(square) || !self.board.is_empty_tile(square) {
+                        continue;
                     }
-
-                    for d in turn_next.iter() {
-                        if d.check_to(king_pos_x, king_pos_y, &self.board, PieceType::Pawn) {
-                            continue;
-                        }
-                        if a.check_to(king_pos_x, king_pos_y, &self.board, PieceType::Rook) {
-                            continue;
-                        }
-                        if a.check_to(king_pos_x, king_pos_y, &self.board, PieceType::Knight) {
-                            continue;
-                        }
-                        if a.check_to(king_pos_x, king_pos_y, &self.board, PieceType::Bishop) {
-                            continue;
-                        }
-                        if a.check_to(king_pos_x, king_pos_y, &self.board, PieceType::Queen) {
-                            continue;
-                        }
-                        if a.check_to(king_pos_x, king_pos_y, &self.board, PieceType::King) {
-                            continue;
-                        }
-                        out.entry(b.0.clone())
-                            .or_insert_with(Vec::new)
-                            .push(f.clone());
+                    let rank = self.board.relative_rank(Rank(y), self.side_to_move());
+                    if piece_type == PieceType::Pawn
+                        && (rank == 0 || self.board.pawn_rules.promotion_ranks.contains(&rank))
+                    {
+                        continue;
                     }
+                    moves.push(Move::new(DROP, square, piece_type));
                 }
             }
         }
+        moves
+    }
+
+    // Corners of the area drops and duck placements go to, so their number
+    // grows with the area the pieces cover and not with the board
+    fn drop_area(&self) -> (usize, usize, usize, usize) {
+        match self.board.move_margin {
+            None if self.board.is_sparse() => self.board.piece_area(DROP_MARGIN),
+            _ => self.board.move_area(),
+        }
+    }
+
+    // The duck may go to any empty square, so never stays where it was
+    fn duck_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let (min_x, min_y, max_x, max_y) = self.drop_area();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let square = Square::at(x, y);
+                if self.board.is_valid_tile(square) && self.board.is_empty_tile(square) {
+                    moves.push(Move::new(DROP, square, PieceType::Duck));
+                }
+            }
+        }
+        moves
+    }
+
+    // Castling is encoded as the king capturing its own rook. The king and
+    // rook then end up on the usual squares, wherever they started.
+    fn castling_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let color = self.side_to_move();
+        let rank = self.back_rank(color);
+        let king = match self.king(color) {
+            Some(king) if king.square.rank.0 == rank => king,
+            _ => return moves,
+        };
+        let king_x = king.square.file.0;
+        if self.in_check() {
+            return moves;
+        }
+
+        for right in self.castling.iter() {
+            if right.color != self.side_to_move() {
+                continue;
+            }
+            let rook = match self.piece_at(Square::new(right.rook_file, Rank(rank))) {
+                Some(rook)
+                    if rook.piece_type == PieceType::Rook && rook.is_white() == king.is_white() =>
+                {
+                    rook
+                }
+                _ => continue,
+            };
+            let rook_x = rook.square.file.0;
+            let (king_to, rook_to) = self.castling_targets(king_x, rook_x);
+
+            // Everything between the start and target squares must be empty
+            // except for the castling king and rook themselves
+            let span = |a: usize, b: usize| a.min(b)..=a.max(b);
+            let blocked = span(king_x, king_to).chain(span(rook_x, rook_to)).any(|x| {
+                x != king_x && x != rook_x && !self.board.is_empty_tile(Square::at(x, rank))
+            });
+            if blocked {
+                continue;
+            }
+            // The king may not pass through an attacked square
+            if span(king_x, king_to)
+                .any(|x| self.square_attacked(Square::at(x, rank), king.color.opponent()))
+            {
+                continue;
+            }
 
+            moves.push(Move::new(king.square, rook.square, PieceType::King));
+        }
+        moves
+    }
+
+    // Target files of king and rook when castling towards the given rook
+    fn castling_targets(&self, king_x: usize, rook_x: usize) -> (usize, usize) {
+        if rook_x > king_x {
+            (self.board.size_x - 2, self.board.size_x - 3)
+        } else {
+            (2, 3)
+        }
+    }
+
+    fn back_rank(&self, color: Color) -> usize {
+        self.board.relative_rank(Rank(0), color)
+    }
+
+    // Pieces of white or black, the neutral ones for any other colour
+    fn pieces_of(&self, color: Color) -> &HashSet<Piece> {
+        match color {
+            Color::White => &self.white_pieces,
+            Color::Black => &self.black_pieces,
+            _ => &self.neutral_pieces,
+        }
+    }
+
+    pub fn king(&self, color: Color) -> Option<&Piece> {
+        self.pieces_of(color)
+            .iter()
+            .find(|p| p.piece_type == PieceType::King)
+    }
+
+    // Keeps the moves the variant allows
+    fn remove_checks(&self, moves: &MoveMap) -> MoveMap {
+        let mut legal = Vec::new();
+        let mut after = self.clone();
+        for m in moves.values().flatten() {
+            after.clone_from(self);
+            after.move_now(m);
+            if self.variant.is_legal(self, m, &after) {
+                legal.push(*m);
+            }
+        }
+        legal.sort();
+
+        let mut out = MoveMap::new();
+        for m in self.variant.restrict(self, legal) {
+            out.entry(m.from).or_default().push(m);
+        }
         out
     }
 
-    fn move_now(&mut self, from: &(usize, usize), to: &(usize, usize, PieceType)) {
-        if self.whites_turn {
-            let muttable_white = self.white_pieces.clone();
-            self.white_pieces.clear();
-            for mut a in muttable_white {
-                if a.pos_x == from.0 && a.pos_y == from.1 {
-                    let muttable_black = self.black_pieces.clone();
-                    self.black_pieces.clear();
-                    a.move_to(to.0, to.1, &mut self.board, to.2);
-                    for b in muttable_black {
-                        if b.pos_x == to.0 && b.pos_y == to.1 {
-                            continue;
-                        }
-                        self.black_pieces.insert(b);
-                    }
+    // Whether the king of the given side is attacked, without the variant
+    // rules on top
+    pub fn king_attacked(&self, color: Color) -> bool {
+        match self.king(color) {
+            Some(king) => self.square_attacked(king.square, color.opponent()),
+            None => false,
+        }
+    }
+
+    // Squares outside the move area are never attacked, since no piece can
+    // move there
+    pub fn square_attacked(&self, square: Square, by: Color) -> bool {
+        self.board.in_move_area(square)
+            && self
+                .pieces_of(by)
+                .iter()
+                .any(|p| self.piece_attacks(p, square))
+    }
+
+    // The pieces of one side attacking the square
+    pub fn attackers(&self, square: Square, by: Color) -> Vec<&Piece> {
+        let attackers = self.pieces_of(by);
+        if !self.board.in_move_area(square) {
+            return Vec::new();
+        }
+        attackers
+            .iter()
+            .filter(|p| self.piece_attacks(p, square))
+            .collect()
+    }
+
+    fn piece_attacks(&self, p: &Piece, square: Square) -> bool {
+        let (x, y) = square.coords();
+        let (pos_x, pos_y) = p.square.coords();
+        let diff = |a: usize, b: usize| (a as i128 - b as i128).abs();
+        match p.piece_type {
+            PieceType::King => {
+                self.variant.king_captures() && diff(pos_x, x) <= 1 && diff(pos_y, y) <= 1
+            }
+            PieceType::Pawn => {
+                let forward = if p.is_white() {
+                    pos_y + 1 == y
+                } else {
+                    y + 1 == pos_y
+                };
+                forward && diff(pos_x, x) == 1
+            }
+            _ => p.square != square && p.attacks(square, &self.board),
+        }
+    }
+
+    pub fn remove_piece(&mut self, square: Square) -> Option<Piece> {
+        let piece = self.piece_at(square)?.clone();
+        match piece.color {
+            Color::White => self.white_pieces.remove(&piece),
+            Color::Black => self.black_pieces.remove(&piece),
+            Color::Neutral | Color::Player(_) => self.neutral_pieces.remove(&piece),
+        };
+        self.board.set_emptiness(square, true);
+        self.promoted.remove(&square);
+        Some(piece)
+    }
+
+    fn insert_piece(&mut self, piece: Piece) {
+        self.board.set_emptiness(piece.square, false);
+        self.board.set_piece_color(piece.square, piece.color);
+        match piece.color {
+            Color::White => self.white_pieces.insert(piece),
+            Color::Black => self.black_pieces.insert(piece),
+            Color::Neutral | Color::Player(_) => self.neutral_pieces.insert(piece),
+        };
+    }
+
+    pub(crate) fn move_now(&mut self, m: &Move) {
+        if m.is_drop() {
+            if m.piece == PieceType::Duck {
+                self.place_duck(m.to);
+            } else {
+                self.drop_now(m);
+            }
+            return;
+        }
+        let (from, to) = (m.from, m.to);
+        let was_promoted = self.promoted.contains(&from);
+        let mut piece = match self.remove_piece(from) {
+            Some(piece) => piece,
+            None => return,
+        };
+        let own_target = self
+            .piece_at(to)
+            .map(|p| p.color == piece.color)
+            .unwrap_or(false);
+
+        self.halfmove_clock += 1;
+        let en_passant = self.en_passant.take();
+        let mut captured = false;
+
+        if piece.piece_type == PieceType::King && own_target {
+            // Castling, lift both pieces before putting them down again since
+            // their target squares may overlap with the start squares
+            let mut rook = self.remove_piece(to).unwrap();
+            let (king_to, rook_to) = self.castling_targets(from.file.0, to.file.0);
+            piece.square.file = File(king_to);
+            rook.square.file = File(rook_to);
+            self.insert_piece(piece.clone());
+            self.insert_piece(rook);
+        } else {
+            let victim_promoted = self.promoted.contains(&to);
+            if let Some(victim) = self.remove_piece(to) {
+                self.halfmove_clock = 0;
+                captured = true;
+                self.pocket_capture(piece.color, victim.piece_type, victim_promoted);
+            }
+            if piece.piece_type == PieceType::Pawn {
+                self.halfmove_clock = 0;
+                if Some(to) == en_passant {
+                    self.remove_piece(Square::new(to.file, from.rank));
+                    captured = true;
+                    self.pocket_capture(piece.color, PieceType::Pawn, false);
+                }
+                if (from.rank.0 as i128 - to.rank.0 as i128).abs() == 2 {
+                    let between = Rank((from.rank.0 + to.rank.0) / 2);
+                    self.en_passant = Some(Square::new(from.file, between));
                 }
-                self.white_pieces.insert(a);
             }
+            self.board.set_emptiness(from, false);
+            let promotes = piece.piece_type != m.piece;
+            piece.move_to(to, &mut self.board, m.piece);
+            self.insert_piece(piece.clone());
+            if was_promoted || promotes {
+                self.promoted.insert(to);
+            }
+        }
+
+        let variant = self.variant.clone();
+        variant.after_move(self, to, captured);
+
+        // Moving the king or a castling rook, or losing the rook, ends the right
+        let back_rank = Rank(self.back_rank(piece.color));
+        let mut castling = std::mem::take(&mut self.castling);
+        castling.retain(|right| {
+            let rank = self.back_rank(right.color);
+            let rook_stays = self
+                .piece_at(Square::new(right.rook_file, Rank(rank)))
+                .map(|p| p.piece_type == PieceType::Rook && p.color == right.color)
+                .unwrap_or(false);
+            let king_stays = self
+                .king(right.color)
+                .map(|k| k.square.rank.0 == rank)
+                .unwrap_or(false);
+            let moved = right.color == piece.color
+                && (piece.piece_type == PieceType::King
+                    || from == Square::new(right.rook_file, back_rank));
+            rook_stays && king_stays && !moved
+        });
+        self.castling = castling;
+    }
+
+    fn drop_now(&mut self, m: &Move) {
+        let pocket = &mut self.pockets[if self.whites_turn { 0 } else { 1 }];
+        match pocket.iter().position(|p| *p == m.piece) {
+            Some(i) => pocket.remove(i),
+            None => return,
+        };
+        self.halfmove_clock += 1;
+        self.en_passant = None;
+        let color = Color::from_white(self.whites_turn);
+        let piece = Piece::new(&mut self.board, m.piece, m.to, color);
+        self.insert_piece(piece);
+        let variant = self.variant.clone();
+        variant.after_move(self, m.to, false);
+    }
+
+    // Lifts the duck from wherever it stands and puts it on the square. This
+    // is the second half of a move, so the move counters stay as they are.
+    fn place_duck(&mut self, to: Square) {
+        if let Some(square) = self.duck().map(|d| d.square) {
+            self.remove_piece(square);
+        }
+        let duck = Piece::new(&mut self.board, PieceType::Duck, to, Color::Neutral);
+        self.insert_piece(duck);
+    }
+
+    pub fn duck(&self) -> Option<&Piece> {
+        self.neutral_pieces
+            .iter()
+            .find(|p| p.piece_type == PieceType::Duck)
+    }
+
+    // Whether the side to move has moved and now places the duck
+    pub fn is_placing_duck(&self) -> bool {
+        self.placing_duck
+    }
+
+    // Moves played since the position was set up, in the order they were
+    // played
+    pub fn history(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut link = self.history.as_ref();
+        while let Some(played) = link {
+            moves.push(played.m);
+            link = played.before.as_ref();
+        }
+        moves.reverse();
+        moves
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_history(&mut self, moves: Vec<Move>) {
+        self.history = None;
+        for m in moves {
+            self.history = Some(Arc::new(Played {
+                m,
+                before: self.history.take(),
+            }));
+        }
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    pub fn clock_mut(&mut self) -> Option<&mut Clock> {
+        self.clock.as_mut()
+    }
+
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_placing_duck(&mut self, placing_duck: bool) {
+        self.placing_duck = placing_duck;
+        self.calc_all_moves();
+    }
+
+    // Captured pieces go to the pocket of the capturer when the variant has
+    // drops, promoted ones as the pawns they were
+    fn pocket_capture(&mut self, by: Color, piece_type: PieceType, promoted: bool) {
+        let side = match by.side() {
+            Some(side) if self.variant.keeps_captures() => side,
+            _ => return,
+        };
+        let piece_type = if promoted {
+            PieceType::Pawn
         } else {
-            let muttable_black = self.black_pieces.clone();
-            self.black_pieces.clear();
-            for mut a in muttable_black {
-                if a.pos_x == from.0 && a.pos_y == from.1 {
-                    let muttable_white = self.white_pieces.clone();
-                    self.white_pieces.clear();
-                    a.move_to(to.0, to.1, &mut self.board, to.2);
-                    for b in muttable_white {
-                        if b.pos_x == to.0 && b.pos_y == to.1 {
-                            continue;
+            piece_type
+        };
+        let pocket = &mut self.pockets[side];
+        pocket.push(piece_type);
+        pocket.sort();
+    }
+
+    // Pieces white or black can drop, other colours have no pocket
+    pub fn pocket(&self, color: Color) -> &[PieceType] {
+        match color.side() {
+            Some(side) => &self.pockets[side],
+            None => &[],
+        }
+    }
+
+    // Gives a piece to white or black to drop later, as when the partner in
+    // bughouse captures it
+    pub fn add_to_pocket(&mut self, color: Color, piece_type: PieceType) {
+        let side = match color.side() {
+            Some(side) => side,
+            None => return,
+        };
+        let pocket = &mut self.pockets[side];
+        pocket.push(piece_type);
+        pocket.sort();
+        self.calc_all_moves();
+    }
+
+    pub(crate) fn set_pockets(&mut self, mut white: Vec<PieceType>, mut black: Vec<PieceType>) {
+        white.sort();
+        black.sort();
+        self.pockets = [white, black];
+        self.calc_all_moves();
+    }
+
+    // Whether the piece on the square got there by promotion
+    pub fn is_promoted(&self, square: Square) -> bool {
+        self.promoted.contains(&square)
+    }
+
+    // Squares of all pieces that got there by promotion, in order
+    pub fn promoted_squares(&self) -> Vec<Square> {
+        let mut squares: Vec<_> = self.promoted.iter().cloned().collect();
+        squares.sort();
+        squares
+    }
+
+    pub(crate) fn set_promoted(&mut self, squares: Vec<Square>) {
+        self.promoted = squares.into_iter().collect();
+        self.calc_all_moves();
+    }
+
+    // What one side gets to see of the position under the variant's rules
+    pub fn view_for(&self, color: Color) -> View {
+        let own = self.pieces_of(color);
+        let mut visible: HashSet<Square> = own.iter().map(|p| p.square).collect();
+        match self.variant.visibility() {
+            Visibility::Full => {
+                return View::new(color, self.all_pieces().cloned().collect(), None)
+            }
+            Visibility::OwnPieces => {}
+            // Every square a piece could move or capture to
+            Visibility::Dark => {
+                for p in own.iter() {
+                    for square in p.targets(&self.board) {
+                        // Pawns see the squares they attack and the ones
+                        // they can step to
+                        let sees = if p.piece_type == PieceType::Pawn {
+                            square.file != p.square.file
+                                || std::iter::once(&PieceType::Pawn)
+                                    .chain(self.board.pawn_rules.promotion_pieces.iter())
+                                    .any(|t| p.check_to(square, &self.board, *t))
+                        } else {
+                            p.attacks(square, &self.board)
+                                || p.check_to(square, &self.board, p.piece_type)
+                        };
+                        if sees {
+                            visible.insert(square);
                         }
-                        self.white_pieces.insert(b);
                     }
                 }
-                self.black_pieces.insert(a);
             }
         }
+        let pieces = self
+            .all_pieces()
+            .filter(|p| visible.contains(&p.square))
+            .cloned()
+            .collect();
+        View::new(color, pieces, Some(visible))
+    }
+
+    pub(crate) fn all_pieces(&self) -> impl Iterator<Item = &Piece> {
+        self.white_pieces
+            .iter()
+            .chain(self.black_pieces.iter())
+            .chain(self.neutral_pieces.iter())
     }
 
-    pub fn moves_from(&self, from: (usize, usize)) -> Option<&Vec<(usize, usize, PieceType)>> {
+    pub fn moves_from(&self, from: Square) -> Option<&Vec<Move>> {
         self.all_moves.get(&from)
     }
 
@@ -239,10 +1091,18 @@ impl Game {
         self.black_pieces.iter()
     }
 
+    pub fn piece_at(&self, square: Square) -> Option<&Piece> {
+        self.all_pieces().find(|p| p.square == square)
+    }
+
     pub fn is_whites_turn(&self) -> bool {
         self.whites_turn
     }
 
+    pub fn side_to_move(&self) -> Color {
+        Color::from_white(self.whites_turn)
+    }
+
     pub fn board(&self) -> &Board {
         &self.board
     }
@@ -250,85 +1110,276 @@ impl Game {
     pub fn no_moves(&self) -> bool {
         self.all_moves.is_empty()
     }
-  
-    pub fn next(&mut self, from: (usize, usize), to: (usize, usize, PieceType)) {
-        if self.all_moves.contains_key(&from) {
-            if self
-                .all_moves
-                .entry(from)
-                .or_insert_with(Vec::new) // Will never happen
-                .contains(&to)
-            {
-                self.move_now(&from, &to);
-            }
+
+    pub fn in_check(&self) -> bool {
+        self.variant.is_check(self, self.side_to_move())
+    }
+
+    // How the game ended, None while it goes on
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some(outcome) = self.variant.early_outcome(self) {
+            return Some(outcome);
         }
-        self.whites_turn = !self.whites_turn;
+        if self.all_moves.is_empty() {
+            return Some(self.variant.no_moves_outcome(self));
+        }
+        None
+    }
+
+    // Checks given by white or black, other colours give none
+    pub fn checks_given(&self, color: Color) -> u32 {
+        color
+            .side()
+            .map(|side| self.checks_given[side])
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn set_checks_given(&mut self, white: u32, black: u32) {
+        self.checks_given = [white, black];
+        self.calc_all_moves();
+    }
+
+    pub fn clear_castling(&mut self) {
+        self.castling.clear();
         self.calc_all_moves();
     }
 
-    pub fn get_coords_from_string(s: String) -> (usize, usize) {
-        if s.len() > 2 {
-            panic!("string with only two caracters supported");
+    // All legal moves for the side to move, in a stable order
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves: Vec<Move> = self.all_moves.values().flatten().copied().collect();
+        moves.sort();
+        moves
+    }
+
+    // Castling captures nothing even though the king lands on its own rook
+    pub fn is_capture(&self, m: &Move) -> bool {
+        if self.is_castling(m) {
+            return false;
         }
+        let en_passant = self.en_passant == Some(m.to)
+            && self
+                .piece_at(m.from)
+                .map(|p| p.piece_type == PieceType::Pawn)
+                .unwrap_or(false);
+        en_passant || !self.board.is_empty_tile(m.to)
+    }
 
-        let c: char = s.chars().nth(0).unwrap();
-        let n: char = s.chars().nth(1).unwrap();
+    pub fn is_castling(&self, m: &Move) -> bool {
+        match (self.piece_at(m.from), self.piece_at(m.to)) {
+            (Some(king), Some(rook)) => {
+                king.piece_type == PieceType::King && king.is_white() == rook.is_white()
+            }
+            _ => false,
+        }
+    }
+
+    // Where the king ends up, which for castling is not the square of the move
+    pub fn king_target(&self, m: &Move) -> Square {
+        if self.is_castling(m) {
+            let (king_to, _) = self.castling_targets(m.from.file.0, m.to.file.0);
+            Square::new(File(king_to), m.to.rank)
+        } else {
+            m.to
+        }
+    }
+
+    pub fn castling_rights(&self) -> &[CastlingRight] {
+        &self.castling
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
 
-        if !((c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z')) {
-            panic!("first character in string must be a-z");
+    // Order independent hash of the position, used as transposition table key
+    pub fn hash_key(&self) -> u64 {
+        let mut key = if self.whites_turn {
+            0
+        } else {
+            0x9e37_79b9_7f4a_7c15
+        };
+        for piece in self.all_pieces() {
+            let (x, y) = piece.square.coords();
+            key ^= hash_fields(
+                0,
+                &[
+                    x as u64 | (y as u64) << 20,
+                    piece.piece_type.index() as u64,
+                    piece.color.index() as u64,
+                ],
+            );
+        }
+        for right in self.castling.iter() {
+            let white = right.color.is_white() as u64;
+            key ^= hash_fields(1, &[right.rook_file.0 as u64, white]);
+        }
+        if let Some((x, y)) = self.en_passant.map(Square::coords) {
+            key ^= hash_fields(2, &[x as u64 | (y as u64) << 20]);
         }
+        if self.checks_given != [0, 0] {
+            let [white, black] = self.checks_given;
+            key ^= hash_fields(3, &[white as u64, black as u64]);
+        }
+        for (side, pocket) in self.pockets.iter().enumerate() {
+            // Each piece in the pocket is keyed by how many of its type come
+            // before it, so the counts matter and not the order
+            for (i, piece_type) in pocket.iter().enumerate() {
+                let nth = pocket[..i].iter().filter(|p| *p == piece_type).count();
+                key ^= hash_fields(4, &[piece_type.index() as u64, nth as u64, side as u64]);
+            }
+        }
+        for (x, y) in self.promoted.iter().map(|s| s.coords()) {
+            key ^= hash_fields(5, &[x as u64 | (y as u64) << 20]);
+        }
+        if self.placing_duck {
+            key ^= hash_fields(6, &[]);
+        }
+        key
+    }
 
-        if !(n >= '1' && n <= '9') {
-            panic!("second character must be 1-9");
+    // Number of leaf nodes of the legal move tree, for checking the move
+    // generator against known results. In Duck chess a move and the duck
+    // placement after it make one ply.
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
         }
+        let moves = self.legal_moves();
+        if depth == 1 && (self.placing_duck || !self.variant.duck()) {
+            return moves.len() as u64;
+        }
+        moves.iter().map(|m| self.perft_after(m, depth)).sum()
+    }
 
-        if c >= 'a' && c <= 'z' {
-            return (c as usize - 'a' as usize, n as usize - '1' as usize);
+    // Perft split up by root move
+    pub fn divide(&self, depth: u32) -> Vec<(Move, u64)> {
+        self.legal_moves()
+            .into_iter()
+            .map(|m| {
+                let nodes = self.perft_after(&m, depth.max(1));
+                (m, nodes)
+            })
+            .collect()
+    }
+
+    // Perft of the position after the move, which still has the whole ply
+    // to go when the duck has yet to be placed
+    fn perft_after(&self, m: &Move, depth: u32) -> u64 {
+        let mut child = self.clone();
+        child.play_legal(*m);
+        if child.placing_duck {
+            child.perft(depth)
         } else {
-            return (c as usize - 'A' as usize, n as usize - '1' as usize);
+            child.perft(depth - 1)
         }
     }
 
-    pub fn get_string_from_coords(x: usize, y: usize) -> String {
-        format!(
-            "{}{}",
-            (x + 'a' as usize) as u8 as char,
-            (y + '1' as usize) as u8 as char
-        )
+    // Builds a position from its parts, used by the FEN parser and
+    // PositionBuilder
+    pub(crate) fn from_parts(
+        board: Board,
+        pieces: Vec<Piece>,
+        whites_turn: bool,
+        castling: Vec<CastlingRight>,
+        en_passant: Option<Square>,
+        halfmove_clock: u32,
+        fullmove_number: u32,
+    ) -> Game {
+        let mut game = Game::new();
+        game.board = board;
+        for piece in pieces {
+            game.insert_piece(piece);
+        }
+        game.whites_turn = whites_turn;
+        game.castling = castling;
+        game.castling.sort();
+        game.en_passant = en_passant;
+        game.halfmove_clock = halfmove_clock;
+        game.fullmove_number = fullmove_number;
+        game.calc_all_moves();
+        game
     }
 
-    pub fn start(&mut self) {
-        self.regular_chess_setup();
+    #[cfg(test)]
+    pub(crate) fn place_piece(&mut self, piece_type: PieceType, square: Square, is_white: bool) {
+        let color = Color::from_white(is_white);
+        let piece = Piece::new(&mut self.board, piece_type, square, color);
+        self.insert_piece(piece);
         self.calc_all_moves();
-        while !self.all_moves.is_empty() {
-            self.calc_all_moves();
-            println!("next move?");
-            let stdin = io::stdin();
-            let mut input = String::new();
-
-            stdin.read_line(&mut input).unwrap();
-
-            let v: Vec<&str> = input.split_whitespace().collect();
-            let mut tmp = PieceType::Pawn;
-            match v[2] {
-                "Pawn" => tmp = PieceType::Pawn,
-                "Rook" => tmp = PieceType::Rook,
-                "Knight" => tmp = PieceType::Knight,
-                "Bishop" => tmp = PieceType::Bishop,
-                "Queen" => tmp = PieceType::Queen,
-                "King" => tmp = PieceType::King,
-                _ => println!("unknown piece"),
-            }
+    }
 
-            let first = Game::get_coords_from_string(v[0].to_string());
-            let second = (
-                Game::get_coords_from_string(v[1].to_string()).0,
-                Game::get_coords_from_string(v[1].to_string()).1,
-                tmp,
-            );
+    #[cfg(test)]
+    pub(crate) fn remove_for_test(&mut self, square: Square) {
+        self.remove_piece(square);
+        self.calc_all_moves();
+    }
 
-            self.next(first, second);
+    #[cfg(test)]
+    pub(crate) fn set_whites_turn(&mut self, whites_turn: bool) {
+        self.whites_turn = whites_turn;
+        self.calc_all_moves();
+    }
+
+    // Plays a legal move. An illegal move is an error and leaves the
+    // position as it was.
+    pub fn next(&mut self, m: Move) -> Result<(), Error> {
+        let legal = match self.all_moves.get(&m.from) {
+            Some(moves) => moves.contains(&m),
+            None => false,
+        };
+        if !legal {
+            return Err(Error::IllegalMove(uci::move_to_uci(self, &m, false)));
+        }
+        self.play_legal(m);
+        Ok(())
+    }
+
+    // Plays a move taken from legal_moves without looking it up again
+    pub(crate) fn play_legal(&mut self, m: Move) {
+        self.move_now(&m);
+        self.history = Some(Arc::new(Played {
+            m,
+            before: self.history.take(),
+        }));
+        // In Duck chess the mover places the duck before the turn passes
+        if self.variant.duck() && !m.places_duck() {
+            self.placing_duck = true;
+            self.calc_all_moves();
+            return;
+        }
+        self.placing_duck = false;
+        if !self.whites_turn {
+            self.fullmove_number += 1;
+        }
+        self.whites_turn = !self.whites_turn;
+        if self.in_check() {
+            self.checks_given[if self.whites_turn { 1 } else { 0 }] += 1;
         }
-        println!("Checkmate");
+        self.calc_all_moves();
+    }
+
+    // Coordinates of a square name like "e4", "j10" or "aa1". The file may
+    // also be written in upper case.
+    pub fn get_coords_from_string(s: String) -> Result<(usize, usize), Error> {
+        Ok(s.parse::<Square>()?.coords())
+    }
+
+    pub fn get_string_from_coords(x: usize, y: usize) -> String {
+        Square::at(x, y).to_string()
+    }
+
+    // Starts a new game of regular chess. Playing it on the command line
+    // is up to repl::Repl, which reads the moves from any input.
+    #[deprecated(note = "use regular_chess_setup")]
+    pub fn start(&mut self) {
+        self.regular_chess_setup();
     }
 }
@@ -1,72 +1,144 @@
+pub mod betza;
 pub mod board;
+pub mod bughouse;
+pub mod display;
+pub mod error;
+pub mod fen;
+pub mod fog;
 pub mod game;
+pub mod multiplayer;
+pub mod pack;
+pub mod pgn;
 pub mod piece;
+pub mod position;
+pub mod repl;
+pub mod san;
+pub mod search;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod skill;
+pub mod square;
+pub mod time;
+pub mod tt;
+pub mod uci;
+pub mod variant;
+
+pub use error::Error;
+pub use piece::Color;
+pub use square::{File, Rank, Square};
 
 #[cfg(test)]
 mod tests {
+    use crate::betza::{self, Mode, PieceDef};
     use crate::board::Board;
-    use crate::game::Game;
+    use crate::bughouse::BughouseMatch;
+    use crate::display::{game_diagram, DiagramStyle};
+    use crate::fen;
+    use crate::fog::{Announcement, CheckDirection, Umpire};
+    use crate::game::{Game, Move};
+    use crate::multiplayer::{self, MultiplayerGame};
+    use crate::pack;
+    use crate::pgn::{self, Pgn};
     use crate::piece;
+    use crate::piece::{Color, PieceType};
+    use crate::position::PositionBuilder;
+    use crate::repl::Repl;
+    use crate::san::{duck_move_from_san, duck_move_to_san, move_from_san, move_to_san};
+    use crate::search::PvLine;
+    use crate::search::{SearchInfo, SearchLimits, SearchObserver, Searcher, MATE};
+    use crate::skill::{calibrate, Skill};
+    use crate::time::{TimeControl, TimeManager};
+    use crate::tt::{Bound, TranspositionTable, TtEntry};
+    use crate::uci::{move_from_uci, move_to_uci, Uci};
+    use crate::variant::{self, Outcome};
+    use crate::{Error, File, Rank, Square};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn board_size_x_valid() {
-        let board: Board = Board::new(8, 1);
+        let board: Board = Board::new(8, 1).unwrap();
         assert_eq!(board.size_x, 8);
-        let board: Board = Board::new(1, 1);
+        let board: Board = Board::new(1, 1).unwrap();
         assert_eq!(board.size_x, 1);
-        let board: Board = Board::new(1000, 1);
+        let board: Board = Board::new(1000, 1).unwrap();
         assert_eq!(board.size_x, 1000);
     }
 
     #[test]
-    #[should_panic]
     fn board_size_x_invalid() {
-        let _board: Board = Board::new(0, 1);
+        assert_eq!(Board::new(0, 1).err(), Some(Error::InvalidDimensions(0, 1)));
+        assert!(Board::new(1_000_001, 1).is_err());
     }
 
     #[test]
     fn board_size_y_valid() {
-        let board: Board = Board::new(1, 8);
+        let board: Board = Board::new(1, 8).unwrap();
         assert_eq!(board.size_y, 8);
-        let board: Board = Board::new(1, 1);
+        let board: Board = Board::new(1, 1).unwrap();
         assert_eq!(board.size_y, 1);
-        let board: Board = Board::new(1, 1000);
+        let board: Board = Board::new(1, 1000).unwrap();
         assert_eq!(board.size_y, 1000);
     }
 
     #[test]
-    #[should_panic]
     fn board_size_y_invalid() {
-        let _board: Board = Board::new(1, 0);
+        assert_eq!(Board::new(1, 0).err(), Some(Error::InvalidDimensions(1, 0)));
+        assert!(Board::new(1, 1_000_001).is_err());
     }
 
     #[test]
     fn test_pawn_forward() {
-        let mut board = Board::new(8, 8);
-        let mut pawn = piece::Piece::new(&mut board, piece::PieceType::Pawn, 0, 1, true);
-        pawn.check_to(0, 2, &mut board, piece::PieceType::Queen);
+        let mut board = Board::new(8, 8).unwrap();
+        let pawn = piece::Piece::new(
+            &mut board,
+            piece::PieceType::Pawn,
+            Square::at(0, 1),
+            Color::White,
+        );
+        assert!(pawn.check_to(Square::at(0, 2), &board, piece::PieceType::Pawn));
     }
 
     #[test]
     fn test_pawn_forward2() {
-        let mut board = Board::new(8, 8);
-        let mut pawn = piece::Piece::new(&mut board, piece::PieceType::Pawn, 0, 1, true);
-        pawn.check_to(0, 3, &mut board, piece::PieceType::Queen);
+        let mut board = Board::new(8, 8).unwrap();
+        let pawn = piece::Piece::new(
+            &mut board,
+            piece::PieceType::Pawn,
+            Square::at(0, 1),
+            Color::White,
+        );
+        assert!(pawn.check_to(Square::at(0, 3), &board, piece::PieceType::Pawn));
     }
 
     #[test]
     fn test_pawn_capture() {
-        let mut board = Board::new(8, 8);
-        let mut pawn1 = piece::Piece::new(&mut board, piece::PieceType::Pawn, 0, 1, true);
-        let mut pawn2 = piece::Piece::new(&mut board, piece::PieceType::Pawn, 1, 2, true);
-        pawn1.check_to(1, 2, &mut board, piece::PieceType::Queen);
+        let mut board = Board::new(8, 8).unwrap();
+        let pawn1 = piece::Piece::new(
+            &mut board,
+            piece::PieceType::Pawn,
+            Square::at(0, 1),
+            Color::White,
+        );
+        piece::Piece::new(
+            &mut board,
+            piece::PieceType::Pawn,
+            Square::at(1, 2),
+            Color::Black,
+        );
+        assert!(pawn1.check_to(Square::at(1, 2), &board, piece::PieceType::Pawn));
     }
 
     #[test]
     fn test_pawn_promotion() {
-        let mut board = Board::new(8, 8);
-        let mut pawn1 = piece::Piece::new(&mut board, piece::PieceType::Pawn, 0, 6, true);
-        pawn1.check_to(0, 7, &mut board, piece::PieceType::Queen);
+        let mut board = Board::new(8, 8).unwrap();
+        let pawn1 = piece::Piece::new(
+            &mut board,
+            piece::PieceType::Pawn,
+            Square::at(0, 6),
+            Color::White,
+        );
+        assert!(pawn1.check_to(Square::at(0, 7), &board, piece::PieceType::Queen));
     }
 
     #[test]
@@ -84,6 +156,2300 @@ mod tests {
     #[test]
     fn run_game() {
         let mut game = Game::new();
-        game.start();
+        game.regular_chess_setup();
+        assert_eq!(fen::to_fen(&game), fen::STARTPOS);
+        assert_eq!(game.legal_moves().len(), 20);
+    }
+
+    #[test]
+    fn games_remember_their_moves() {
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        play_uci(&mut game, &["e2e4", "e7e5"]);
+        let mut copy = game.clone();
+        play_uci(&mut copy, &["g1f3"]);
+        let uci: Vec<String> = copy
+            .history()
+            .iter()
+            .map(|m| move_to_uci(&game, m, false))
+            .collect();
+        assert_eq!(uci, vec!["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(game.history().len(), 2);
+
+        // A new setup starts a new record
+        game.regular_chess_setup();
+        assert!(game.history().is_empty());
+        assert!(fen::from_fen(fen::STARTPOS).unwrap().history().is_empty());
+    }
+
+    #[test]
+    fn illegal_moves_are_errors() {
+        let mut game = fen::from_fen(fen::STARTPOS).unwrap();
+        let fen = fen::to_fen(&game);
+        let e2 = Square::at(4, 1);
+        assert_eq!(
+            game.next(Move::new(e2, Square::at(4, 4), PieceType::Pawn)),
+            Err(Error::IllegalMove("e2e5".to_string()))
+        );
+        assert!(game.is_whites_turn());
+        assert_eq!(fen::to_fen(&game), fen);
+        assert!(game
+            .next(Move::new(e2, Square::at(4, 3), PieceType::Pawn))
+            .is_ok());
+        assert!(!game.is_whites_turn());
+    }
+
+    #[test]
+    fn squares_on_a_narrow_board() {
+        let e4: Square = "e4".parse().unwrap();
+        assert_eq!(e4, Square::new(File(4), Rank(3)));
+        assert_eq!(e4.to_string(), "e4");
+        assert_eq!(e4.coords(), (4, 3));
+
+        // Three files and ten ranks, so swapping them would fall off
+        let mut board = Board::new(3, 10).unwrap();
+        let corner = Square::at(2, 9);
+        assert!(board.is_valid_tile(corner));
+        assert!(!board.is_valid_tile(Square::at(9, 2)));
+        assert_eq!(corner.offset((1, 0), &board), None);
+        assert_eq!(corner.offset((-2, -9), &board), Some(Square::at(0, 0)));
+        assert_eq!(File(2).offset(1, &board), None);
+        assert_eq!(Rank(9).offset(-9, &board), Some(Rank(0)));
+        assert_eq!(Rank(0).offset(-1, &board), None);
+
+        board.set_emptiness(corner, false);
+        board.set_piece_color(corner, Color::Black);
+        assert!(!board.is_empty_tile(corner));
+        assert_eq!(board.piece_color(corner), Color::Black);
+        assert!(board.is_empty_tile(Square::at(2, 8)));
+    }
+
+    #[test]
+    fn square_names() {
+        assert_eq!(Game::get_coords_from_string("e4".to_string()), Ok((4, 3)));
+        assert_eq!(Game::get_coords_from_string("H8".to_string()), Ok((7, 7)));
+        assert_eq!(Game::get_coords_from_string("j12".to_string()), Ok((9, 11)));
+        assert_eq!(Game::get_coords_from_string("ab1".to_string()), Ok((27, 0)));
+        for s in &["", "e", "4e", "e0", "e04", "é4", "e4e", "e-1"] {
+            assert_eq!(
+                Game::get_coords_from_string(s.to_string()),
+                Err(Error::InvalidSquare(s.to_string()))
+            );
+        }
+
+        for (x, name) in &[
+            (0, "a"),
+            (25, "z"),
+            (26, "aa"),
+            (51, "az"),
+            (701, "zz"),
+            (702, "aaa"),
+        ] {
+            assert_eq!(File(*x).to_string(), *name);
+            assert_eq!(name.parse(), Ok(File(*x)));
+        }
+        assert_eq!(Game::get_string_from_coords(9, 11), "j12");
+        assert_eq!(
+            Game::get_string_from_coords(999_999, 999_999),
+            "bdwgn1000000"
+        );
+        assert_eq!(
+            Game::get_coords_from_string("bdwgn1000000".to_string()),
+            Ok((999_999, 999_999))
+        );
+    }
+
+    #[test]
+    fn notation_on_large_boards() {
+        // Ranks past the ninth and runs of more than nine empty squares
+        let fen = "k11/12/12/12/12/12/12/12/12/12/12/R10K w - - 0 1";
+        let game = fen::from_fen(fen).unwrap();
+        assert_eq!(fen::to_fen(&game), fen);
+        let m = move_from_san(&game, "Ra10").unwrap();
+        assert_eq!(m.to, Square::at(0, 9));
+        assert_eq!(move_to_uci(&game, &m, false), "a1a10");
+        assert_eq!(move_from_uci(&game, "a1a10", false), Some(m));
+
+        // Files past z
+        let fen = "k29/30/R26PK1 w - - 0 1";
+        let game = fen::from_fen(fen).unwrap();
+        assert_eq!(fen::to_fen(&game), fen);
+        let m = move_from_san(&game, "Raa1").unwrap();
+        assert_eq!(m.to, Square::at(26, 0));
+        assert_eq!(move_to_san(&game, &m), "Raa1");
+        assert_eq!(move_to_uci(&game, &m, false), "a1aa1");
+
+        let fen = "k13/14/10pP2/14/14/14/14/14/14/14/14/14/14/K13 b - l11 0 1";
+        assert_eq!(
+            fen::from_fen(fen).unwrap().en_passant(),
+            Some(Square::at(11, 10))
+        );
+        assert!(fen::from_fen("k13/14/K13 b - o2 0 1").is_err());
+    }
+
+    #[test]
+    fn position_builder() {
+        let sq = |s: &str| s.parse::<Square>().unwrap();
+        let mut builder = PositionBuilder::new();
+        builder
+            .place(sq("e1"), PieceType::King, Color::White)
+            .place(sq("h1"), PieceType::Rook, Color::White)
+            .place(sq("e8"), PieceType::King, Color::Black)
+            .place(sq("d4"), PieceType::Pawn, Color::White)
+            .place(sq("e4"), PieceType::Pawn, Color::Black)
+            .side_to_move(Color::Black)
+            .castling(Color::White, File(7))
+            .en_passant(Some(sq("d3")))
+            .halfmove_clock(0)
+            .fullmove_number(30);
+        let game = builder.build().unwrap();
+        assert_eq!(fen::to_fen(&game), "4k3/8/8/8/3Pp3/8/8/4K2R b K d3 0 30");
+        assert!(move_from_san(&game, "exd3").is_some());
+
+        // En passant needs the pawn that double stepped, castling the rook
+        builder.remove(sq("d4"));
+        assert!(matches!(builder.build(), Err(Error::InvalidPosition(_))));
+        builder.en_passant(None);
+        assert!(builder.build().is_ok());
+        builder.remove(sq("h1"));
+        assert!(matches!(builder.build(), Err(Error::InvalidPosition(_))));
+    }
+
+    #[test]
+    fn position_builder_rejects_impossible_positions() {
+        let sq = |s: &str| s.parse::<Square>().unwrap();
+        let kings = || {
+            let mut builder = PositionBuilder::new();
+            builder
+                .place(sq("e1"), PieceType::King, Color::White)
+                .place(sq("e8"), PieceType::King, Color::Black);
+            builder
+        };
+        let invalid = |builder: &PositionBuilder| {
+            assert!(matches!(builder.build(), Err(Error::InvalidPosition(_))))
+        };
+
+        invalid(kings().remove(sq("e8")));
+        invalid(kings().place(sq("a8"), PieceType::King, Color::Black));
+        invalid(kings().place(sq("a1"), PieceType::Pawn, Color::White));
+        invalid(kings().place(sq("a8"), PieceType::Pawn, Color::White));
+        invalid(kings().fullmove_number(0));
+
+        // Only the side to move may be in check
+        invalid(kings().place(sq("e4"), PieceType::Rook, Color::White));
+        assert!(kings()
+            .place(sq("e4"), PieceType::Rook, Color::White)
+            .side_to_move(Color::Black)
+            .build()
+            .is_ok());
+
+        // Two knights can't give check at once, a knight and a rook can
+        let mut two_knights = kings();
+        two_knights
+            .place(sq("d3"), PieceType::Knight, Color::Black)
+            .place(sq("f3"), PieceType::Knight, Color::Black);
+        invalid(&two_knights);
+        two_knights
+            .remove(sq("f3"))
+            .place(sq("e5"), PieceType::Rook, Color::Black);
+        assert!(two_knights.build().is_ok());
+        two_knights.place(sq("h4"), PieceType::Bishop, Color::Black);
+        invalid(&two_knights);
+
+        assert_eq!(
+            kings()
+                .place(Square::at(8, 0), PieceType::Rook, Color::White)
+                .build()
+                .err(),
+            Some(Error::InvalidSquare("i1".to_string()))
+        );
+        assert_eq!(
+            PositionBuilder::with_size(0, 8).err(),
+            Some(Error::InvalidDimensions(0, 8))
+        );
+
+        // FEN parses positions the builder would not set up
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 5 20";
+        let game = fen::from_fen(fen).unwrap();
+        assert_eq!(
+            fen::to_fen(&PositionBuilder::from_game(&game).build().unwrap()),
+            fen
+        );
+        let game = fen::from_fen("4k3/8/8/8/8/8/8/4KK2 w - - 0 1").unwrap();
+        invalid(&PositionBuilder::from_game(&game));
+    }
+
+    fn back_rank_mate() -> Game {
+        let mut game = Game::new();
+        game.place_piece(PieceType::King, Square::at(6, 0), true);
+        game.place_piece(PieceType::Rook, Square::at(0, 0), true);
+        game.place_piece(PieceType::King, Square::at(7, 7), false);
+        game.place_piece(PieceType::Pawn, Square::at(6, 6), false);
+        game.place_piece(PieceType::Pawn, Square::at(7, 6), false);
+        game
+    }
+
+    #[test]
+    fn game_is_send_and_clone() {
+        fn assert_send_clone<T: Send + Clone>() {}
+        assert_send_clone::<Game>();
+    }
+
+    #[test]
+    fn clone_from_copies_the_whole_position() {
+        let kiwipete =
+            fen::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let mut crazyhouse = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        crazyhouse
+            .load_fen("4k3/8/8/8/8/8/8/4K3[QNp] w - - 0 1")
+            .unwrap();
+        let grand = Game::with_variant(variant::by_name("grand").unwrap());
+        // Into games of other sizes and variants, and back
+        for (source, scratch) in [
+            (&kiwipete, &grand),
+            (&crazyhouse, &kiwipete),
+            (&grand, &crazyhouse),
+        ] {
+            let mut copy = scratch.clone();
+            copy.clone_from(source);
+            assert_eq!(fen::to_fen(&copy), fen::to_fen(source));
+            assert_eq!(copy.legal_moves(), source.legal_moves());
+            assert_eq!(copy.variant().name(), source.variant().name());
+
+            let m = source.legal_moves()[0];
+            copy.next(m).unwrap();
+            assert_ne!(fen::to_fen(&copy), fen::to_fen(source));
+        }
+    }
+
+    #[test]
+    fn search_finds_mate_in_one() {
+        let game = back_rank_mate();
+        let result = Searcher::new(1 << 12).search(&game, &SearchLimits::depth(2));
+        let best = result.best_move.unwrap();
+        assert_eq!(
+            best,
+            Move::new(Square::at(0, 0), Square::at(0, 7), PieceType::Rook)
+        );
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn search_single_thread_is_deterministic() {
+        let game = back_rank_mate();
+        let first = Searcher::new(1 << 12).search(&game, &SearchLimits::depth(3));
+        let second = Searcher::new(1 << 12).search(&game, &SearchLimits::depth(3));
+        assert_eq!(first.pv, second.pv);
+        assert_eq!(first.score, second.score);
+        assert_eq!(first.nodes, second.nodes);
+    }
+
+    #[test]
+    fn search_multi_threaded() {
+        let game = back_rank_mate();
+        let mut limits = SearchLimits::depth(2);
+        limits.threads = 4;
+        let result = Searcher::new(1 << 12).search(&game, &limits);
+        assert_eq!(result.best_move.unwrap().to, Square::at(0, 7));
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn transposition_table_keeps_entries_that_pack_to_zero() {
+        let table = TranspositionTable::new(16);
+        let entry = TtEntry {
+            score: 0,
+            depth: 0,
+            bound: Bound::Exact,
+            move_index: Some(0),
+        };
+        assert_eq!(table.probe(0), None);
+        table.store(0, entry);
+        assert_eq!(table.probe(0), Some(entry));
+        table.clear();
+        assert_eq!(table.probe(0), None);
+    }
+
+    #[test]
+    fn hash_key_keeps_large_piece_types_apart_from_colours() {
+        // The index of the last custom piece is 256 more than a pawn's, which
+        // must not read as a pawn of another colour
+        let key = |piece_type: PieceType, white: bool| {
+            let mut game = Game::new();
+            game.place_piece(piece_type, Square::at(0, 0), white);
+            game.hash_key()
+        };
+        assert_ne!(
+            key(PieceType::Custom(247), true),
+            key(PieceType::Pawn, false)
+        );
+    }
+
+    #[test]
+    fn search_scores_stalemate_as_draw() {
+        let mut game = Game::new();
+        game.place_piece(PieceType::King, Square::at(0, 0), true);
+        game.place_piece(PieceType::Queen, Square::at(6, 5), true);
+        game.place_piece(PieceType::Rook, Square::at(5, 0), true);
+        game.place_piece(PieceType::King, Square::at(7, 7), false);
+        game.set_whites_turn(false);
+        assert!(!game.in_check());
+        assert!(game.no_moves());
+        let result = Searcher::new(1 << 12).search(&game, &SearchLimits::depth(2));
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, 0);
+    }
+
+    #[test]
+    fn search_multi_pv_lines_are_ranked_and_distinct() {
+        let game = back_rank_mate();
+        let mut limits = SearchLimits::depth(2);
+        limits.multi_pv = 4;
+        let result = Searcher::new(1 << 12).search(&game, &limits);
+        assert_eq!(result.lines.len(), 4);
+        assert_eq!(result.lines[0].score, MATE - 1);
+        assert_eq!(result.lines[0].pv, result.pv);
+        for pair in result.lines.windows(2) {
+            assert!(pair[0].score >= pair[1].score);
+        }
+        let mut first_moves: Vec<_> = result.lines.iter().map(|l| l.pv[0]).collect();
+        first_moves.dedup();
+        assert_eq!(first_moves.len(), 4);
+    }
+
+    #[test]
+    fn uci_multi_pv_option() {
+        let input =
+            "uci\nsetoption name MultiPV value 3\nposition startpos moves e2e4\ngo depth 1\nquit\n";
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("option name MultiPV"));
+        assert!(output.contains("multipv 1 "));
+        assert!(output.contains("multipv 3 "));
+        assert!(!output.contains("multipv 4 "));
+        assert!(output.contains("bestmove "));
+    }
+
+    struct Collect(Arc<Mutex<Vec<SearchInfo>>>);
+
+    impl SearchObserver for Collect {
+        fn progress(&mut self, info: &SearchInfo) {
+            self.0.lock().unwrap().push(info.clone());
+        }
+    }
+
+    #[test]
+    fn background_search_streams_progress_until_stopped() {
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let mut limits = SearchLimits::depth(1);
+        limits.infinite = true;
+        let searcher = Searcher::new(1 << 12);
+        let handle = searcher.spawn(back_rank_mate(), limits, false, Collect(infos.clone()));
+        while infos.lock().unwrap().is_empty() {
+            std::thread::yield_now();
+        }
+        assert!(!handle.is_finished());
+        handle.stop();
+        let result = handle.wait();
+        assert_eq!(result.score, MATE - 1);
+        let infos = infos.lock().unwrap();
+        assert_eq!(infos[0].depth, 1);
+        assert_eq!(infos[0].pv, vec![result.best_move.unwrap()]);
+    }
+
+    #[test]
+    fn ponder_search_waits_for_ponderhit() {
+        let searcher = Searcher::new(1 << 12);
+        let handle = searcher.spawn(back_rank_mate(), SearchLimits::depth(1), true, ());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!handle.is_finished());
+        handle.ponderhit();
+        let result = handle.wait();
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn uci_go_infinite_until_stop() {
+        let input = "position startpos\ngo infinite\nisready\nstop\nquit\n";
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("readyok"));
+        assert_eq!(output.matches("bestmove ").count(), 1);
+    }
+
+    fn xorshift(mut x: u64) -> u64 {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    }
+
+    // Plays a whole game against the clock with a fake search where every
+    // iteration takes 1.5 to 2.5 times longer than the one before, and the
+    // best move and score wobble randomly. Returns the lowest clock reading.
+    fn simulate_clock(control: TimeControl, moves: u32, mut seed: u64) -> Duration {
+        let period = control.moves_to_go;
+        let mut control = control;
+        let mut lowest = control.remaining;
+        for _ in 0..moves {
+            let mut time = TimeManager::new(&control);
+            let mut elapsed = Duration::from_micros(500);
+            let mut iteration = elapsed;
+            let (mut best, mut score) = (0, 0);
+            loop {
+                seed = xorshift(seed);
+                if seed.is_multiple_of(4) {
+                    best += 1;
+                }
+                score += (seed % 41) as i32 - 20 - if seed.is_multiple_of(7) { 60 } else { 0 };
+                if !time.iteration_done(elapsed, best, score) {
+                    break;
+                }
+                iteration = iteration.mul_f64(1.5 + (seed % 100) as f64 / 100.0);
+                if time.out_of_time(elapsed + iteration) {
+                    elapsed = elapsed.max(time.hard_limit());
+                    break;
+                }
+                elapsed += iteration;
+            }
+
+            // Some lag between the engine and the GUI on every move
+            let used = elapsed + Duration::from_millis(10);
+            assert!(used < control.remaining, "flagged with {:?}", control);
+            control.remaining = control.remaining - used + control.increment;
+            lowest = lowest.min(control.remaining);
+
+            control.moves_to_go = match (control.moves_to_go, period) {
+                (Some(1), Some(period)) => {
+                    control.remaining += Duration::from_secs(90 * 60);
+                    Some(period)
+                }
+                (Some(m), _) => Some(m - 1),
+                (None, _) => None,
+            };
+        }
+        lowest
+    }
+
+    #[test]
+    fn time_sudden_death_never_flags() {
+        for seed in 1..20 {
+            let control = TimeControl {
+                remaining: Duration::from_secs(60),
+                increment: Duration::from_millis(0),
+                moves_to_go: None,
+            };
+            simulate_clock(control, 150, seed);
+            let control = TimeControl {
+                remaining: Duration::from_secs(5),
+                increment: Duration::from_millis(0),
+                moves_to_go: None,
+            };
+            simulate_clock(control, 150, seed);
+        }
+    }
+
+    #[test]
+    fn time_fischer_increment_never_flags() {
+        for seed in 1..20 {
+            let control = TimeControl {
+                remaining: Duration::from_secs(180),
+                increment: Duration::from_secs(2),
+                moves_to_go: None,
+            };
+            let lowest = simulate_clock(control, 250, seed);
+            // The increment is used but the clock is not run down to nothing
+            assert!(lowest > Duration::from_secs(2));
+
+            let control = TimeControl {
+                remaining: Duration::from_millis(100),
+                increment: Duration::from_millis(100),
+                moves_to_go: None,
+            };
+            simulate_clock(control, 250, seed);
+        }
+    }
+
+    #[test]
+    fn time_classical_repeating_control_never_flags() {
+        for seed in 1..20 {
+            let control = TimeControl {
+                remaining: Duration::from_secs(90 * 60),
+                increment: Duration::from_millis(0),
+                moves_to_go: Some(40),
+            };
+            simulate_clock(control, 250, seed);
+        }
+    }
+
+    #[test]
+    fn time_extends_on_instability_and_score_drop() {
+        let control = TimeControl {
+            remaining: Duration::from_secs(60),
+            increment: Duration::from_millis(0),
+            moves_to_go: None,
+        };
+        let mut stable = TimeManager::new(&control);
+        let mut unstable = TimeManager::new(&control);
+        for i in 0..4 {
+            stable.iteration_done(Duration::from_millis(1), 0, 10);
+            unstable.iteration_done(Duration::from_millis(1), i, 10 - 50 * i as i32);
+        }
+        assert_eq!(stable.target(), stable.soft_limit());
+        assert!(unstable.target() > stable.target());
+        assert!(unstable.target() <= unstable.hard_limit());
+
+        let mut forced = TimeManager::new(&control);
+        forced.set_single_reply();
+        assert!(!forced.iteration_done(Duration::from_millis(0), 0, 0));
+    }
+
+    #[test]
+    fn uci_go_with_clock() {
+        let input = "position startpos\ngo wtime 500 btime 500\n";
+        let start = Instant::now();
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(String::from_utf8(output).unwrap().contains("bestmove "));
+    }
+
+    #[test]
+    fn skill_limits_grow_with_level() {
+        assert_eq!(Skill::from_elo(0), Skill::new(0));
+        assert_eq!(Skill::from_elo(5000), Skill::new(20));
+        assert!(!Skill::new(20).is_limited());
+        for level in 1..=20 {
+            let (weaker, stronger) = (Skill::new(level - 1), Skill::new(level));
+            assert!(weaker.depth() <= stronger.depth());
+            assert!(weaker.nodes() <= stronger.nodes());
+            assert!(Skill::from_elo(800 + 100 * level).level() >= weaker.level());
+        }
+    }
+
+    #[test]
+    fn skill_error_shrinks_with_level() {
+        let game = back_rank_mate();
+        let moves = game.legal_moves();
+        let lines: Vec<PvLine> = [100, 60, 20, -300]
+            .iter()
+            .zip(moves.iter())
+            .map(|(score, m)| PvLine {
+                score: *score,
+                pv: vec![*m],
+            })
+            .collect();
+
+        let average_loss = |skill: Skill| {
+            let total: i32 = (1..500)
+                .map(|seed| 100 - skill.pick(&lines, seed).unwrap().score)
+                .sum();
+            total as f64 / 499.0
+        };
+        assert_eq!(average_loss(Skill::new(20)), 0.0);
+        assert!(average_loss(Skill::new(0)) > average_loss(Skill::new(10)));
+        assert!(average_loss(Skill::new(10)) > average_loss(Skill::new(19)));
+        assert!(average_loss(Skill::new(0)) > 0.0);
+    }
+
+    #[test]
+    fn skill_limited_search_still_plays() {
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        let mut limits = SearchLimits::depth(4);
+        limits.skill = Some(Skill::new(0));
+        let result = Searcher::new(1 << 12).search(&game, &limits);
+        assert!(game.legal_moves().contains(&result.best_move.unwrap()));
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    fn uci_skill_options() {
+        let input = "uci\nsetoption name UCI_LimitStrength value true\nsetoption name UCI_Elo value 1200\nsetoption name Skill Level value 3\ngo depth 3\n";
+        let mut uci = Uci::new();
+        let output = uci.run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("option name Skill Level"));
+        assert!(output.contains("option name UCI_Elo"));
+        assert!(!output.contains("unsupported option"));
+        assert!(output.contains("bestmove "));
+        // With UCI_LimitStrength on the Elo wins over the skill level
+        assert_eq!(uci.skill(), Skill::from_elo(1200));
+        assert_ne!(uci.skill(), Skill::new(3));
+
+        let input = "setoption name UCI_LimitStrength value false\n";
+        uci.run(input.as_bytes(), Vec::new()).unwrap();
+        assert_eq!(uci.skill(), Skill::new(3));
+    }
+
+    #[test]
+    fn uci_go_nodes() {
+        let input = "position startpos\ngo nodes 500\n";
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("bestmove "));
+        // The search stops soon after the limit instead of going to its
+        // default depth
+        let nodes: Vec<u64> = output
+            .lines()
+            .filter_map(|line| line.split(" nodes ").nth(1))
+            .filter_map(|rest| rest.split(' ').next()?.parse().ok())
+            .collect();
+        assert!(!nodes.is_empty());
+        assert!(nodes.iter().all(|n| *n < 1000), "{:?}", nodes);
+    }
+
+    #[test]
+    fn skill_calibration_points_add_up() {
+        let points = calibrate(&[0, 20], 2, 2);
+        assert_eq!(points.iter().sum::<f64>(), 2.0);
+    }
+
+    // Slow, run with --ignored to check that the levels stay in order
+    #[test]
+    #[ignore]
+    fn skill_calibration_is_monotonic() {
+        let points = calibrate(&[0, 6, 20], 4, 40);
+        assert!(
+            points[0] <= points[1] && points[1] <= points[2],
+            "{:?}",
+            points
+        );
+    }
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let game = fen::from_fen(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                game.perft(depth as u32 + 1),
+                *nodes,
+                "{} depth {}",
+                fen,
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn perft_startpos() {
+        assert_perft(fen::STARTPOS, &[20, 400, 8902]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn perft_en_passant_and_promotions() {
+        assert_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812],
+        );
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn perft_chess960() {
+        assert_perft(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            &[21, 528, 12189],
+        );
+        assert_perft(
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            &[21, 807, 18002],
+        );
+        assert_perft(
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            &[20, 479, 10471],
+        );
+    }
+
+    #[test]
+    fn chess960_setup_numbering() {
+        let mut game = Game::new();
+        game.chess960_setup(518).unwrap();
+        assert_eq!(fen::to_fen(&game), fen::STARTPOS);
+        game.chess960_setup(0).unwrap();
+        assert_eq!(
+            fen::to_fen(&game),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+        assert_eq!(
+            fen::to_shredder_fen(&game),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1"
+        );
+
+        // Every position has the king between the rooks and bishops on
+        // opposite colours
+        let mut positions = std::collections::HashSet::new();
+        for index in 0..960 {
+            game.chess960_setup(index).unwrap();
+            let fen = fen::to_shredder_fen(&game);
+            let back_rank: Vec<char> = fen.split('/').next_back().unwrap()[..8].chars().collect();
+            let find = |c: char| -> Vec<usize> { (0..8).filter(|x| back_rank[*x] == c).collect() };
+            let (rooks, king, bishops) = (find('R'), find('K'), find('B'));
+            assert!(rooks[0] < king[0] && king[0] < rooks[1], "{}", fen);
+            assert_ne!(bishops[0] % 2, bishops[1] % 2, "{}", fen);
+            positions.insert(fen);
+        }
+        assert_eq!(positions.len(), 960);
+        assert!(matches!(
+            game.chess960_setup(960),
+            Err(Error::InvalidPosition(_))
+        ));
+    }
+
+    #[test]
+    fn chess960_castling_ends_on_standard_squares() {
+        let mut game =
+            fen::from_fen("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1").unwrap();
+        let castle = |game: &Game, rook_x: usize| {
+            game.legal_moves()
+                .into_iter()
+                .find(|m| game.is_castling(m) && m.to.file.0 == rook_x)
+                .unwrap()
+        };
+        let m = castle(&game, 6);
+        assert!(!game.is_capture(&m));
+        game.next(m).unwrap();
+        let m = castle(&game, 1);
+        game.next(m).unwrap();
+        assert_eq!(
+            fen::to_fen(&game),
+            "2kr2r1/pppppppp/8/8/8/8/PPPPPPPP/1R3RK1 w - - 2 2"
+        );
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w HAha - 0 1",
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+        ]
+        .iter()
+        {
+            assert_eq!(fen::to_shredder_fen(&fen::from_fen(fen).unwrap()), *fen);
+        }
+        for fen in [
+            fen::STARTPOS,
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+            "r3k2r/8/8/8/8/8/8/R3KR1R w KFkq - 0 1",
+        ]
+        .iter()
+        {
+            assert_eq!(fen::to_fen(&fen::from_fen(fen).unwrap()), *fen);
+        }
+        assert!(fen::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1").is_err());
+        assert!(fen::from_fen("rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1").is_err());
+
+        // Huge numbers of empty squares, rights given twice and en passant
+        // squares no pawn skipped are errors
+        assert!(fen::from_fen("99999999999999999999999/8 w - - 0 1").is_err());
+        assert!(fen::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KKkq - 0 1").is_err());
+        assert!(fen::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KHkq - 0 1").is_err());
+        assert!(
+            fen::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq a1 0 1").is_err()
+        );
+        assert!(
+            fen::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq d3 0 1").is_err()
+        );
+        assert!(
+            fen::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 1").is_err()
+        );
+        assert!(
+            fen::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").is_ok()
+        );
+    }
+
+    #[test]
+    fn xfen_castling_with_several_rooks() {
+        // K and Q name the outermost rook on their side of the king, inner
+        // rooks go by their file
+        let rooks = |fen: &str| -> Vec<(bool, usize)> {
+            let game = fen::from_fen(fen).unwrap();
+            let rights = game.castling_rights();
+            rights
+                .iter()
+                .map(|r| (r.color.is_white(), r.rook_file.0))
+                .collect()
+        };
+        assert_eq!(
+            rooks("4k3/8/8/8/8/8/8/RR2KR1R w KQ - 0 1"),
+            [(true, 0), (true, 7)]
+        );
+        assert_eq!(
+            rooks("4k3/8/8/8/8/8/8/RR2KR1R w FB - 0 1"),
+            [(true, 1), (true, 5)]
+        );
+        assert_eq!(
+            rooks("rr2kr1r/8/8/8/8/8/8/4K3 w kq - 0 1"),
+            [(false, 0), (false, 7)]
+        );
+
+        // The outermost rook named by its file is still written as K or Q
+        let game = fen::from_fen("4k3/8/8/8/8/8/8/RR2KR1R w HB - 0 1").unwrap();
+        assert_eq!(fen::to_fen(&game), "4k3/8/8/8/8/8/8/RR2KR1R w KB - 0 1");
+        assert_eq!(
+            fen::to_shredder_fen(&game),
+            "4k3/8/8/8/8/8/8/RR2KR1R w HB - 0 1"
+        );
+
+        // One rook can't have its right twice
+        assert!(fen::from_fen("4k3/8/8/8/8/8/8/RR2KR1R w KH - 0 1").is_err());
+        assert!(fen::from_fen("4k3/8/8/8/8/8/8/RR2KR1R w QA - 0 1").is_err());
+        assert!(fen::from_fen("rr2kr1r/8/8/8/8/8/8/4K3 w kk - 0 1").is_err());
+        // K needs a rook on the king's side at all
+        assert!(fen::from_fen("4k3/8/8/8/8/8/8/RR2K3 w K - 0 1").is_err());
+
+        // Past z files have two letters, which run together in the field
+        let wide = "r13k12r1r/30/30/30/30/30/30/R13K12R1R";
+        let game = fen::from_fen(&format!("{} w KABkab - 0 1", wide)).unwrap();
+        assert_eq!(
+            rooks(&format!("{} w KABkab - 0 1", wide)),
+            [(true, 27), (true, 29), (false, 27), (false, 29)]
+        );
+        assert_eq!(fen::to_fen(&game), format!("{} w KABkab - 0 1", wide));
+        let shredder = fen::to_shredder_fen(&game);
+        assert_eq!(shredder, format!("{} w ADABadab - 0 1", wide));
+        let again = fen::from_fen(&shredder).unwrap();
+        assert_eq!(fen::to_fen(&again), fen::to_fen(&game));
+    }
+
+    #[test]
+    fn uci_castling_notation() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let game = fen::from_fen(fen).unwrap();
+        let m = move_from_uci(&game, "e1g1", false).unwrap();
+        assert!(game.is_castling(&m));
+        assert_eq!(move_to_uci(&game, &m, true), "e1h1");
+        assert_eq!(move_from_uci(&game, "e1h1", true), Some(m));
+        assert_eq!(move_from_uci(&game, "e1h1", false), None);
+
+        let input = format!(
+            "setoption name UCI_Chess960 value true\nposition fen {} moves e1a1\nposition fen {} moves e1c1\nquit\n",
+            fen, fen
+        );
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "info string illegal move e1c1\n");
+    }
+
+    #[test]
+    fn perft_capablanca_and_gothic() {
+        let mut game = Game::new();
+        game.capablanca_setup();
+        assert_eq!(
+            fen::to_fen(&game),
+            "rnabqkbcnr/pppppppppp/10/10/10/10/PPPPPPPPPP/RNABQKBCNR w KQkq - 0 1"
+        );
+        assert_eq!(game.perft(1), 28, "\n{}", game);
+        assert_eq!(game.perft(2), 784, "\n{}", game);
+        assert_eq!(game.perft(3), 25228, "\n{}", game);
+
+        game.gothic_setup();
+        assert_eq!(game.perft(1), 28, "\n{}", game);
+        assert_eq!(game.perft(2), 784, "\n{}", game);
+    }
+
+    #[test]
+    fn perft_grand() {
+        let mut game = Game::new();
+        game.grand_setup();
+        assert!(game.castling_rights().is_empty());
+        assert_eq!(game.perft(1), 65, "\n{}", game);
+        assert_eq!(game.perft(2), 4225, "\n{}", game);
+    }
+
+    #[test]
+    fn compound_pieces_move_as_both_parts() {
+        let mut game = Game::new();
+        game.capablanca_setup();
+        let archbishop = game.moves_from(Square::at(2, 0)).unwrap();
+        assert_eq!(archbishop.len(), 2);
+        assert!(archbishop.iter().all(|m| m.piece == PieceType::Archbishop));
+
+        // Knight jumps and rook slides on an empty board
+        let mut game = Game::new();
+        game.place_piece(PieceType::King, Square::at(0, 0), true);
+        game.place_piece(PieceType::King, Square::at(7, 7), false);
+        game.place_piece(PieceType::Chancellor, Square::at(3, 3), true);
+        assert_eq!(
+            game.moves_from(Square::at(3, 3)).unwrap().len(),
+            14 + 8,
+            "\n{}",
+            game
+        );
+    }
+
+    #[test]
+    fn grand_promotion_only_to_captured_pieces() {
+        let mut game = Game::new();
+        game.grand_setup();
+        // Clear the a-file apart from one white pawn
+        for (x, y) in [(0, 0), (0, 2), (0, 7), (1, 7), (0, 9)].iter() {
+            game.remove_for_test(Square::at(*x, *y));
+        }
+        let promotions = |y: usize| -> Vec<PieceType> {
+            let mut game = game.clone();
+            game.place_piece(PieceType::Pawn, Square::at(0, y - 1), true);
+            let mut types: Vec<PieceType> = game
+                .moves_from(Square::at(0, y - 1))
+                .unwrap()
+                .iter()
+                .map(|m| m.piece)
+                .collect();
+            types.sort();
+            types
+        };
+        // Only the missing rook can come back, and only on the last rank
+        // is promotion forced
+        assert_eq!(promotions(6), vec![PieceType::Pawn]);
+        assert_eq!(promotions(7), vec![PieceType::Pawn, PieceType::Rook]);
+        assert_eq!(promotions(9), vec![PieceType::Rook]);
+    }
+
+    #[test]
+    fn betza_parses_leapers_and_riders() {
+        let king = betza::parse("K").unwrap();
+        assert_eq!(king.len(), 2);
+        assert!(king.iter().all(|p| p.range == 1 && p.leaps.len() == 4));
+
+        let nightrider = betza::parse("NN").unwrap();
+        assert_eq!(nightrider[0].range, 0);
+        assert_eq!(nightrider[0].leaps.len(), 8);
+        assert_eq!(betza::parse("W3").unwrap()[0].range, 3);
+
+        let asymmetric = betza::parse("mRcB").unwrap();
+        assert_eq!(asymmetric[0].mode, Mode::Move);
+        assert_eq!(asymmetric[1].mode, Mode::Capture);
+
+        let pawn = betza::parse("mfWcfF").unwrap();
+        assert_eq!(pawn[0].leaps, vec![(0, 1)]);
+        let mut captures = pawn[1].leaps.clone();
+        captures.sort();
+        assert_eq!(captures, vec![(-1, 1), (1, 1)]);
+        assert_eq!(betza::parse("ffN").unwrap()[0].leaps.len(), 2);
+        assert_eq!(betza::parse("fsN").unwrap()[0].leaps.len(), 2);
+        assert_eq!(betza::parse("flF").unwrap()[0].leaps, vec![(-1, 1)]);
+
+        assert!(betza::parse("X").is_err());
+        assert!(betza::parse("fm").is_err());
+        assert!(betza::parse("xW").is_err());
+    }
+
+    #[test]
+    fn betza_pieces_match_the_builtin_ones() {
+        let pieces = vec![
+            PieceDef::new("Rook", 'x', 500, "R").unwrap(),
+            PieceDef::new("Knight", 'y', 320, "N").unwrap(),
+            PieceDef::new("Bishop", 'z', 330, "B").unwrap(),
+            PieceDef::new("Queen", 'w', 900, "Q").unwrap(),
+        ];
+        let game = fen::from_fen_with_pieces(
+            "xyzwkzyx/pppppppp/8/8/8/8/PPPPPPPP/XYZWKZYX w - - 0 1",
+            &pieces,
+        )
+        .unwrap();
+        assert_eq!(game.perft(3), 8902, "\n{}", game);
+
+        let game = fen::from_fen_with_pieces("8/2p5/3p4/KP5x/1X3p1k/8/4P1P1/8 w - - 0 1", &pieces)
+            .unwrap();
+        assert_eq!(game.perft(1), 14, "\n{}", game);
+        assert_eq!(game.perft(2), 191, "\n{}", game);
+        assert_eq!(game.perft(3), 2812, "\n{}", game);
+        assert_eq!(
+            fen::to_fen(&game),
+            "8/2p5/3p4/KP5x/1X3p1k/8/4P1P1/8 w - - 0 1"
+        );
+    }
+
+    #[test]
+    fn custom_piece_moves_and_captures_differently() {
+        let mut game = Game::new();
+        let piece = game
+            .register_piece(PieceDef::new("Mover", 'm', 400, "mRcB").unwrap())
+            .unwrap();
+        game.place_piece(PieceType::King, Square::at(7, 0), true);
+        game.place_piece(PieceType::King, Square::at(7, 7), false);
+        game.place_piece(piece, Square::at(0, 0), true);
+        game.place_piece(PieceType::Pawn, Square::at(3, 3), false);
+        game.place_piece(PieceType::Pawn, Square::at(0, 4), false);
+
+        let mut targets: Vec<(usize, usize)> = game
+            .moves_from(Square::at(0, 0))
+            .unwrap()
+            .iter()
+            .map(|m| m.to.coords())
+            .collect();
+        targets.sort();
+        // Slides up to the pawn on a5 without taking it, takes the one on d4
+        // diagonally and moves along the first rank
+        let mut expected = vec![(0, 1), (0, 2), (0, 3), (3, 3)];
+        expected.extend((1..7).map(|x| (x, 0)));
+        expected.sort();
+        assert_eq!(targets, expected);
+
+        // Only the capturing part gives check
+        game.place_piece(PieceType::King, Square::at(2, 2), false);
+        assert!(!game.is_capture(&Move::new(Square::at(0, 0), Square::at(0, 1), piece)));
+        assert!(game.square_attacked(Square::at(2, 2), Color::White));
+        assert!(!game.square_attacked(Square::at(0, 5), Color::White));
+
+        assert!(game
+            .register_piece(PieceDef::new("Clash", 'n', 300, "N").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn pawns_promote_to_custom_pieces() {
+        let mut game = Game::new();
+        let amazon = game
+            .register_piece(PieceDef::new("Amazon", 'z', 1200, "QN").unwrap())
+            .unwrap();
+        let mut rules = crate::board::PawnRules::standard(8);
+        rules.promotion_pieces.push(amazon);
+        game.place_piece(PieceType::King, Square::at(0, 0), true);
+        game.place_piece(PieceType::King, Square::at(7, 0), false);
+        game.place_piece(PieceType::Pawn, Square::at(4, 6), true);
+        game.set_pawn_rules(rules);
+
+        let m = move_from_uci(&game, "e7e8z", false).unwrap();
+        assert_eq!(m.piece, amazon);
+        game.next(m).unwrap();
+        assert_eq!(fen::to_fen(&game), "4Z3/8/8/8/8/8/8/K6k b - - 0 1");
+    }
+
+    fn variant_perft(name: &str, fen: Option<&str>, expected: &[u64]) {
+        let mut game = Game::with_variant(variant::by_name(name).unwrap());
+        if let Some(fen) = fen {
+            game.load_fen(fen).unwrap();
+        }
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                game.perft(depth as u32 + 1),
+                *nodes,
+                "{} depth {}",
+                name,
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn perft_atomic() {
+        variant_perft("atomic", None, &[20, 400, 8902]);
+        variant_perft(
+            "atomic",
+            Some("rn2kb1r/1pp1p2p/p2q1pp1/3P4/2P3b1/4PN2/PP3PPP/R2QKB1R b KQkq - 0 1"),
+            &[40, 1238, 45237],
+        );
+        variant_perft(
+            "atomic",
+            Some("rn1qkb1r/p5pp/2p5/3p4/N3P3/5P2/PPP4P/R1BQK3 w Qkq - 0 1"),
+            &[28, 833, 23353],
+        );
+    }
+
+    #[test]
+    fn atomic_explosions() {
+        let mut game = Game::with_variant(variant::by_name("atomic").unwrap());
+        game.load_fen("4k3/8/8/3n1p2/4P3/8/8/4K3 w - - 0 1")
+            .unwrap();
+        let m = move_from_uci(&game, "e4d5", false).unwrap();
+        game.next(m).unwrap();
+        // The pawn on f5 survives, the capturing pawn and the knight don't
+        assert_eq!(fen::to_fen(&game), "4k3/8/8/5p2/8/8/8/4K3 b - - 0 1");
+
+        // Blowing up the king wins even when it takes the own king out of check
+        game.load_fen("4k3/3q4/8/8/8/8/3R4/3K4 w - - 0 1").unwrap();
+        let m = move_from_uci(&game, "d2d7", false).unwrap();
+        game.next(m).unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::WhiteWins));
+    }
+
+    #[test]
+    fn perft_king_of_the_hill() {
+        variant_perft("kingofthehill", None, &[20, 400, 8902]);
+        // Two of the king moves reach the centre and end the game
+        variant_perft(
+            "kingofthehill",
+            Some("k7/8/8/8/8/4K3/8/8 w - - 0 1"),
+            &[8, 18],
+        );
+    }
+
+    #[test]
+    fn perft_three_check() {
+        variant_perft("3check", None, &[20, 400, 8902]);
+        // With one check left the three checks at depth 2 end the game
+        variant_perft(
+            "3check",
+            Some("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 1+1 0 1"),
+            &[48, 2039, 97848],
+        );
+    }
+
+    #[test]
+    fn three_check_counters_in_fen() {
+        let mut game = Game::with_variant(variant::by_name("3check").unwrap());
+        for uci in ["e2e4", "e7e5", "f1c4", "b8c6", "c4f7"].iter() {
+            let m = move_from_uci(&game, uci, false).unwrap();
+            game.next(m).unwrap();
+        }
+        assert_eq!(game.checks_given(Color::White), 1);
+        let fen = fen::to_fen(&game);
+        assert_eq!(
+            fen,
+            "r1bqkbnr/pppp1Bpp/2n5/4p3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 2+3 0 3"
+        );
+
+        let mut loaded = Game::with_variant(variant::by_name("3check").unwrap());
+        loaded.load_fen(&fen).unwrap();
+        assert_eq!(loaded.checks_given(Color::White), 1);
+        loaded
+            .load_fen(&fen.replace(" 2+3 0 3", " 0 3 +2+0"))
+            .unwrap();
+        assert_eq!(loaded.checks_given(Color::White), 2);
+    }
+
+    #[test]
+    fn perft_antichess() {
+        variant_perft("antichess", None, &[20, 400, 8067, 153299]);
+    }
+
+    #[test]
+    fn antichess_forces_captures_and_loses_pieces_to_win() {
+        let mut game = Game::with_variant(variant::by_name("antichess").unwrap());
+        game.load_fen("8/8/8/8/8/8/3p4/4K3 w - - 0 1").unwrap();
+        // The king must take, and has then given away its last piece
+        assert_eq!(game.legal_moves().len(), 1, "\n{}", game);
+        let m = game.legal_moves()[0];
+        game.next(m).unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::BlackWins));
+
+        // Pawns may promote to a king
+        game.load_fen("8/4P3/8/8/8/8/8/k7 w - - 0 1").unwrap();
+        assert!(move_from_uci(&game, "e7e8k", false).is_some());
+    }
+
+    #[test]
+    fn perft_horde() {
+        variant_perft("horde", None, &[8, 128, 1274, 23310]);
+    }
+
+    #[test]
+    fn perft_racing_kings() {
+        variant_perft("racingkings", None, &[21, 421, 11264, 296242]);
+    }
+
+    #[test]
+    fn racing_kings_goal() {
+        let mut game = Game::with_variant(variant::by_name("racingkings").unwrap());
+        // Black can still follow the white king onto the last rank
+        game.load_fen("4K3/k7/8/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(game.outcome(), None);
+        let m = move_from_uci(&game, "a7a8", false).unwrap();
+        game.next(m).unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::Draw));
+
+        game.load_fen("4K3/8/k7/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::WhiteWins));
+    }
+
+    #[test]
+    fn uci_variant_option() {
+        let input = "setoption name UCI_Variant value antichess\nposition startpos moves e2e3 b7b5\ngo depth 1\nquit\n";
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // Taking on b5 is forced
+        assert!(output.contains("bestmove f1b5"), "{}", output);
+    }
+
+    #[test]
+    fn perft_crazyhouse() {
+        variant_perft(
+            "crazyhouse",
+            Some("2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1"),
+            &[301],
+        );
+        variant_perft(
+            "crazyhouse",
+            Some("r1bqk2r/pppp1ppp/2n1p3/4P3/1b1Pn3/2NB1N2/PPP2PPP/R1BQK2R[] b KQkq - 0 1"),
+            &[42, 1347],
+        );
+        variant_perft(
+            "crazyhouse",
+            Some("4k3/1Q~6/8/8/4b3/8/Kpp5/8[] b - - 0 1"),
+            &[20, 360, 5445],
+        );
+    }
+
+    #[test]
+    fn crazyhouse_pockets_and_drops() {
+        let mut game = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        for uci in ["e2e4", "d7d5", "e4d5", "d8d5"].iter() {
+            let m = move_from_uci(&game, uci, false).unwrap();
+            game.next(m).unwrap();
+        }
+        assert_eq!(game.pocket(Color::White), &[PieceType::Pawn]);
+        assert_eq!(game.pocket(Color::Black), &[PieceType::Pawn]);
+        assert_eq!(
+            fen::to_fen(&game),
+            "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR[Pp] w KQkq - 0 3"
+        );
+
+        // Pawns can't be dropped on the first or last rank
+        assert!(move_from_uci(&game, "P@e8", false).is_none());
+        assert!(move_from_uci(&game, "P@e1", false).is_none());
+        let m = move_from_uci(&game, "P@e4", false).unwrap();
+        assert!(m.is_drop());
+        assert!(!game.is_capture(&m));
+        game.next(m).unwrap();
+        assert!(game.pocket(Color::White).is_empty());
+        assert_eq!(
+            game.piece_at(Square::at(4, 3))
+                .map(|p| (p.piece_type, p.is_white())),
+            Some((PieceType::Pawn, true))
+        );
+    }
+
+    #[test]
+    fn crazyhouse_promoted_pieces_return_as_pawns() {
+        let mut game = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        game.load_fen("4k3/1P6/8/8/8/8/r7/4K3[] w - - 0 1").unwrap();
+        let m = move_from_uci(&game, "b7b8q", false).unwrap();
+        game.next(m).unwrap();
+        assert!(game.is_promoted(Square::at(1, 7)));
+        assert_eq!(fen::to_fen(&game), "1Q~2k3/8/8/8/8/8/r7/4K3[] b - - 0 1");
+
+        // The promoted queen moves on and is still a pawn in disguise
+        let mut game = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        game.load_fen("1Q~6/4k3/8/8/8/8/r7/4K3[] w - - 0 1")
+            .unwrap();
+        let m = move_from_uci(&game, "b8b2", false).unwrap();
+        game.next(m).unwrap();
+        let m = move_from_uci(&game, "a2b2", false).unwrap();
+        game.next(m).unwrap();
+        assert_eq!(game.pocket(Color::Black), &[PieceType::Pawn]);
+        assert!(!game.is_promoted(Square::at(1, 1)));
+    }
+
+    #[test]
+    fn uci_crazyhouse_drop() {
+        let input = "setoption name UCI_Variant value crazyhouse\nposition fen 4k3/8/8/8/8/8/8/4K3[Q] w - - 0 1 moves Q@e7\nisready\nquit\n";
+        let output = Uci::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("illegal"), "{}", output);
+    }
+
+    fn san_of(fen: &str, uci: &str) -> String {
+        let game = fen::from_fen(fen).unwrap();
+        let m = move_from_uci(&game, uci, false).unwrap();
+        move_to_san(&game, &m)
+    }
+
+    #[test]
+    fn san_notation() {
+        assert_eq!(san_of(fen::STARTPOS, "e2e4"), "e4");
+        assert_eq!(san_of(fen::STARTPOS, "g1f3"), "Nf3");
+        assert_eq!(san_of("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5"), "exd5");
+        assert_eq!(san_of("k7/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san_of("7k/8/8/R7/8/8/8/R3K3 w - - 0 1", "a5a3"), "R5a3");
+        assert_eq!(san_of("8/7k/8/8/8/Q1Q5/8/Q3K3 w - - 0 1", "a3b2"), "Qa3b2");
+        assert_eq!(san_of("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), "a8=Q+");
+        assert_eq!(
+            san_of("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"),
+            "O-O"
+        );
+        assert_eq!(
+            san_of(
+                "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2",
+                "d8h4"
+            ),
+            "Qh4#"
+        );
+
+        let game = fen::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let m = move_from_san(&game, "0-0-0").unwrap();
+        assert_eq!(move_to_uci(&game, &m, false), "e1c1");
+        assert!(move_from_san(&game, "Rd1!?").is_some());
+        assert!(move_from_san(&game, "Rd8").is_none());
+
+        // More disambiguation than needed and long algebraic notation
+        let game = fen::from_fen(fen::STARTPOS).unwrap();
+        let nf3 = move_from_san(&game, "Nf3");
+        assert!(nf3.is_some());
+        for san in ["Ngf3", "N1f3", "Ng1f3", "Ng1-f3"].iter() {
+            assert_eq!(move_from_san(&game, san), nf3, "{}", san);
+        }
+        assert_eq!(move_from_san(&game, "e2e4"), move_from_san(&game, "e4"));
+        assert_eq!(move_from_san(&game, "e2-e4"), move_from_san(&game, "e4"));
+        assert!(move_from_san(&game, "Nhf3").is_none());
+        assert!(move_from_san(&game, "Nxf3").is_none());
+        let game = fen::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let exd5 = move_from_san(&game, "exd5");
+        assert!(exd5.is_some());
+        assert_eq!(move_from_san(&game, "e4xd5"), exd5);
+        assert_eq!(move_from_san(&game, "e4d5"), exd5);
+        // Rooks on both a files still need telling apart
+        let game = fen::from_fen("k7/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+        assert!(move_from_san(&game, "Rd1").is_none());
+        assert!(move_from_san(&game, "Ra1d1").is_some());
+        let game = fen::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(move_from_san(&game, "a8Q"), move_from_san(&game, "a8=Q"));
+        assert_eq!(
+            move_from_san(&game, "a7a8=N").unwrap().piece,
+            PieceType::Knight
+        );
+        assert!(move_from_san(&game, "a8").is_none());
+    }
+
+    fn play_san(bughouse: &mut BughouseMatch, board: usize, san: &str) {
+        let m = move_from_san(bughouse.board(board), san).unwrap();
+        bughouse.play(board, m, Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn bughouse_passes_captures_to_the_partner() {
+        let mut bughouse = BughouseMatch::new(Duration::from_secs(180), Duration::from_secs(0));
+        play_san(&mut bughouse, 0, "e4");
+        play_san(&mut bughouse, 0, "d5");
+        play_san(&mut bughouse, 0, "exd5");
+        // The capturer keeps nothing, its partner plays black on board B
+        assert!(bughouse.board(0).pocket(Color::White).is_empty());
+        assert_eq!(bughouse.board(1).pocket(Color::Black), &[PieceType::Pawn]);
+
+        play_san(&mut bughouse, 1, "e4");
+        play_san(&mut bughouse, 1, "P@d5");
+        assert!(bughouse.board(1).pocket(Color::Black).is_empty());
+        assert_eq!(bughouse.clock(0).remaining(true), Duration::from_secs(178));
+        assert_eq!(bughouse.end(), None);
+        assert_eq!(
+            bughouse.to_bpgn(),
+            "[Event \"Bughouse\"]\n[TimeControl \"180+0\"]\n[Result \"*\"]\n\n\
+             1A. e4 {179.0} 1a. d5 {179.0} 2A. exd5 {178.0} 1B. e4 {179.0} 1b. P@d5 {179.0} *\n"
+        );
+    }
+
+    #[test]
+    fn bughouse_ends_with_either_board() {
+        let mut bughouse = BughouseMatch::new(Duration::from_secs(60), Duration::from_secs(2));
+        for san in ["f3", "e5", "g4", "Qh4#"].iter() {
+            play_san(&mut bughouse, 1, san);
+        }
+        let end = bughouse.end().unwrap();
+        assert_eq!((end.board, end.outcome), (1, Outcome::BlackWins));
+        // Black on board B plays with white on board A in team one, which
+        // the result names
+        assert_eq!(end.winning_team(), Some(0));
+        assert_eq!(end.describe(), "team 1 (A and b) wins on board B");
+        let bpgn = bughouse.to_bpgn();
+        assert!(bpgn.contains("[Result \"1-0\"]"));
+        assert!(bpgn.ends_with("2b. Qh4# {62.0} {team 1 (A and b) wins on board B} 1-0\n"));
+        let m = move_from_san(bughouse.board(0), "e4").unwrap();
+        assert!(bughouse.play(0, m, Duration::from_secs(1)).is_err());
+
+        let mut bughouse = BughouseMatch::new(Duration::from_secs(60), Duration::from_secs(0));
+        assert!(!bughouse.check_flag(0, Duration::from_secs(59)));
+        let m = move_from_san(bughouse.board(0), "e4").unwrap();
+        assert!(bughouse.play(0, m, Duration::from_secs(61)).is_err());
+        let end = bughouse.end().unwrap();
+        assert!(end.on_time);
+        assert_eq!(end.winning_team(), Some(1));
+        assert!(bughouse
+            .to_bpgn()
+            .ends_with("{team 2 (a and B) wins on time on board A} 0-1\n"));
+    }
+
+    #[test]
+    fn huge_boards_are_sparse() {
+        assert!(!Board::new(8, 8).unwrap().is_sparse());
+        let mut board = Board::new(1_000_000, 1_000_000).unwrap();
+        assert!(board.is_sparse());
+        assert!(board.is_empty_tile(Square::at(999_999, 999_999)));
+
+        board.origin = (500_000, 500_000);
+        let square = board.from_signed(-3, 4).unwrap();
+        assert_eq!(square, Square::at(499_997, 500_004));
+        assert_eq!(board.signed(square), (-3, 4));
+        assert_eq!(board.from_signed(-500_001, 0), None);
+
+        board.set_emptiness(Square::at(500_000, 500_040), false);
+        board.set_piece_color(Square::at(500_000, 500_040), Color::White);
+        assert_eq!(
+            board.piece_color(Square::at(500_000, 500_040)),
+            Color::White
+        );
+        assert_eq!(
+            board.blocker_distance(Square::at(500_000, 500_000), (0, 2), 100),
+            Some(20)
+        );
+        assert_eq!(
+            board.blocker_distance(Square::at(500_000, 500_000), (0, 3), 100),
+            None
+        );
+        assert_eq!(
+            board.blocker_distance(Square::at(500_000, 500_000), (0, 1), 39),
+            None
+        );
+        board.set_emptiness(Square::at(500_000, 500_040), true);
+        assert!(board.is_empty_tile(Square::at(500_000, 500_040)));
+    }
+
+    #[test]
+    fn infinite_chess() {
+        let game = Game::with_variant(variant::by_name("infinite").unwrap());
+        assert!(game.board().is_sparse());
+        // Besides the 20 usual moves, rooks, bishops, queen and king can go
+        // backwards off the old board and knights jump behind their lines
+        assert_eq!(game.perft(1), 121, "\n{}", game);
+
+        let mut game = game;
+        let board = game.board().clone();
+        let square = |x: i64, y: i64| board.from_signed(x, y).unwrap();
+        let e2 = square(4, 1);
+        game.next(Move::new(e2, square(4, 3), PieceType::Pawn))
+            .unwrap();
+        let e7 = square(4, 6);
+        game.next(Move::new(e7, square(4, 4), PieceType::Pawn))
+            .unwrap();
+
+        // Pieces move at most 8 squares past the outermost pieces, here
+        // files -8 to 15 and ranks -8 to 15. The bishop leaves the old board
+        // and rides on up to that border.
+        assert_eq!(game.board().move_margin, Some(8));
+        let targets = game.moves_from(square(5, 0)).unwrap();
+        let bishop_to = |x: i64, y: i64| {
+            targets.contains(&Move::new(square(5, 0), square(x, y), PieceType::Bishop))
+        };
+        assert!(bishop_to(-1, 6));
+        assert!(bishop_to(-8, 13));
+        assert!(!bishop_to(-9, 14));
+        // Attacks follow the same rule
+        assert!(game.square_attacked(square(-8, 13), Color::White));
+        assert!(!game.square_attacked(square(-9, 14), Color::White));
+        assert!(game.attackers(square(-9, 14), Color::White).is_empty());
+
+        // The margin grows with the pieces: once the bishop stands on the
+        // border, it reaches 8 squares further
+        let bishop = Move::new(square(5, 0), square(-8, 13), PieceType::Bishop);
+        game.next(bishop).unwrap();
+        assert!(game.square_attacked(square(-16, 21), Color::White));
+        assert!(!game.square_attacked(square(-17, 22), Color::White));
+    }
+
+    #[test]
+    fn infinite_chess_formats_quickly() {
+        let game = Game::with_variant(variant::by_name("infinite").unwrap());
+        let start = Instant::now();
+        let fen = fen::to_fen(&game);
+        let ranks: Vec<&str> = fen.split(' ').next().unwrap().split('/').collect();
+        assert_eq!(ranks.len(), 1_000_000);
+        assert_eq!(ranks[0], "1000000");
+        assert_eq!(ranks[1_000_000 - 1 - 499_996], "499996RNBQKBNR499996");
+        assert_eq!(ranks[1_000_000 - 1 - 500_002], "499996pppppppp499996");
+        assert!(fen.ends_with(" w - - 0 1"));
+
+        // Debug and Display only draw the area around the pieces
+        let debug = format!("{:?}", game);
+        let pawns = debug.split_whitespace().filter(|c| *c == "P").count();
+        assert_eq!(pawns, 8, "{}", debug);
+        assert_eq!(debug.lines().count(), 8 + 2 * 8 + 1);
+        assert_eq!(game.to_string(), debug);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sparse_boards_bound_drops_and_duck_placements() {
+        // Two kings on a board without a move margin, too large to list all
+        // of its empty squares
+        let size = crate::board::MAX_SIZE;
+        let mut board = Board::new(size, size).unwrap();
+        let white = Color::White;
+        let black = Color::Black;
+        let kings = vec![
+            piece::Piece::new(&mut board, PieceType::King, Square::at(10, 10), white),
+            piece::Piece::new(&mut board, PieceType::King, Square::at(12, 10), black),
+        ];
+        let game = Game::from_parts(board, kings, true, Vec::new(), None, 0, 1);
+        // The origin in the corner and the kings span files a to m and
+        // ranks 1 to 11, drops go 8 squares further
+        let empty = 21 * 19 - 2;
+
+        let mut crazyhouse = game.clone();
+        crazyhouse.set_variant(variant::by_name("crazyhouse").unwrap());
+        crazyhouse.add_to_pocket(Color::White, PieceType::Knight);
+        let drops = crazyhouse
+            .legal_moves()
+            .iter()
+            .filter(|m| m.is_drop())
+            .count();
+        assert_eq!(drops, empty);
+
+        let mut duck = game;
+        duck.set_variant(variant::by_name("duck").unwrap());
+        play_uci(&mut duck, &["k11k10"]);
+        assert!(duck.is_placing_duck());
+        assert_eq!(duck.legal_moves().len(), empty);
+    }
+
+    fn play_uci(game: &mut Game, moves: &[&str]) {
+        for uci in moves.iter() {
+            let m = move_from_uci(game, uci, false).unwrap();
+            game.next(m).unwrap();
+        }
+    }
+
+    #[test]
+    fn views_of_hidden_information_variants() {
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        let view = game.view_for(Color::White);
+        assert_eq!(view.pieces().len(), 32);
+        assert!(view.is_visible(Square::at(4, 6)));
+
+        let game = Game::with_variant(variant::by_name("kriegspiel").unwrap());
+        let view = game.view_for(Color::White);
+        assert_eq!(view.pieces().len(), 16);
+        assert!(view.is_visible(Square::at(4, 1)));
+        assert!(!view.is_visible(Square::at(4, 2)));
+        assert!(view.piece_at(Square::at(4, 6)).is_none());
+
+        let mut game = Game::with_variant(variant::by_name("darkchess").unwrap());
+        let view = game.view_for(Color::White);
+        assert!(view.is_visible(Square::at(4, 3)));
+        assert!(view.is_visible(Square::at(7, 2)));
+        assert!(!view.is_visible(Square::at(4, 4)));
+        play_uci(&mut game, &["e2e4", "d7d5"]);
+        // The pawn on e4 sees the one it could take but not what's behind it
+        let view = game.view_for(Color::White);
+        assert!(view
+            .piece_at(Square::at(3, 4))
+            .map(|p| !p.is_white())
+            .unwrap_or(false));
+        assert!(view.is_visible(Square::at(4, 4)));
+        assert!(view.piece_at(Square::at(3, 6)).is_none());
+        assert!(game
+            .view_for(Color::Black)
+            .piece_at(Square::at(4, 3))
+            .is_some());
+    }
+
+    #[test]
+    fn dark_chess_is_won_by_capturing_the_king() {
+        let mut game = Game::with_variant(variant::by_name("darkchess").unwrap());
+        game.load_fen("4k3/8/8/8/8/8/4Q3/4K3 b - - 0 1").unwrap();
+        // Walking into the queen is allowed, there is no check
+        play_uci(&mut game, &["e8e7"]);
+        assert_eq!(game.outcome(), None);
+        play_uci(&mut game, &["e2e7"]);
+        assert_eq!(game.outcome(), Some(Outcome::WhiteWins));
+    }
+
+    #[test]
+    fn kriegspiel_umpire() {
+        let game = |fen: &str| {
+            let mut game = Game::with_variant(variant::by_name("kriegspiel").unwrap());
+            game.load_fen(fen).unwrap();
+            game
+        };
+        let attempt = |umpire: &mut Umpire, uci: &str| {
+            let m = move_from_uci(umpire.game(), uci, false)
+                .or_else(|| {
+                    let from: Square = uci[..2].parse().unwrap();
+                    let to: Square = uci[2..4].parse().unwrap();
+                    let piece = umpire.game().piece_at(from)?;
+                    Some(Move::new(from, to, piece.piece_type))
+                })
+                .unwrap();
+            umpire.attempt(m)
+        };
+
+        let mut umpire = Umpire::new(game("4k3/8/8/3p1p2/4P3/8/8/R3KB2 w - - 0 1"));
+        assert_eq!(umpire.pawn_tries(), 2);
+        assert_eq!(attempt(&mut umpire, "e4e6"), vec![Announcement::Illegal]);
+        assert!(umpire.game().is_whites_turn());
+        assert_eq!(
+            attempt(&mut umpire, "e4d5"),
+            vec![Announcement::Capture(Square::at(3, 4))]
+        );
+        assert_eq!(attempt(&mut umpire, "f5f4"), vec![]);
+        assert_eq!(
+            attempt(&mut umpire, "f1b5"),
+            vec![Announcement::Check(CheckDirection::LongDiagonal)]
+        );
+        assert_eq!(umpire.view(Color::Black).pieces().len(), 2);
+
+        let mut umpire = Umpire::new(Game::with_variant(variant::by_name("kriegspiel").unwrap()));
+        for uci in ["f2f3", "e7e5", "g2g4"].iter() {
+            assert_eq!(attempt(&mut umpire, uci), vec![]);
+        }
+        let announcements = attempt(&mut umpire, "d8h4");
+        assert_eq!(
+            announcements,
+            vec![
+                Announcement::Check(CheckDirection::ShortDiagonal),
+                Announcement::GameOver(Outcome::BlackWins)
+            ]
+        );
+        let text: Vec<String> = announcements.iter().map(|a| a.to_string()).collect();
+        assert_eq!(text, vec!["check along the short diagonal", "black wins"]);
+        assert_eq!(
+            Announcement::Capture(Square::at(3, 4)).to_string(),
+            "capture on d5"
+        );
+    }
+
+    #[test]
+    fn duck_chess_perft() {
+        // A ply is a move and one of the 32 duck placements after it
+        variant_perft("duck", None, &[640]);
+        let game = Game::with_variant(variant::by_name("duck").unwrap());
+        let divide = game.divide(1);
+        assert_eq!(divide.len(), 20);
+        assert!(divide.iter().all(|(_, nodes)| *nodes == 32));
+    }
+
+    #[test]
+    fn duck_chess_turns() {
+        let mut game = Game::with_variant(variant::by_name("duck").unwrap());
+        play_uci(&mut game, &["e2e4"]);
+        assert!(game.is_whites_turn());
+        assert!(game.is_placing_duck());
+        assert!(game.legal_moves().iter().all(|m| m.places_duck()));
+        play_uci(&mut game, &["@d5"]);
+        assert!(!game.is_whites_turn());
+        assert_eq!(game.duck().map(|d| d.square), Some(Square::at(3, 4)));
+        assert_eq!(game.halfmove_clock(), 0);
+
+        // The duck has to move somewhere else
+        play_uci(&mut game, &["d7d6"]);
+        assert!(move_from_uci(&game, "@d5", false).is_none());
+        play_uci(&mut game, &["@e5"]);
+        assert_eq!(game.fullmove_number(), 2);
+        assert_eq!(
+            fen::to_fen(&game),
+            "rnbqkbnr/ppp1pppp/3p4/4*3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"
+        );
+    }
+
+    #[test]
+    fn duck_blocks_both_sides() {
+        let mut game = Game::with_variant(variant::by_name("duck").unwrap());
+        game.load_fen("4k3/4p3/8/4*3/8/8/8/4R2K w - - 0 1").unwrap();
+        let duck = game.piece_at(Square::at(4, 4)).unwrap();
+        assert_eq!(duck.color, Color::Neutral);
+        assert!(!duck.color.is_enemy_of(Color::White));
+        // The rook can neither pass nor take the duck
+        let rook: Vec<(usize, usize)> = game
+            .moves_from(Square::at(4, 0))
+            .unwrap()
+            .iter()
+            .filter(|m| m.to.file.0 == 4)
+            .map(|m| m.to.coords())
+            .collect();
+        assert_eq!(rook, vec![(4, 1), (4, 2), (4, 3)]);
+
+        // Nor can the pawn step past it
+        game.load_fen("4k3/4p3/8/4*3/8/8/8/4R2K b - - 0 1").unwrap();
+        let pawn: Vec<(usize, usize)> = game
+            .moves_from(Square::at(4, 6))
+            .unwrap()
+            .iter()
+            .map(|m| m.to.coords())
+            .collect();
+        assert_eq!(pawn, vec![(4, 5)]);
+    }
+
+    #[test]
+    fn duck_chess_is_won_by_capturing_the_king() {
+        let mut game = Game::with_variant(variant::by_name("duck").unwrap());
+        game.load_fen("4k3/8/8/8/8/8/8/4QK2 w - - 0 1").unwrap();
+        // The king stands in the open, there is no check
+        assert!(!game.in_check());
+        play_uci(&mut game, &["e1e8"]);
+        assert_eq!(game.outcome(), Some(Outcome::WhiteWins));
+        assert!(game.no_moves());
+    }
+
+    #[test]
+    fn duck_chess_notation() {
+        let game = Game::with_variant(variant::by_name("duck").unwrap());
+        let (m, duck) = duck_move_from_san(&game, "e4@d5").unwrap();
+        assert_eq!(move_to_uci(&game, &m, false), "e2e4");
+        assert!(duck.places_duck());
+        assert_eq!(duck.to.coords(), (3, 4));
+        assert_eq!(duck_move_to_san(&game, &m, &duck), "e4@d5");
+        assert_eq!(duck_move_from_san(&game, "Nf3@f3"), None);
+    }
+
+    fn cross_game(
+        mode: multiplayer::Mode,
+        pieces: &[(u8, PieceType, usize, usize)],
+    ) -> MultiplayerGame {
+        let mut board = multiplayer::cross_board();
+        let pieces = pieces
+            .iter()
+            .map(|(player, piece_type, x, y)| {
+                piece::Piece::new(
+                    &mut board,
+                    *piece_type,
+                    Square::at(*x, *y),
+                    Color::Player(*player),
+                )
+            })
+            .collect();
+        MultiplayerGame::new(board, pieces, 4, mode)
+    }
+
+    const CROSS_KINGS: [(u8, PieceType, usize, usize); 4] = [
+        (0, PieceType::King, 7, 0),
+        (1, PieceType::King, 0, 7),
+        (2, PieceType::King, 6, 13),
+        (3, PieceType::King, 13, 6),
+    ];
+
+    fn cross_move(game: &MultiplayerGame, from: (usize, usize), to: (usize, usize)) -> Move {
+        *game
+            .legal_moves()
+            .iter()
+            .find(|m| m.from.coords() == from && m.to.coords() == to)
+            .unwrap()
+    }
+
+    #[test]
+    fn four_player_start() {
+        let board = multiplayer::cross_board();
+        let valid = (0..14)
+            .flat_map(|x| (0..14).map(move |y| (x, y)))
+            .filter(|(x, y)| board.is_valid_tile(Square::at(*x, *y)))
+            .count();
+        assert_eq!(valid, 160);
+
+        let mut game = MultiplayerGame::four_player(multiplayer::Mode::FreeForAll);
+        assert_eq!(game.pieces().count(), 64);
+        assert_eq!(game.perft(1), 20, "\n{}", game.board());
+        // Red's king faces yellow's queen
+        assert_eq!(
+            game.piece_at(Square::at(7, 0)).unwrap().piece_type,
+            PieceType::King
+        );
+        assert_eq!(
+            game.piece_at(Square::at(7, 13)).unwrap().piece_type,
+            PieceType::Queen
+        );
+
+        // Turns go round the board and every army pushes away from its edge
+        for (player, from, to) in [
+            (0, (4, 1), (4, 3)),
+            (1, (1, 4), (3, 4)),
+            (2, (9, 12), (9, 10)),
+            (3, (12, 9), (10, 9)),
+        ]
+        .iter()
+        {
+            assert_eq!(game.turn(), *player);
+            let m = cross_move(&game, *from, *to);
+            game.play(m).unwrap();
+        }
+        assert_eq!(game.turn(), 0);
+    }
+
+    #[test]
+    fn four_player_corners_block_lines() {
+        let mut pieces = CROSS_KINGS.to_vec();
+        pieces.push((0, PieceType::Bishop, 3, 0));
+        let game = cross_game(multiplayer::Mode::FreeForAll, &pieces);
+        let targets: Vec<(usize, usize)> = game
+            .legal_moves()
+            .iter()
+            .filter(|m| m.from == Square::at(3, 0))
+            .map(|m| m.to.coords())
+            .collect();
+        assert!(!targets.contains(&(0, 3)));
+        assert!(targets.contains(&(4, 1)));
+        let bishop = game.piece_at(Square::at(3, 0)).unwrap();
+        assert!(!bishop.attacks(Square::at(0, 3), game.board()));
+    }
+
+    #[test]
+    fn four_player_checkmate_knocks_out() {
+        let mut pieces = CROSS_KINGS.to_vec();
+        pieces.push((0, PieceType::Rook, 1, 3));
+        pieces.push((0, PieceType::Rook, 5, 10));
+
+        let mut game = cross_game(multiplayer::Mode::FreeForAll, &pieces);
+        let m = cross_move(&game, (5, 10), (0, 10));
+        game.play(m).unwrap();
+        assert!(game.is_eliminated(1));
+        assert_eq!(game.points(0), 20);
+        assert_eq!(game.turn(), 2);
+        assert_eq!(game.winners(), None);
+
+        // In teams the first player out decides the game
+        let mut game = cross_game(multiplayer::Mode::Teams, &pieces);
+        let m = cross_move(&game, (5, 10), (0, 10));
+        game.play(m).unwrap();
+        assert_eq!(game.winners(), Some(&[0, 2][..]));
+        assert!(game.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn four_player_captures() {
+        let mut pieces = CROSS_KINGS.to_vec();
+        pieces.push((0, PieceType::Rook, 13, 3));
+        pieces.push((0, PieceType::Rook, 7, 5));
+        pieces.push((2, PieceType::Pawn, 7, 8));
+
+        // Partners don't take each other's pieces
+        let game = cross_game(multiplayer::Mode::Teams, &pieces);
+        assert!(!game.legal_moves().iter().any(|m| m.to.coords() == (7, 8)));
+
+        let mut game = cross_game(multiplayer::Mode::FreeForAll, &pieces);
+        let m = cross_move(&game, (7, 5), (7, 8));
+        game.play(m).unwrap();
+        assert_eq!(game.points(0), 1);
+
+        // Kings left in check can be taken, which knocks their owner out
+        let mut game = cross_game(multiplayer::Mode::FreeForAll, &pieces);
+        let m = cross_move(&game, (13, 3), (13, 6));
+        game.play(m).unwrap();
+        assert!(game.is_eliminated(3));
+        assert_eq!(game.points(0), 20);
+    }
+
+    #[test]
+    fn diagrams() {
+        let mut game = fen::from_fen(fen::STARTPOS).unwrap();
+        let mut lines = format!("{}", game);
+        assert!(lines.starts_with("8  r n b q k b n r\n7  p p p p p p p p\n"));
+        assert!(lines.ends_with("1  R N B Q K B N R\n   a b c d e f g h\n"));
+
+        let e4 = Move::new(Square::at(4, 1), Square::at(4, 3), PieceType::Pawn);
+        game.next(e4).unwrap();
+        let mut style = DiagramStyle::new();
+        style.highlight_move(&e4);
+        lines = game_diagram(&game, &style);
+        assert!(lines.contains("\n4  . . . .[P]. . .\n"), "\n{}", lines);
+        assert!(lines.contains("\n2  P P P P[.]P P P\n"), "\n{}", lines);
+
+        style.flipped = true;
+        style.unicode = true;
+        style.coordinates = false;
+        lines = game_diagram(&game, &style);
+        assert!(lines.starts_with(" ♖ ♘ ♗ ♔ ♕ ♗ ♘ ♖\n"), "\n{}", lines);
+        assert!(lines.ends_with(" ♜ ♞ ♝ ♚ ♛ ♝ ♞ ♜\n"), "\n{}", lines);
+
+        style.ansi = true;
+        lines = game_diagram(&game, &style);
+        assert_eq!(lines.lines().count(), 8);
+        assert!(lines.lines().all(|l| l.contains("\x1b[0m")));
+
+        assert!(format!("{}", game.board()).contains("\n1  w w w w w w w w\n"));
+        assert!(format!("{:?}", game).starts_with(&fen::to_fen(&game)));
+
+        // Files with two letters get wider cells
+        let game = fen::from_fen("k26K/28 w - - 0 1").unwrap();
+        lines = format!("{}", game);
+        assert!(lines.starts_with("2  k  .  .  "), "\n{}", lines);
+        assert!(lines.ends_with(" z  aa ab\n"), "\n{}", lines);
+    }
+
+    #[test]
+    fn pgn_round_trip() {
+        let text = "[Event \"Casual \\\"blitz\\\"\"]\n[Result \"1-0\"]\n\n\
+                    1. e4 e5 2. Bc4 {the Italian} Nc6 (2... Nf6 3. d3) 3. Qh5 $1 Nf6??\n\
+                    4.Qxf7# 1-0\n\n1. d4 d5 *\n";
+        let games = pgn::parse(text).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(
+            games[0].moves,
+            ["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6??", "Qxf7#"]
+        );
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].moves, ["d4", "d5"]);
+
+        let positions = games[0].replay().unwrap();
+        assert_eq!(positions.len(), 8);
+        assert_eq!(positions[7].outcome(), Some(Outcome::WhiteWins));
+
+        // Writing the replayed moves gives the same game back
+        let start = positions[0].clone();
+        let mut game = start.clone();
+        let mut played = Vec::new();
+        for san in games[0].moves.iter() {
+            let m = move_from_san(&game, san).unwrap();
+            game.next(m).unwrap();
+            played.push(m);
+        }
+        let written = Pgn::new(&start, &played);
+        assert_eq!(written.result, "1-0");
+        assert!(written
+            .to_string()
+            .ends_with("\n\n1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n"));
+        assert_eq!(pgn::parse(&written.to_string()).unwrap(), vec![written]);
+
+        // Games from a FEN number their moves from it
+        let start = fen::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 40").unwrap();
+        let m = move_from_san(&start, "Kd7").unwrap();
+        let written = Pgn::new(&start, &[m]);
+        assert_eq!(written.tag("FEN"), Some("4k3/8/8/8/8/8/4P3/4K3 b - - 0 40"));
+        assert!(written.to_string().ends_with("\n\n40... Kd7 *\n"));
+
+        // Games of other variants name them, and are replayed under their
+        // rules
+        let start = Game::with_variant(variant::by_name("atomic").unwrap());
+        let mut game = start.clone();
+        let mut played = Vec::new();
+        for san in ["e4", "d5", "exd5"].iter() {
+            let m = move_from_san(&game, san).unwrap();
+            game.next(m).unwrap();
+            played.push(m);
+        }
+        let written = Pgn::new(&start, &played);
+        assert_eq!(written.tag("Variant"), Some("atomic"));
+        assert_eq!(written.tag("FEN"), None);
+        let read = pgn::parse(&written.to_string()).unwrap().remove(0);
+        assert_eq!(read.start_position().unwrap().variant().name(), "atomic");
+        let positions = read.replay().unwrap();
+        assert_eq!(fen::to_fen(&positions[3]), fen::to_fen(&game));
+        assert!(Pgn::new(&fen::from_fen(fen::STARTPOS).unwrap(), &[])
+            .tag("Variant")
+            .is_none());
+        let unknown = pgn::parse("[Variant \"shogi\"]\n\n*").unwrap();
+        assert!(unknown[0].start_position().is_err());
+
+        let bad = pgn::parse("1. e4 e5 2. Ke3 *").unwrap();
+        assert_eq!(
+            bad[0].replay().err(),
+            Some(Error::IllegalMove("Ke3 at ply 3".to_string()))
+        );
+        assert!(pgn::parse("1. e4 {open").is_err());
+    }
+
+    #[test]
+    fn repl_keeps_the_variant() {
+        let game = Game::with_variant(variant::by_name("atomic").unwrap());
+        let input = "e4\nd5\nexd5\npgn\nload 4k3/8/8/8/8/8/8/4K3 w - - 0 1\npgn\nnew\npgn\n";
+        let output = Repl::with_game(game)
+            .run(input.as_bytes(), Vec::new())
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.matches("[Variant \"atomic\"]").count(),
+            3,
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn repl_loads_fens_before_files() {
+        // A board of one rank has no slash in its FEN
+        let path = std::env::temp_dir().join("chackad_repl_load.pgn");
+        std::fs::write(&path, "1. e4 e5 *\n").unwrap();
+        let input = format!(
+            "load k6K w - - 0 1\nfen\nload {}\nfen\nload no_such_game.pgn\n",
+            path.display()
+        );
+        let output = Repl::new().run(input.as_bytes(), Vec::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\nk6K w - - 0 1\n"), "{}", output);
+        assert!(
+            output.contains("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("error: no_such_game.pgn is neither a FEN"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn repl_script() {
+        let input = "e4\ne7-e5\nf1 c4\nNc6\nQh5\nundo\nQh5\nNf6\nQf7\nhelp\nQxf7\nd4\n\
+                     pgn\nfen\nundo\nundo\nflip\nload 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\ngo 2\n";
+        let output = Repl::new().run(input.as_bytes(), Vec::new()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("8  r n b q k b n r\n"), "{}", output);
+        assert!(output.contains("\n2... Nc6\n"), "{}", output);
+        assert!(output.contains("error: illegal move 'Qf7'"), "{}", output);
+        assert!(output.contains("4. Qxf7#\nWhite wins by checkmate (1-0)\n"));
+        assert!(output.contains("error: the game is over"));
+        assert!(output.contains("\n1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n"));
+        assert!(
+            output.contains("r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4")
+        );
+        // Flipped after taking back Qxf7 and Nf6, with Qh5 highlighted
+        assert!(output.contains("\n1  R N . K[.]B N R\n"), "{}", output);
+        assert!(output.contains("1. Ra8# (mate in 1)\n"), "{}", output);
+        assert!(!output.contains("white> "));
+    }
+
+    const PACK_CORPUS: &str = "\
+[Event \"Paris\"]\n[White \"Morphy\"]\n[Black \"Duke Karl / Count Isouard\"]\n[Result \"1-0\"]\n\n\
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 4. dxe5 Bxf3 5. Qxf3 dxe5 6. Bc4 Nf6 7. Qb3 Qe7\n\
+8. Nc3 c6 9. Bg5 b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7 Rxd7\n\
+14. Rd1 Qe6 15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0\n\n\
+[Result \"1/2-1/2\"]\n\n\
+1. e4 Nf6 2. e5 d5 3. exd6 cxd6 4. Nf3 g6 5. Be2 Bg7 6. O-O O-O 1/2-1/2\n\n\
+[SetUp \"1\"]\n[FEN \"r3k2r/8/8/8/3pP3/8/8/R3K2R b KQkq e3 0 20\"]\n\n\
+20... dxe3 21. O-O-O O-O *\n\n\
+[FEN \"1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1\"]\n\n\
+1. axb8=R+ Kd7 2. Rb7+ Kc6 *\n\n\
+[Event \"Empty\"]\n\n*\n\n\
+1. Nf3 Nf6 2. Ng1 Ng8 1/2-1/2\n";
+
+    #[test]
+    fn packed_positions_and_games() {
+        let start = fen::from_fen(fen::STARTPOS).unwrap();
+        let packed = pack::pack_position(&start).unwrap();
+        assert_eq!(packed.len(), 26);
+        assert_eq!(
+            fen::to_fen(&pack::unpack_position(&packed).unwrap()),
+            fen::STARTPOS
+        );
+        assert!(pack::unpack_position(&packed[..20]).is_err());
+        assert!(pack::unpack_position(&[packed.clone(), vec![0]].concat()).is_err());
+        let crazyhouse = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        assert!(pack::pack_position(&crazyhouse).is_err());
+
+        // Every position and move of the corpus packs and unpacks to itself
+        let games = pgn::parse(PACK_CORPUS).unwrap();
+        let mut expected = Vec::new();
+        for game in games.iter() {
+            let positions = game.replay().unwrap();
+            let mut moves = Vec::new();
+            for (position, san) in positions.iter().zip(game.moves.iter()) {
+                let packed = pack::pack_position(position).unwrap();
+                assert!(
+                    packed.len() <= 32,
+                    "{} bytes for\n{}",
+                    packed.len(),
+                    position
+                );
+                let unpacked = pack::unpack_position(&packed).unwrap();
+                assert_eq!(fen::to_fen(&unpacked), fen::to_fen(position));
+                assert_eq!(unpacked.legal_moves(), position.legal_moves());
+
+                let m = move_from_san(position, san).unwrap();
+                let index = pack::pack_move(position, &m).unwrap();
+                assert_eq!(pack::unpack_move(position, index), Ok(m));
+                moves.push(move_to_san(position, &m));
+            }
+            let mut canonical = game.clone();
+            canonical.moves = moves;
+            expected.push(canonical);
+        }
+
+        let mut writer = pack::GameWriter::new(Vec::new()).unwrap();
+        for game in games.iter() {
+            writer.write(game).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert!(bytes.len() * 2 < PACK_CORPUS.len());
+        let read: Vec<Pgn> = pack::GameReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, expected);
+
+        // Broken files and games are errors, not panics
+        assert!(pack::GameReader::new(&b"PGN!\x01"[..]).is_err());
+        let mut truncated = pack::GameReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(truncated.any(|game| game.is_err()));
+        assert!(truncated.next().is_none());
+        // A tag claiming to be 2^63 bytes long, in a file of 16
+        let huge = b"CHKG\x01\x01\x80\x80\x80\x80\x80\x80\x80\x80\x80\x01";
+        let mut reader = pack::GameReader::new(&huge[..]).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(reader.next().is_none());
+        // A game that stops after two of the four plies it announces
+        let mut writer = pack::GameWriter::new(Vec::new()).unwrap();
+        writer
+            .write(&pgn::parse("1. e4 e5 2. Nf3 Nc6 *").unwrap()[0])
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+        let cut = &bytes[..bytes.len() - 3];
+        let error = pack::GameReader::new(cut)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("ply 2 of 4"));
+        // Corrupted bytes give some error or some game, never a panic
+        for i in 5..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0xa5;
+            for game in pack::GameReader::new(corrupted.as_slice()).unwrap() {
+                let _ = game;
+            }
+        }
+        let mut writer = pack::GameWriter::new(Vec::new()).unwrap();
+        let illegal = pgn::parse("1. e4 e5 2. Ke3 *").unwrap();
+        assert!(writer.write(&illegal[0]).is_err());
+        assert_eq!(writer.finish().unwrap().len(), 5);
+    }
+    // Saves the game to JSON and loads it back, which has to give the same
+    // position and the same JSON again
+    #[cfg(feature = "serde")]
+    fn json_round_trip(game: &Game) -> Game {
+        let json = serde_json::to_string(game).unwrap();
+        let loaded: Game = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+        assert_eq!(loaded.legal_moves(), game.legal_moves());
+        assert_eq!(loaded.hash_key(), game.hash_key());
+        assert_eq!(loaded.variant().name(), game.variant().name());
+        assert_eq!(loaded.history(), game.history());
+        assert_eq!(loaded.clock(), game.clock());
+        loaded
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let m = Move::new(Square::at(4, 1), Square::at(4, 3), PieceType::Pawn);
+        assert_eq!(
+            serde_json::to_string(&m).unwrap(),
+            r#"{"from":[4,1],"to":[4,3],"piece":"Pawn"}"#
+        );
+        let drop = Move::new(crate::game::DROP, Square::at(3, 4), PieceType::Custom(2));
+        let json = serde_json::to_string(&drop).unwrap();
+        assert_eq!(json, r#"{"from":null,"to":[3,4],"piece":{"Custom":2}}"#);
+        assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), drop);
+
+        // Lost castling rights, an en passant square, the moves and the
+        // clock carry over
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        let mut clock = crate::time::Clock::new(Duration::from_secs(60), Duration::from_secs(2));
+        clock.spend(true, Duration::from_millis(4_500));
+        game.set_clock(Some(clock));
+        play_uci(&mut game, &["e2e4", "c7c5", "e1e2", "c5c4", "d2d4"]);
+        let mut loaded = json_round_trip(&game);
+        assert_eq!(loaded.history().len(), 5);
+        let d4 = Move::new(Square::at(3, 1), Square::at(3, 3), PieceType::Pawn);
+        assert_eq!(loaded.history()[4], d4);
+        assert_eq!(
+            loaded.clock().unwrap().remaining(true),
+            Duration::from_millis(57_500)
+        );
+        play_uci(&mut loaded, &["c4d3"]);
+        assert_eq!(loaded.halfmove_clock(), 0);
+        assert_eq!(loaded.history().len(), 6);
+
+        let mut game = Game::with_variant(variant::by_name("crazyhouse").unwrap());
+        game.load_fen("4k3/1P6/8/8/8/8/r7/4K3[Nbp] w - - 0 1")
+            .unwrap();
+        play_uci(&mut game, &["b7b8q"]);
+        let loaded = json_round_trip(&game);
+        assert!(loaded.is_promoted(Square::at(1, 7)));
+        assert_eq!(
+            loaded.pocket(Color::Black),
+            &[PieceType::Pawn, PieceType::Bishop]
+        );
+
+        let mut game = Game::with_variant(variant::by_name("duck").unwrap());
+        play_uci(&mut game, &["e2e4", "@d5", "d7d6"]);
+        assert!(json_round_trip(&game).is_placing_duck());
+
+        let mut game = Game::with_variant(variant::by_name("3check").unwrap());
+        play_uci(&mut game, &["e2e4", "f7f6", "d1h5"]);
+        assert_eq!(json_round_trip(&game).checks_given(Color::White), 1);
+
+        let mut game = Game::new();
+        game.register_piece(PieceDef::new("Amazon", 'z', 1200, "QN").unwrap())
+            .unwrap();
+        game.load_fen("4k3/8/8/8/8/8/8/Z3K3 w - - 0 1").unwrap();
+        json_round_trip(&game);
+
+        json_round_trip(&Game::with_variant(variant::by_name("infinite").unwrap()));
+
+        let mut board = Board::new(10, 10).unwrap();
+        board.remove_tile(Square::at(0, 0));
+        piece::Piece::new(&mut board, PieceType::Rook, Square::at(9, 9), Color::Black).move_to(
+            Square::at(9, 5),
+            &mut board,
+            PieceType::Rook,
+        );
+        let json = serde_json::to_string(&board).unwrap();
+        let loaded: Board = serde_json::from_str(&json).unwrap();
+        assert!(!loaded.is_valid_tile(Square::at(0, 0)));
+        assert_eq!(loaded.occupied(), vec![((9, 5), Color::Black)]);
+        assert_eq!(loaded.moved(), vec![(9, 5), (9, 9)]);
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_rejects_broken_games() {
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        let json = serde_json::to_value(&game).unwrap();
+        let load = |json: &serde_json::Value| serde_json::from_value::<Game>(json.clone());
+
+        let mut newer = json.clone();
+        newer["version"] = serde_json::json!(2);
+        let error = load(&newer).err().unwrap().to_string();
+        assert!(error.contains("schema version 2"), "{}", error);
+
+        let mut unknown = json.clone();
+        unknown["variant"] = serde_json::json!("fairy");
+        assert!(load(&unknown).is_err());
+
+        // A piece off the board, two on one square and one the board
+        // doesn't know of
+        let mut off = json.clone();
+        off["pieces"][0]["square"] = serde_json::json!([8, 0]);
+        assert!(load(&off).is_err());
+        let mut stacked = json.clone();
+        stacked["pieces"][1]["square"] = serde_json::json!([0, 0]);
+        assert!(load(&stacked).is_err());
+        let mut moved = json.clone();
+        moved["pieces"][0]["square"] = serde_json::json!([0, 4]);
+        assert!(load(&moved).is_err());
+
+        // Pieces of another colour than the board says, or of a player of
+        // a multiplayer game
+        let mut recoloured = json.clone();
+        recoloured["pieces"][0]["color"] = serde_json::json!("Black");
+        assert!(load(&recoloured).is_err());
+        let mut player = json.clone();
+        player["pieces"][0]["color"] = serde_json::json!({ "Player": 1 });
+        assert!(load(&player).is_err());
+
+        // Custom pieces the board doesn't define
+        let mut custom = json.clone();
+        custom["pockets"][0] = serde_json::json!([{ "Custom": 0 }]);
+        let error = load(&custom).err().unwrap().to_string();
+        assert!(error.contains("custom piece 0"), "{}", error);
+        let mut promotion = json.clone();
+        promotion["board"]["pawn_rules"]["promotion_pieces"] = serde_json::json!([{ "Custom": 3 }]);
+        assert!(load(&promotion).is_err());
+
+        // Drops only come from null
+        let drop =
+            r#"{"from":[18446744073709551615,18446744073709551615],"to":[0,0],"piece":"Duck"}"#;
+        assert!(serde_json::from_str::<Move>(drop).is_err());
+
+        assert!(load(&json).is_ok());
     }
 }
@@ -0,0 +1,393 @@
+use crate::board::{Board, PawnRules};
+use crate::error::Error;
+use crate::game::Move;
+use crate::piece::{Color, Piece, PieceType};
+use crate::search::piece_value;
+use crate::square::Square;
+use std::collections::HashSet;
+
+// The four player board is a 14x14 square without its 3x3 corners
+pub const CROSS_SIZE: usize = 14;
+const CROSS_CORNER: usize = 3;
+
+const BACK_RANK: [PieceType; 8] = [
+    PieceType::Rook,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::King,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Rook,
+];
+
+const CHECKMATE_POINTS: u32 = 20;
+const STALEMATE_POINTS: u32 = 20;
+
+// Whether everyone plays for themselves or the players sitting opposite
+// each other form a team
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Mode {
+    FreeForAll,
+    Teams,
+}
+
+// Points for taking a piece of a player still in the game. Taking the king
+// knocks its owner out.
+fn capture_points(board: &Board, piece_type: PieceType) -> u32 {
+    match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 3,
+        PieceType::Bishop | PieceType::Rook => 5,
+        PieceType::Queen => 9,
+        PieceType::King => 20,
+        _ => (piece_value(board, piece_type) / 100) as u32,
+    }
+}
+
+// The 14x14 cross with red, blue, yellow and green pawns moving up, right,
+// down and left
+pub fn cross_board() -> Board {
+    let mut board = Board::sized(CROSS_SIZE, CROSS_SIZE);
+    let corner = |v: usize| !(CROSS_CORNER..CROSS_SIZE - CROSS_CORNER).contains(&v);
+    for x in 0..CROSS_SIZE {
+        for y in 0..CROSS_SIZE {
+            if corner(x) && corner(y) {
+                board.remove_tile(Square::at(x, y));
+            }
+        }
+    }
+    board.player_directions = vec![(0, 1), (1, 0), (0, -1), (-1, 0)];
+    board
+}
+
+// Square on the cross of a player's file and rank, counted from the left
+// end of its own back rank
+fn cross_square(player: usize, file: usize, rank: usize) -> Square {
+    let last = CROSS_SIZE - 1;
+    let x = CROSS_CORNER + file;
+    match player {
+        0 => Square::at(x, rank),
+        1 => Square::at(rank, last - x),
+        2 => Square::at(last - x, last - rank),
+        _ => Square::at(last - rank, x),
+    }
+}
+
+// Chess for more than two players, taking turns in the order of their
+// numbers. Players without a legal move are out, checkmated or stalemated,
+// and so is a player whose king gets captured. Their pieces stay on the
+// board but give no check and are worth nothing.
+//
+// In free for all captures and knocking players out score points and the
+// game goes on until one player is left, then the highest score wins. In
+// teams the game ends with the first player out.
+#[derive(Clone)]
+pub struct MultiplayerGame {
+    board: Board,
+    pieces: HashSet<Piece>,
+    mode: Mode,
+    players: usize,
+    // Player to move and the one who moved before
+    turn: usize,
+    last_mover: usize,
+    eliminated: Vec<bool>,
+    points: Vec<u32>,
+    moves: Vec<Move>,
+    // An empty list is a draw
+    winners: Option<Vec<usize>>,
+}
+
+impl MultiplayerGame {
+    // A game on any board. The pieces belong to Color::Player sides and the
+    // board says which way their pawns move.
+    pub fn new(board: Board, pieces: Vec<Piece>, players: usize, mode: Mode) -> MultiplayerGame {
+        let mut game = MultiplayerGame {
+            board,
+            pieces: HashSet::new(),
+            mode,
+            players,
+            turn: 0,
+            last_mover: 0,
+            eliminated: vec![false; players],
+            points: vec![0; players],
+            moves: Vec::new(),
+            winners: None,
+        };
+        for piece in pieces {
+            game.insert_piece(piece);
+        }
+        game.moves = game.generate();
+        game
+    }
+
+    // Four armies on the cross shaped board. Pawns double step from their
+    // start squares and promote on the eighth rank in free for all, where
+    // they meet in the middle, and on the eleventh in teams. There is no
+    // castling or en passant.
+    pub fn four_player(mode: Mode) -> MultiplayerGame {
+        let mut board = cross_board();
+        let mut pawn_rules = PawnRules::standard(CROSS_SIZE);
+        pawn_rules.promotion_ranks = match mode {
+            Mode::FreeForAll => vec![7],
+            Mode::Teams => vec![10],
+        };
+        board.pawn_rules = pawn_rules;
+
+        let mut pieces = Vec::new();
+        for player in 0..4 {
+            let color = Color::Player(player as u8);
+            for (file, piece_type) in BACK_RANK.iter().enumerate() {
+                for (rank, piece_type) in [(0, *piece_type), (1, PieceType::Pawn)].iter() {
+                    let square = cross_square(player, file, *rank);
+                    pieces.push(Piece::new(&mut board, *piece_type, square, color));
+                }
+            }
+        }
+        MultiplayerGame::new(board, pieces, 4, mode)
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn pieces(&self) -> impl Iterator<Item = &Piece> {
+        self.pieces.iter()
+    }
+
+    pub fn piece_at(&self, square: Square) -> Option<&Piece> {
+        self.pieces.iter().find(|p| p.square == square)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    // The player to move
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+
+    pub fn is_eliminated(&self, player: usize) -> bool {
+        self.eliminated[player]
+    }
+
+    pub fn points(&self, player: usize) -> u32 {
+        self.points[player]
+    }
+
+    // Teams are the even and the odd players
+    pub fn team(&self, player: usize) -> usize {
+        player % 2
+    }
+
+    // Legal moves of the player to move, in a stable order
+    pub fn legal_moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    // The winning players once the game is over
+    pub fn winners(&self) -> Option<&[usize]> {
+        self.winners.as_deref()
+    }
+
+    pub fn king(&self, player: usize) -> Option<&Piece> {
+        self.pieces
+            .iter()
+            .find(|p| p.color == Color::Player(player as u8) && p.piece_type == PieceType::King)
+    }
+
+    // Whether a player still in the game attacks the king of the player
+    pub fn in_check(&self, player: usize) -> bool {
+        let king = match self.king(player) {
+            Some(king) => king.square,
+            None => return false,
+        };
+        self.pieces.iter().any(|p| match p.color {
+            Color::Player(other) => {
+                let other = other as usize;
+                self.is_enemy(player, other) && !self.eliminated[other] && self.attacks(p, king)
+            }
+            _ => false,
+        })
+    }
+
+    pub fn play(&mut self, m: Move) -> Result<(), Error> {
+        if self.winners.is_some() {
+            return Err(Error::GameOver);
+        }
+        if !self.moves.contains(&m) {
+            return Err(Error::IllegalMove(format!(
+                "{}{} for player {}",
+                m.from, m.to, self.turn
+            )));
+        }
+        let player = self.turn;
+        if let Some(victim) = self.move_now(&m) {
+            if let Color::Player(owner) = victim.color {
+                let owner = owner as usize;
+                if !self.eliminated[owner] {
+                    self.points[player] += capture_points(&self.board, victim.piece_type);
+                    if victim.piece_type == PieceType::King {
+                        self.eliminate(owner, false);
+                    }
+                }
+            }
+        }
+        self.last_mover = player;
+        self.next_turn();
+        Ok(())
+    }
+
+    // Number of leaf nodes of the legal move tree
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        if depth == 1 {
+            return self.moves.len() as u64;
+        }
+        self.moves
+            .iter()
+            .map(|m| {
+                let mut child = self.clone();
+                child.play(*m).unwrap();
+                child.perft(depth - 1)
+            })
+            .sum()
+    }
+
+    fn is_enemy(&self, a: usize, b: usize) -> bool {
+        a != b && (self.mode == Mode::FreeForAll || self.team(a) != self.team(b))
+    }
+
+    // Passes the turn to the next player still in the game, knocking out
+    // the ones on the way that can't move
+    fn next_turn(&mut self) {
+        loop {
+            if self.winners.is_some() {
+                self.moves.clear();
+                return;
+            }
+            self.turn = (1..=self.players)
+                .map(|i| (self.turn + i) % self.players)
+                .find(|p| !self.eliminated[*p])
+                .unwrap_or(self.turn);
+            self.moves = self.generate();
+            if !self.moves.is_empty() {
+                return;
+            }
+            let player = self.turn;
+            if self.in_check(player) {
+                self.points[self.last_mover] += CHECKMATE_POINTS;
+                self.eliminate(player, false);
+            } else {
+                if self.mode == Mode::FreeForAll {
+                    self.points[player] += STALEMATE_POINTS;
+                }
+                self.eliminate(player, true);
+            }
+        }
+    }
+
+    fn eliminate(&mut self, player: usize, stalemate: bool) {
+        self.eliminated[player] = true;
+        match self.mode {
+            // A stalemate in teams is a draw
+            Mode::Teams => {
+                let winners = if stalemate {
+                    Vec::new()
+                } else {
+                    (0..self.players)
+                        .filter(|p| self.team(*p) != self.team(player))
+                        .collect()
+                };
+                self.winners = Some(winners);
+            }
+            Mode::FreeForAll => {
+                let left = self.eliminated.iter().filter(|e| !**e).count();
+                if left <= 1 {
+                    let best = self.points.iter().max().cloned().unwrap_or(0);
+                    let winners = (0..self.players)
+                        .filter(|p| self.points[*p] == best)
+                        .collect();
+                    self.winners = Some(winners);
+                }
+            }
+        }
+    }
+
+    fn generate(&self) -> Vec<Move> {
+        let player = self.turn;
+        let color = Color::Player(player as u8);
+        let mut moves = Vec::new();
+        for piece in self.pieces.iter().filter(|p| p.color == color) {
+            let mut types = vec![piece.piece_type];
+            if piece.piece_type == PieceType::Pawn {
+                types.extend(self.board.pawn_rules.promotion_pieces.iter());
+            }
+            for to in piece.targets(&self.board) {
+                // Pieces of partners can't be taken
+                if let Some(Color::Player(other)) = self.piece_at(to).map(|p| p.color) {
+                    if !self.is_enemy(player, other as usize) {
+                        continue;
+                    }
+                }
+                for promotion in types.iter() {
+                    if !piece.check_to(to, &self.board, *promotion) {
+                        continue;
+                    }
+                    let m = Move::new(piece.square, to, *promotion);
+                    let mut after = self.clone();
+                    after.move_now(&m);
+                    if !after.in_check(player) {
+                        moves.push(m);
+                    }
+                }
+            }
+        }
+        moves.sort();
+        moves
+    }
+
+    fn attacks(&self, p: &Piece, square: Square) -> bool {
+        let (dx, dy) = (
+            square.file.0 as i64 - p.square.file.0 as i64,
+            square.rank.0 as i64 - p.square.rank.0 as i64,
+        );
+        match p.piece_type {
+            PieceType::Pawn => {
+                let (fx, fy) = self.board.forward(p.color);
+                (dx, dy) == (fx - fy, fy - fx) || (dx, dy) == (fx + fy, fy + fx)
+            }
+            PieceType::King => dx.abs().max(dy.abs()) == 1,
+            _ => (dx, dy) != (0, 0) && p.attacks(square, &self.board),
+        }
+    }
+
+    // Makes the move and returns the captured piece
+    fn move_now(&mut self, m: &Move) -> Option<Piece> {
+        let mut piece = self.remove_piece(m.from)?;
+        let victim = self.remove_piece(m.to);
+        piece.move_to(m.to, &mut self.board, m.piece);
+        self.insert_piece(piece);
+        victim
+    }
+
+    fn remove_piece(&mut self, square: Square) -> Option<Piece> {
+        let piece = self.piece_at(square)?.clone();
+        self.pieces.remove(&piece);
+        self.board.set_emptiness(square, true);
+        Some(piece)
+    }
+
+    fn insert_piece(&mut self, piece: Piece) {
+        self.board.set_emptiness(piece.square, false);
+        self.board.set_piece_color(piece.square, piece.color);
+        self.pieces.insert(piece);
+    }
+}
@@ -0,0 +1,472 @@
+use crate::error::Error;
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::pgn::Pgn;
+use crate::piece::{Color, PieceType};
+use crate::position::PositionBuilder;
+use crate::san::{move_from_san, move_to_san};
+use crate::square::{File, Square};
+use crate::uci::move_to_uci;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+// Compact binary encodings of regular chess for game databases.
+//
+// A packed position is the occupancy of the 64 squares as a little endian
+// u64, bit 8 * rank + file, then a 4-bit code for every occupied square in
+// that order, low nibble first, then the halfmove clock and the fullmove
+// number as varints. The start position takes 26 bytes. The codes are
+//
+//   0-5   white pawn, knight, bishop, rook, queen, king
+//   6-11  the same for black
+//   12    a pawn that just made a double step, which sets en passant
+//   13    a white rook that can still castle
+//   14    a black rook that can still castle
+//   15    the black king with black to move
+//
+// A move is its index in the sorted list of legal moves, one byte since no
+// position of regular chess has more than 218 of them.
+//
+// A game file starts with the magic bytes and the format version, followed
+// by the games one after another:
+//
+//   number of tags, then each name and value as a varint length and UTF-8
+//   1 and a packed start position, or 0 for the regular setup
+//   number of plies and a byte for every move
+//   result: 0 for *, 1 for 1-0, 2 for 0-1 and 3 for 1/2-1/2
+//
+// The FEN tag is written without its value, which comes from the packed
+// start position when the game is read back. Tag names and values are at
+// most MAX_TEXT bytes long.
+const MAGIC: &[u8; 4] = b"CHKG";
+const VERSION: u8 = 1;
+const MAX_TEXT: u64 = 1 << 20;
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+const EN_PASSANT_PAWN: u8 = 12;
+const WHITE_CASTLING_ROOK: u8 = 13;
+const BLACK_CASTLING_ROOK: u8 = 14;
+const BLACK_KING_TO_MOVE: u8 = 15;
+
+const RESULTS: [&str; 4] = ["*", "1-0", "0-1", "1/2-1/2"];
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+// Reads a varint from the front of the bytes and moves past it
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| Error::ParseError("packed data ends too early".to_string()))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::ParseError("varint is too long".to_string()))
+}
+
+fn is_regular_chess(game: &Game) -> bool {
+    let board = game.board();
+    game.variant().name() == "chess"
+        && board.size_x == 8
+        && board.size_y == 8
+        && board.holes().is_empty()
+}
+
+// The position of a game of regular chess in about 24 to 32 bytes
+pub fn pack_position(game: &Game) -> Result<Vec<u8>, Error> {
+    if !is_regular_chess(game) {
+        return Err(Error::InvalidPosition(
+            "only positions of regular chess can be packed".to_string(),
+        ));
+    }
+    let mut occupancy = 0u64;
+    let mut codes = [0u8; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = match game.piece_at(Square::at(x, y)) {
+                Some(piece) => piece,
+                None => continue,
+            };
+            let index = PIECE_TYPES
+                .iter()
+                .position(|t| *t == piece.piece_type)
+                .filter(|_| matches!(piece.color, Color::White | Color::Black))
+                .ok_or_else(|| {
+                    Error::InvalidPosition(format!("{} can't be packed", piece.square))
+                })?;
+            let white = piece.is_white();
+            let (back_rank, fourth_rank) = if white { (0, 3) } else { (7, 4) };
+            let behind = if white { y.wrapping_sub(1) } else { y + 1 };
+            let skipped = y == fourth_rank && game.en_passant() == Some(Square::at(x, behind));
+            let castles = game
+                .castling_rights()
+                .iter()
+                .any(|r| r.color == piece.color && r.rook_file == File(x));
+            codes[y * 8 + x] = match piece.piece_type {
+                PieceType::Pawn if skipped => EN_PASSANT_PAWN,
+                PieceType::Rook if y == back_rank && castles => {
+                    if white {
+                        WHITE_CASTLING_ROOK
+                    } else {
+                        BLACK_CASTLING_ROOK
+                    }
+                }
+                PieceType::King if !white && !game.is_whites_turn() => BLACK_KING_TO_MOVE,
+                _ => index as u8 + if white { 0 } else { 6 },
+            };
+            occupancy |= 1 << (y * 8 + x);
+        }
+    }
+    if game.castling_rights().len()
+        != codes
+            .iter()
+            .filter(|c| **c == WHITE_CASTLING_ROOK || **c == BLACK_CASTLING_ROOK)
+            .count()
+    {
+        return Err(Error::InvalidPosition(
+            "castling rights without their rooks can't be packed".to_string(),
+        ));
+    }
+
+    let mut bytes = occupancy.to_le_bytes().to_vec();
+    let occupied: Vec<u8> = (0..64)
+        .filter(|i| occupancy & 1 << i != 0)
+        .map(|i| codes[i])
+        .collect();
+    for pair in occupied.chunks(2) {
+        bytes.push(pair[0] | pair.get(1).map_or(0, |high| high << 4));
+    }
+    write_varint(&mut bytes, u64::from(game.halfmove_clock()));
+    write_varint(&mut bytes, u64::from(game.fullmove_number()));
+    Ok(bytes)
+}
+
+// Reads a packed position from the front of the bytes and moves past it
+fn read_position(bytes: &mut &[u8]) -> Result<Game, Error> {
+    let too_short = || Error::ParseError("packed position ends too early".to_string());
+    if bytes.len() < 8 {
+        return Err(too_short());
+    }
+    let mut occupancy = [0; 8];
+    occupancy.copy_from_slice(&bytes[..8]);
+    let occupancy = u64::from_le_bytes(occupancy);
+    let count = occupancy.count_ones() as usize;
+    let nibbles = bytes.get(8..8 + count.div_ceil(2)).ok_or_else(too_short)?;
+    let code = |i: usize| (nibbles[i / 2] >> (4 * (i % 2))) & 0xf;
+
+    let mut builder = PositionBuilder::new();
+    let squares = (0..64).filter(|i| occupancy & 1 << i != 0);
+    for (i, index) in squares.enumerate() {
+        let (x, y) = (index % 8, index / 8);
+        let square = Square::at(x, y);
+        let (piece_type, color) = match code(i) {
+            EN_PASSANT_PAWN => {
+                // The pawn stands on the fourth rank of its side
+                let white = y == 3;
+                let skipped = if white { 2 } else { 5 };
+                builder
+                    .en_passant(Some(Square::at(x, skipped)))
+                    .side_to_move(Color::from_white(!white));
+                (PieceType::Pawn, Color::from_white(white))
+            }
+            WHITE_CASTLING_ROOK => {
+                builder.castling(Color::White, File(x));
+                (PieceType::Rook, Color::White)
+            }
+            BLACK_CASTLING_ROOK => {
+                builder.castling(Color::Black, File(x));
+                (PieceType::Rook, Color::Black)
+            }
+            BLACK_KING_TO_MOVE => {
+                builder.side_to_move(Color::Black);
+                (PieceType::King, Color::Black)
+            }
+            c => (PIECE_TYPES[c as usize % 6], Color::from_white(c < 6)),
+        };
+        builder.place(square, piece_type, color);
+    }
+    *bytes = &bytes[8 + nibbles.len()..];
+    let halfmove_clock = read_varint(bytes)?;
+    let fullmove_number = read_varint(bytes)?;
+    let clock = |value: u64| {
+        u32::try_from(value).map_err(|_| Error::ParseError("clock out of range".to_string()))
+    };
+    builder
+        .halfmove_clock(clock(halfmove_clock)?)
+        .fullmove_number(clock(fullmove_number)?);
+    builder.build()
+}
+
+// The position pack_position wrote, which has to fill all the bytes
+pub fn unpack_position(mut bytes: &[u8]) -> Result<Game, Error> {
+    let game = read_position(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(Error::ParseError(
+            "packed position is followed by other bytes".to_string(),
+        ));
+    }
+    Ok(game)
+}
+
+fn sorted_moves(game: &Game) -> Vec<Move> {
+    let mut moves = game.legal_moves();
+    moves.sort();
+    moves
+}
+
+// Index of the move among the sorted legal moves of the position
+pub fn pack_move(game: &Game, m: &Move) -> Result<u8, Error> {
+    let index = sorted_moves(game)
+        .iter()
+        .position(|legal| legal == m)
+        .ok_or_else(|| Error::IllegalMove(move_to_uci(game, m, false)))?;
+    u8::try_from(index)
+        .map_err(|_| Error::InvalidPosition("too many legal moves to pack".to_string()))
+}
+
+pub fn unpack_move(game: &Game, index: u8) -> Result<Move, Error> {
+    sorted_moves(game)
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| Error::IllegalMove(format!("number {}", index)))
+}
+
+fn invalid_data(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Writes games to a game file one at a time, so databases of any size can
+// be written without holding them in memory
+pub struct GameWriter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> GameWriter<W> {
+    pub fn new(mut output: W) -> io::Result<GameWriter<W>> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION])?;
+        Ok(GameWriter { output })
+    }
+
+    // Fails on games with illegal moves or positions that can't be packed,
+    // without writing anything of them
+    pub fn write(&mut self, game: &Pgn) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, game.tags.len() as u64);
+        for (name, value) in game.tags.iter() {
+            let value = if name == "FEN" { "" } else { value.as_str() };
+            for text in [name.as_str(), value].iter() {
+                if text.len() as u64 > MAX_TEXT {
+                    return Err(invalid_data(Error::ParseError(format!(
+                        "tag {} is longer than {} bytes",
+                        name, MAX_TEXT
+                    ))));
+                }
+                write_varint(&mut bytes, text.len() as u64);
+                bytes.extend_from_slice(text.as_bytes());
+            }
+        }
+
+        let mut position = game.start_position().map_err(invalid_data)?;
+        if fen::to_fen(&position) == fen::STARTPOS {
+            bytes.push(0);
+        } else {
+            bytes.push(1);
+            bytes.extend(pack_position(&position).map_err(invalid_data)?);
+        }
+
+        write_varint(&mut bytes, game.moves.len() as u64);
+        for (ply, san) in game.moves.iter().enumerate() {
+            let m = move_from_san(&position, san).ok_or_else(|| {
+                invalid_data(Error::IllegalMove(format!("{} at ply {}", san, ply + 1)))
+            })?;
+            bytes.push(pack_move(&position, &m).map_err(invalid_data)?);
+            position.play_legal(m);
+        }
+
+        let result = RESULTS
+            .iter()
+            .position(|r| *r == game.result)
+            .ok_or_else(|| {
+                invalid_data(Error::ParseError(format!("unknown result {}", game.result)))
+            })?;
+        bytes.push(result as u8);
+        self.output.write_all(&bytes)
+    }
+
+    // Flushes the output and hands it back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+// Reads the games of a game file one at a time. Moves come back in SAN
+// as move_to_san writes them.
+pub struct GameReader<R: Read> {
+    input: R,
+    broken: bool,
+}
+
+impl<R: Read> GameReader<R> {
+    pub fn new(mut input: R) -> io::Result<GameReader<R>> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data(Error::ParseError(
+                "not a game file".to_string(),
+            )));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data(Error::ParseError(format!(
+                "game file version {} is not supported",
+                header[4]
+            ))));
+        }
+        Ok(GameReader {
+            input,
+            broken: false,
+        })
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let first = self.byte()?;
+        self.varint_from(first)
+    }
+
+    fn varint_from(&mut self, first: u8) -> io::Result<u64> {
+        let mut bytes = vec![first];
+        while bytes[bytes.len() - 1] & 0x80 != 0 && bytes.len() <= 10 {
+            bytes.push(self.byte()?);
+        }
+        read_varint(&mut bytes.as_slice()).map_err(invalid_data)
+    }
+
+    // The length is checked before anything is allocated for it, so a broken
+    // file can't ask for more memory than it has bytes
+    fn text(&mut self) -> io::Result<String> {
+        let len = self.varint()?;
+        if len > MAX_TEXT {
+            return Err(invalid_data(Error::ParseError(format!(
+                "tag of {} bytes is longer than {}",
+                len, MAX_TEXT
+            ))));
+        }
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes)
+            .map_err(|_| invalid_data(Error::ParseError("tag is not UTF-8".to_string())))
+    }
+
+    // The packed position is as long as its occupancy and clocks say
+    fn position(&mut self) -> io::Result<Game> {
+        let mut bytes = vec![0; 8];
+        self.input.read_exact(&mut bytes)?;
+        let mut occupancy = [0; 8];
+        occupancy.copy_from_slice(&bytes);
+        let count = u64::from_le_bytes(occupancy).count_ones() as usize;
+        bytes.resize(8 + count.div_ceil(2), 0);
+        self.input.read_exact(&mut bytes[8..])?;
+        for _ in 0..2 {
+            write_varint(&mut bytes, self.varint()?);
+        }
+        unpack_position(&bytes).map_err(invalid_data)
+    }
+
+    fn game(&mut self, tag_count: u64) -> io::Result<Pgn> {
+        let mut tags = Vec::new();
+        for _ in 0..tag_count {
+            let name = self.text()?;
+            let value = self.text()?;
+            tags.push((name, value));
+        }
+        let mut position = match self.byte()? {
+            0 => fen::from_fen(fen::STARTPOS).map_err(invalid_data)?,
+            _ => self.position()?,
+        };
+        for (name, value) in tags.iter_mut() {
+            if name == "FEN" {
+                *value = fen::to_fen(&position);
+            }
+        }
+
+        let mut moves = Vec::new();
+        let plies = self.varint()?;
+        for ply in 1..=plies {
+            let index = self.byte().map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_data(Error::ParseError(format!(
+                    "the game ends at ply {} of {}",
+                    ply - 1,
+                    plies
+                ))),
+                _ => e,
+            })?;
+            let m = unpack_move(&position, index).map_err(invalid_data)?;
+            moves.push(move_to_san(&position, &m));
+            position.play_legal(m);
+        }
+        let result = RESULTS
+            .get(self.byte()? as usize)
+            .ok_or_else(|| invalid_data(Error::ParseError("unknown result".to_string())))?;
+        Ok(Pgn {
+            tags,
+            moves,
+            result: result.to_string(),
+        })
+    }
+}
+
+impl<R: Read> Iterator for GameReader<R> {
+    type Item = io::Result<Pgn>;
+
+    // Ends where the file does, between two games, or after the first
+    // error, since the next game can't be found once one is broken
+    fn next(&mut self) -> Option<io::Result<Pgn>> {
+        if self.broken {
+            return None;
+        }
+        let mut first = [0];
+        loop {
+            match self.input.read(&mut first) {
+                Ok(0) => return None,
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.broken = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let game = self
+            .varint_from(first[0])
+            .and_then(|tag_count| self.game(tag_count));
+        self.broken = game.is_err();
+        Some(game)
+    }
+}
@@ -0,0 +1,255 @@
+use crate::error::Error;
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::san::{move_from_san, move_to_san};
+use crate::variant::{self, Outcome, Variant};
+use std::fmt;
+
+const LINE_LENGTH: usize = 80;
+
+// A game in Portable Game Notation: the tag pairs, the moves in SAN and the
+// result, which is one of 1-0, 0-1, 1/2-1/2 and * for unfinished games
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Pgn {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: String,
+}
+
+impl Pgn {
+    // Records moves played from the start position. Games of other variants
+    // get the Variant tag, games that don't start from the regular setup the
+    // FEN and SetUp tags.
+    pub fn new(start: &Game, moves: &[Move]) -> Pgn {
+        let mut game = start.clone();
+        let mut sans = Vec::new();
+        for m in moves {
+            sans.push(move_to_san(&game, m));
+            game.play_legal(*m);
+        }
+        let result = result_string(game.outcome()).to_string();
+        let mut tags: Vec<(String, String)> = [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        tags.push(("Result".to_string(), result.clone()));
+        let variant = start.variant().name();
+        if variant != variant::Standard.name() {
+            tags.push(("Variant".to_string(), variant.to_string()));
+        }
+        let start_fen = fen::to_fen(start);
+        if start_fen != fen::STARTPOS {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), start_fen));
+        }
+        Pgn {
+            tags,
+            moves: sans,
+            result,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The position given by the FEN tag or else the setup of the variant
+    // named by the Variant tag, regular chess without one
+    pub fn start_position(&self) -> Result<Game, Error> {
+        let name = match self.tag("Variant") {
+            Some(name) => name,
+            None => return fen::from_fen(self.tag("FEN").unwrap_or(fen::STARTPOS)),
+        };
+        let variant = variant::variants()
+            .into_iter()
+            .find(|v| v.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::ParseError(format!("unknown variant {}", name)))?;
+        let mut game = Game::with_variant(variant);
+        if let Some(fen) = self.tag("FEN") {
+            game.load_fen(fen)?;
+        }
+        Ok(game)
+    }
+
+    // Every position of the game from the start to after the last move.
+    // Moves that are not legal where they are played are errors naming the
+    // move and its ply.
+    pub fn replay(&self) -> Result<Vec<Game>, Error> {
+        let mut game = self.start_position()?;
+        let mut positions = vec![game.clone()];
+        for (ply, san) in self.moves.iter().enumerate() {
+            let m = move_from_san(&game, san)
+                .ok_or_else(|| Error::IllegalMove(format!("{} at ply {}", san, ply + 1)))?;
+            game.play_legal(m);
+            positions.push(game.clone());
+        }
+        Ok(positions)
+    }
+}
+
+pub fn result_string(outcome: Option<Outcome>) -> &'static str {
+    match outcome {
+        Some(Outcome::WhiteWins) => "1-0",
+        Some(Outcome::BlackWins) => "0-1",
+        Some(Outcome::Draw) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+// Tags one per line, then the numbered moves wrapped at 80 columns
+impl fmt::Display for Pgn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{} \"{}\"]", name, value)?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f)?;
+        }
+
+        // Black moves first when the start position says so
+        let (mut number, mut white) = match self.start_position() {
+            Ok(game) => (game.fullmove_number(), game.is_whites_turn()),
+            Err(_) => (1, true),
+        };
+        let mut tokens = Vec::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            if white {
+                tokens.push(format!("{}.", number));
+            } else if i == 0 {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(san.clone());
+            if !white {
+                number += 1;
+            }
+            white = !white;
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+// Reads all games of a PGN file. Comments, variations, NAGs and move
+// numbers are skipped, the moves are not checked until the game is
+// replayed.
+pub fn parse(text: &str) -> Result<Vec<Pgn>, Error> {
+    let mut games = Vec::new();
+    let mut current = Pgn {
+        tags: Vec::new(),
+        moves: Vec::new(),
+        result: "*".to_string(),
+    };
+    let mut chars = text.chars().peekable();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                if !chars.any(|c| c == '}') {
+                    return Err(Error::ParseError("unterminated comment".to_string()));
+                }
+            }
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => return Err(Error::ParseError("unbalanced ')'".to_string())),
+            _ if depth > 0 => {}
+            '[' => {
+                let mut tag = String::new();
+                let mut quoted = false;
+                loop {
+                    match chars.next() {
+                        Some('\\') if quoted => tag.extend(chars.next()),
+                        Some('"') => {
+                            quoted = !quoted;
+                            tag.push('"');
+                        }
+                        Some(']') if !quoted => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(Error::ParseError("unterminated tag".to_string())),
+                    }
+                }
+                // A tag after moves starts the next game
+                if !current.moves.is_empty() {
+                    games.push(current.clone());
+                    current.tags.clear();
+                    current.moves.clear();
+                }
+                current.tags.push(parse_tag(&tag)?);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{;()[".contains(*c)) {
+                    token.push(c);
+                }
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        current.result = token;
+                        games.push(current.clone());
+                        current.tags.clear();
+                        current.moves.clear();
+                        current.result = "*".to_string();
+                    }
+                    _ if token.starts_with('$') => {}
+                    _ => {
+                        // Move numbers like 12. and 12... may be glued to the
+                        // move, castling with zeros has no dot
+                        let san =
+                            token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                        let number = &token[..token.len() - san.len()];
+                        if !number.contains('.') {
+                            current.moves.push(token.clone());
+                        } else if !san.is_empty() {
+                            current.moves.push(san.to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if depth > 0 {
+        return Err(Error::ParseError("unterminated variation".to_string()));
+    }
+    if !current.tags.is_empty() || !current.moves.is_empty() {
+        games.push(current);
+    }
+    Ok(games)
+}
+
+// The inside of a tag like Event "Casual game"
+fn parse_tag(tag: &str) -> Result<(String, String), Error> {
+    let invalid = || Error::ParseError(format!("invalid tag [{}]", tag));
+    let tag = tag.trim();
+    let space = tag.find(char::is_whitespace).ok_or_else(invalid)?;
+    let value = tag[space..].trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(invalid());
+    }
+    Ok((
+        tag[..space].to_string(),
+        value[1..value.len() - 1].to_string(),
+    ))
+}
@@ -1,7 +1,8 @@
-// TODO create better logic for seperating White/Black case for pawns (now it is almost duplicate code)
-use crate::board::Board;
+use crate::board::{steps_along, Board};
+use crate::square::Square;
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PieceType {
     Pawn,
     Rook,
@@ -9,324 +10,420 @@ pub enum PieceType {
     Bishop,
     Queen,
     King,
+    // Bishop and knight
+    Archbishop,
+    // Rook and knight
+    Chancellor,
+    // Neutral piece of Duck chess that is placed instead of moved
+    Duck,
+    // Defined at runtime, indexes the pieces registered on the board
+    Custom(u8),
+}
+
+impl PieceType {
+    // Distinct small number for every type
+    pub fn index(self) -> usize {
+        match self {
+            PieceType::Pawn => 0,
+            PieceType::Rook => 1,
+            PieceType::Knight => 2,
+            PieceType::Bishop => 3,
+            PieceType::Queen => 4,
+            PieceType::King => 5,
+            PieceType::Archbishop => 6,
+            PieceType::Chancellor => 7,
+            PieceType::Duck => 8,
+            PieceType::Custom(i) => 9 + i as usize,
+        }
+    }
+}
+
+// Side a piece belongs to. Neutral pieces like the duck of Duck chess
+// belong to nobody, block everyone and can't be captured. Games of more
+// than two players number their sides instead of using white and black.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    White,
+    Black,
+    Neutral,
+    Player(u8),
+}
+
+impl Color {
+    pub fn from_white(white: bool) -> Color {
+        if white {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    pub fn is_white(self) -> bool {
+        self == Color::White
+    }
+
+    // The other side of a two player game, other colours have none
+    pub fn opponent(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+            other => other,
+        }
+    }
+
+    // Index of white and black in lists of both sides
+    pub(crate) fn side(self) -> Option<usize> {
+        match self {
+            Color::White => Some(0),
+            Color::Black => Some(1),
+            _ => None,
+        }
+    }
+
+    // Distinct small number for every colour
+    pub fn index(self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 1,
+            Color::Neutral => 2,
+            Color::Player(i) => 3 + i as usize,
+        }
+    }
+
+    // Whether pieces of the two colours can capture each other
+    pub fn is_enemy_of(self, other: Color) -> bool {
+        self != other && self != Color::Neutral && other != Color::Neutral
+    }
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Piece {
     pub piece_type: PieceType,
-    pub pos_x: usize,
-    pub pos_y: usize,
-    pub is_white: bool,
+    pub square: Square,
+    pub color: Color,
+}
+
+// Files and ranks from one square to another
+fn distance(from: Square, to: Square) -> (i64, i64) {
+    (
+        to.file.0 as i64 - from.file.0 as i64,
+        to.rank.0 as i64 - from.rank.0 as i64,
+    )
 }
 
 impl Piece {
-    pub fn new(
-        board: &mut Board,
-        piece_type: PieceType,
-        pos_x: usize,
-        pos_y: usize,
-        is_white: bool,
-    ) -> Piece {
-        board.set_emptiness(pos_x, pos_y, false);
-        board.set_piece_is_white(pos_x, pos_y, is_white);
+    pub fn new(board: &mut Board, piece_type: PieceType, square: Square, color: Color) -> Piece {
+        board.set_emptiness(square, false);
+        board.set_piece_color(square, color);
         Piece {
-            piece_type: piece_type,
-            pos_x: pos_x,
-            pos_y: pos_y,
-            is_white: is_white,
+            piece_type,
+            square,
+            color,
         }
     }
 
-    pub fn move_to(&mut self, to_x: usize, to_y: usize, board: &mut Board, promotion: PieceType) {
-        board.set_emptiness(to_x, to_y, false);
-        board.set_emptiness(self.pos_x, self.pos_y, true);
-        board.false_orig_piece(self.pos_x, self.pos_y);
-        board.false_orig_piece(to_x, to_y);
-        self.pos_x = to_x;
-        self.pos_y = to_y;
+    pub fn is_white(&self) -> bool {
+        self.color.is_white()
+    }
+
+    pub fn move_to(&mut self, to: Square, board: &mut Board, promotion: PieceType) {
+        board.set_emptiness(to, false);
+        board.set_emptiness(self.square, true);
+        board.false_orig_piece(self.square);
+        board.false_orig_piece(to);
+        self.square = to;
         self.piece_type = promotion;
-        board.set_piece_is_white(to_x, to_y, self.is_white);
+        board.set_piece_color(to, self.color);
     }
 
-    pub fn check_to(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
-        if !board.is_valid_tile(to_x, to_y) {
+    pub fn check_to(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
+        if !board.is_valid_tile(to) {
             return false;
         }
         match &self.piece_type {
-            PieceType::Pawn => return self.check_pawn(to_x, to_y, board, promotion),
-            PieceType::Rook => return self.check_rook(to_x, to_y, board, promotion),
-            PieceType::Knight => return self.check_knight(to_x, to_y, board, promotion),
-            PieceType::Bishop => return self.check_bishop(to_x, to_y, board, promotion),
-            PieceType::Queen => return self.check_queen(to_x, to_y, board, promotion),
-            PieceType::King => return self.check_king(to_x, to_y, board, promotion),
+            PieceType::Pawn => self.check_pawn(to, board, promotion),
+            PieceType::Rook => self.check_rook(to, board, promotion),
+            PieceType::Knight => self.check_knight(to, board, promotion),
+            PieceType::Bishop => self.check_bishop(to, board, promotion),
+            PieceType::Queen => self.check_queen(to, board, promotion),
+            PieceType::King => self.check_king(to, board, promotion),
+            PieceType::Archbishop => self.check_archbishop(to, board, promotion),
+            PieceType::Chancellor => self.check_chancellor(to, board, promotion),
+            PieceType::Duck => false,
+            PieceType::Custom(_) => {
+                promotion == self.piece_type && self.check_custom(to, board, false)
+            }
         }
     }
-    fn check_pawn(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
-        if self.is_white {
-            // Regular move one step forward (including promotion)
-            if to_x == self.pos_x && to_y == self.pos_y + 1 {
-                if board.is_empty_tile(to_x, to_y) && to_y == board.size_y - 1 {
-                    if promotion == PieceType::Pawn || promotion == PieceType::King {
-                        return false;
-                    }
-                    return true;
-                } else if board.is_empty_tile(to_x, to_y) && promotion == PieceType::Pawn {
-                    return true;
-                } else {
-                    return false;
-                }
-            }
-
-            if promotion != PieceType::Pawn {
-                return false;
-            }
+    fn check_pawn(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
+        // Pawns of every side move the same way in their own direction, so
+        // work with steps forward and to the side
+        let rules = &board.pawn_rules;
+        let rank = board.rank_for(self.square, self.color);
+        let to_rank = board.rank_for(to, self.color);
+        let (fx, fy) = board.forward(self.color);
+        let (dx, dy) = distance(self.square, to);
+        let forward = dx * fx + dy * fy;
+        let sideways = (dx * fy - dy * fx).abs();
 
-            // Capture move
-            if to_y == self.pos_y + 1 && (to_x + 1 == self.pos_x || to_x == self.pos_x + 1) {
-                if !board.is_empty_tile(to_x, to_y) && !board.is_piece_white(to_x, to_y) {
-                    return true;
-                } else {
+        // Promotion is forced on the last promotion rank and optional on the
+        // ones before it
+        let promotes = rules.promotion_pieces.contains(&promotion);
+        match rules.promotion_ranks.iter().position(|r| *r == to_rank) {
+            Some(i) if i + 1 == rules.promotion_ranks.len() => {
+                if !promotes {
                     return false;
                 }
             }
-
-            // Double move
-            if to_x == self.pos_x && to_y == self.pos_y + 2 {
-                if board.is_empty_tile(to_x, to_y)
-                    && board.is_empty_tile(to_x, to_y - 1)
-                    && self.pos_y == 1
-                {
-                    return true;
-                } else {
+            Some(_) => {
+                if !promotes && promotion != PieceType::Pawn {
                     return false;
                 }
             }
-        } else {
-            // Regular move one step forward (including promotion)
-            if to_x == self.pos_x && to_y + 1 == self.pos_y {
-                if board.is_empty_tile(to_x, to_y) && to_y == 0 {
-                    if promotion == PieceType::Pawn || promotion == PieceType::King {
-                        return false;
-                    }
-                    return true;
-                } else if board.is_empty_tile(to_x, to_y) && promotion == PieceType::Pawn {
-                    return true;
-                } else {
+            None => {
+                if promotion != PieceType::Pawn {
                     return false;
                 }
             }
+        }
 
-            if promotion != PieceType::Pawn {
-                return false;
-            }
+        // Regular move one step forward
+        if sideways == 0 && forward == 1 {
+            return board.is_empty_tile(to);
+        }
 
-            // Capture move
-            if to_y + 1 == self.pos_y && (to_x + 1 == self.pos_x || to_x == self.pos_x + 1) {
-                if !board.is_empty_tile(to_x, to_y) && board.is_piece_white(to_x, to_y) {
-                    return true; //No error TODO fix with rust things
-                } else {
-                    return false;
-                }
-            }
+        // Capture move
+        if sideways == 1 && forward == 1 {
+            return !board.is_empty_tile(to) && board.piece_color(to).is_enemy_of(self.color);
+        }
 
-            // Double move
-            if to_x == self.pos_x && to_y + 2 == self.pos_y {
-                if board.is_empty_tile(to_x, to_y)
-                    && board.is_empty_tile(to_x, to_y + 1)
-                    && self.pos_y == 6
-                {
-                    return true; //No error TODO fix with rust things
-                } else {
-                    return false;
-                }
-            }
+        // Double move
+        if sideways == 0 && forward == 2 && rules.double_step_ranks.contains(&rank) {
+            return match self.square.offset((fx, fy), board) {
+                Some(between) => board.is_empty_tile(to) && board.is_empty_tile(between),
+                None => false,
+            };
         }
 
         false
     }
 
-    fn check_rook(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
+    // Whether the square is empty or holds an enemy piece
+    fn can_enter(&self, to: Square, board: &Board) -> bool {
+        board.is_empty_tile(to) || board.piece_color(to).is_enemy_of(self.color)
+    }
+
+    fn check_rook(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
         if promotion != PieceType::Rook {
             return false;
         }
-        if !board.is_empty_tile(to_x, to_y) && board.is_piece_white(to_x, to_y) == self.is_white {
+        if !self.can_enter(to, board) {
             return false;
         }
-
-        if to_x == self.pos_x && to_y != self.pos_y {
-            if self.pos_y < to_y {
-                for i in self.pos_y..to_y {
-                    if i == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(to_x, i) {
-                            return false;
-                        }
-                    }
-                }
-            } else {
-                for i in (to_y..self.pos_y).rev() {
-                    if i == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(to_x, i) {
-                            return false;
-                        }
-                    }
-                }
-            }
-            return true;
-        }
-
-        if to_y == self.pos_y && to_x != self.pos_x {
-            if self.pos_x < to_x {
-                for i in self.pos_x..to_x {
-                    if i == self.pos_x {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(to_x, i) {
-                            return false;
-                        }
-                    }
-                }
-            } else {
-                for i in (to_x..self.pos_x).rev() {
-                    if i == self.pos_x {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(to_x, i) {
-                            return false;
-                        }
-                    }
-                }
-            }
-            return true;
+        if (to.file == self.square.file) == (to.rank == self.square.rank) {
+            return false;
         }
+        self.line_is_open(to, board)
+    }
 
-        false
+    // Whether nothing stands between the piece and the square on a rank, file
+    // or diagonal
+    fn line_is_open(&self, to: Square, board: &Board) -> bool {
+        let (diff_x, diff_y) = distance(self.square, to);
+        let distance = diff_x.abs().max(diff_y.abs()) as usize;
+        board
+            .blocker_distance(
+                self.square,
+                (diff_x.signum(), diff_y.signum()),
+                distance - 1,
+            )
+            .is_none()
     }
 
-    fn check_knight(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
+    fn check_knight(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
         if promotion != PieceType::Knight {
             return false;
         }
-        if board.is_empty_tile(to_x, to_y) || board.is_piece_white(to_x, to_y) != self.is_white {
-            if (to_x as i128 - self.pos_x as i128).abs() == 2
-                && (to_y as i128 - self.pos_y as i128).abs() == 1
-            {
+        if self.can_enter(to, board) {
+            let (diff_x, diff_y) = distance(self.square, to);
+            if diff_x.abs() == 2 && diff_y.abs() == 1 {
                 return true;
             }
-            if (to_y as i128 - self.pos_y as i128).abs() == 2
-                && (to_x as i128 - self.pos_x as i128).abs() == 1
-            {
+            if diff_y.abs() == 2 && diff_x.abs() == 1 {
                 return true;
             }
         }
         false
     }
-    fn check_bishop(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
+    fn check_bishop(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
         if promotion != PieceType::Bishop {
             return false;
         }
-        if !board.is_empty_tile(to_x, to_y) && board.is_piece_white(to_x, to_y) == self.is_white {
+        if !self.can_enter(to, board) {
             return false;
         }
 
-        //if (to_x as i128 - self.pos_x as i128) != (to_y as i128 - self.pos_y as i128) {
-        //    return false;
-        //}
-        if self.pos_x != to_x && self.pos_y != to_y {
-            if self.pos_x < to_x && self.pos_y < to_y {
-                for (i, j) in (self.pos_x..to_x).zip(self.pos_y..to_y) {
-                    if i == self.pos_x && j == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(i, j) {
-                            return false;
-                        }
-                    }
-                }
-            } else if self.pos_x > to_x && self.pos_y < to_y {
-                for (i, j) in (to_x..self.pos_x).rev().zip(self.pos_y..to_y) {
-                    if i == self.pos_x && j == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(i, j) {
-                            return false;
-                        }
-                    }
-                }
-            } else if self.pos_x < to_x && self.pos_y > to_y {
-                for (i, j) in (self.pos_x..to_x).zip((to_y..self.pos_y).rev()) {
-                    if i == self.pos_x && j == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(i, j) {
-                            return false;
-                        }
-                    }
-                }
-            } else {
-                for (i, j) in (to_x..self.pos_x).rev().zip((to_y..self.pos_y).rev()) {
-                    if i == self.pos_x && j == self.pos_y {
-                        continue;
-                    } else {
-                        if !board.is_empty_tile(i, j) {
-                            return false;
-                        }
-                    }
-                }
-            }
-            return true;
+        let (diff_x, diff_y) = distance(self.square, to);
+        if diff_x == 0 || diff_x.abs() != diff_y.abs() {
+            return false;
         }
-
-        false
+        self.line_is_open(to, board)
     }
 
-    fn check_queen(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
+    fn check_queen(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
         if promotion != PieceType::Queen {
             return false;
         }
-        if self.check_bishop(to_x, to_y, board, PieceType::Bishop)
-            || self.check_rook(to_x, to_y, board, PieceType::Rook)
+        if self.check_bishop(to, board, PieceType::Bishop)
+            || self.check_rook(to, board, PieceType::Rook)
         {
             return true;
         }
         false
     }
-    fn check_king(&self, to_x: usize, to_y: usize, board: &Board, promotion: PieceType) -> bool {
-        if promotion != PieceType::King {
+    fn check_archbishop(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
+        if promotion != PieceType::Archbishop {
             return false;
         }
-        if !board.is_empty_tile(to_x, to_y) && board.is_piece_white(to_x, to_y) == self.is_white {
+        self.check_bishop(to, board, PieceType::Bishop)
+            || self.check_knight(to, board, PieceType::Knight)
+    }
+
+    fn check_chancellor(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
+        if promotion != PieceType::Chancellor {
             return false;
         }
-        if to_x == self.pos_x && to_y == self.pos_y {
+        self.check_rook(to, board, PieceType::Rook)
+            || self.check_knight(to, board, PieceType::Knight)
+    }
+
+    // Whether the piece could capture on the square if there was something to
+    // capture, used for custom pieces that move and capture differently
+    pub fn attacks(&self, to: Square, board: &Board) -> bool {
+        match self.piece_type {
+            PieceType::Custom(_) => board.is_valid_tile(to) && self.check_custom(to, board, true),
+            _ => self.check_to(to, board, self.piece_type),
+        }
+    }
+
+    fn check_custom(&self, to: Square, board: &Board, attack: bool) -> bool {
+        let def = match board.piece_def(self.piece_type) {
+            Some(def) => def,
+            None => return false,
+        };
+        let empty = board.is_empty_tile(to);
+        if !attack && !empty && !board.piece_color(to).is_enemy_of(self.color) {
             return false;
         }
-        if (to_x as i128 - self.pos_x as i128).abs() == 1
-            || (to_x as i128 - self.pos_x as i128).abs() == 0
-        {
-            if (to_y as i128 - self.pos_y as i128).abs() == 1
-                || (to_y as i128 - self.pos_y as i128).abs() == 0
-            {
-                return true;
+
+        for pattern in def.patterns.iter() {
+            let allowed = if attack || !empty {
+                pattern.mode.can_capture()
+            } else {
+                pattern.mode.can_move()
+            };
+            if !allowed {
+                continue;
+            }
+            for (dx, dy) in pattern.leaps.iter() {
+                // Black moves down the board
+                let step = board.orient(self.color, (*dx as i64, *dy as i64));
+                let steps = match steps_along(distance(self.square, to), step) {
+                    Some(steps) if pattern.range == 0 || steps <= pattern.range => steps,
+                    _ => continue,
+                };
+                if board
+                    .blocker_distance(self.square, step, steps - 1)
+                    .is_none()
+                {
+                    return true;
+                }
             }
         }
-        if to_x == self.pos_x + 2
-            && to_y == self.pos_y
-            && board.check_orig(self.pos_x, self.pos_y)
-            && board.check_orig(board.size_x - 1, to_y)
-        {
-            return true;
+        false
+    }
+
+    // Squares the piece might move to, found by following its moves from
+    // where it stands instead of trying every square of the board. Whether
+    // a move is possible is still up to check_to.
+    pub fn targets(&self, board: &Board) -> Vec<Square> {
+        const ORTHOGONAL: [(i64, i64); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+        const DIAGONAL: [(i64, i64); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+        const KNIGHT: [(i64, i64); 8] = [
+            (1, 2),
+            (2, 1),
+            (2, -1),
+            (1, -2),
+            (-1, -2),
+            (-2, -1),
+            (-2, 1),
+            (-1, 2),
+        ];
+        let from = self.square;
+        let leaps = |leaps: &[(i64, i64)]| -> Vec<Square> {
+            leaps
+                .iter()
+                .filter_map(|leap| board.offset(from, *leap))
+                .collect()
+        };
+        let rides = |steps: &[(i64, i64)]| -> Vec<Square> {
+            steps
+                .iter()
+                .flat_map(|step| board.ray(from, *step, 0))
+                .collect()
+        };
+
+        match self.piece_type {
+            PieceType::Pawn => {
+                let (fx, fy) = board.forward(self.color);
+                leaps(&[
+                    (fx, fy),
+                    (2 * fx, 2 * fy),
+                    (fx - fy, fy - fx),
+                    (fx + fy, fy + fx),
+                ])
+            }
+            PieceType::Knight => leaps(&KNIGHT),
+            PieceType::King => leaps(&[ORTHOGONAL, DIAGONAL].concat()),
+            PieceType::Rook => rides(&ORTHOGONAL),
+            PieceType::Bishop => rides(&DIAGONAL),
+            PieceType::Queen => rides(&[ORTHOGONAL, DIAGONAL].concat()),
+            PieceType::Archbishop => [rides(&DIAGONAL), leaps(&KNIGHT)].concat(),
+            PieceType::Chancellor => [rides(&ORTHOGONAL), leaps(&KNIGHT)].concat(),
+            PieceType::Duck => Vec::new(),
+            PieceType::Custom(_) => {
+                let def = match board.piece_def(self.piece_type) {
+                    Some(def) => def,
+                    None => return Vec::new(),
+                };
+                let mut squares = Vec::new();
+                for pattern in def.patterns.iter() {
+                    for (dx, dy) in pattern.leaps.iter() {
+                        let step = board.orient(self.color, (*dx as i64, *dy as i64));
+                        squares.extend(board.ray(from, step, pattern.range));
+                    }
+                }
+                squares.sort();
+                squares.dedup();
+                squares
+            }
         }
-        if to_x + 2 == self.pos_x
-            && to_y == self.pos_y
-            && board.check_orig(self.pos_x, self.pos_y)
-            && board.check_orig(0, to_y)
-        {
-            return true;
+    }
+
+    fn check_king(&self, to: Square, board: &Board, promotion: PieceType) -> bool {
+        if promotion != PieceType::King {
+            return false;
         }
-        false
+        if !self.can_enter(to, board) {
+            return false;
+        }
+        if to == self.square {
+            return false;
+        }
+        // Castling is generated by Game since it depends on the rooks
+        let (diff_x, diff_y) = distance(self.square, to);
+        diff_x.abs() <= 1 && diff_y.abs() <= 1
     }
 }
@@ -0,0 +1,241 @@
+use crate::board::Board;
+use crate::error::Error;
+use crate::game::{CastlingRight, Game};
+use crate::piece::{Color, Piece, PieceType};
+use crate::square::{File, Rank, Square};
+use std::collections::HashMap;
+
+// Sets up an arbitrary position piece by piece and checks that it could
+// come up in a game before turning it into one:
+//
+//   let mut builder = PositionBuilder::new();
+//   builder
+//       .place(Square::at(4, 0), PieceType::King, Color::White)
+//       .place(Square::at(4, 7), PieceType::King, Color::Black)
+//       .side_to_move(Color::Black);
+//   let game = builder.build()?;
+#[derive(Clone)]
+pub struct PositionBuilder {
+    board: Board,
+    pieces: HashMap<Square, (PieceType, Color)>,
+    whites_turn: bool,
+    castling: Vec<CastlingRight>,
+    en_passant: Option<Square>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+}
+
+impl PositionBuilder {
+    // An empty 8x8 board with white to move
+    pub fn new() -> PositionBuilder {
+        PositionBuilder::on(Board::sized(8, 8))
+    }
+
+    // An empty board of another size
+    pub fn with_size(size_x: usize, size_y: usize) -> Result<PositionBuilder, Error> {
+        Ok(PositionBuilder::on(Board::new(size_x, size_y)?))
+    }
+
+    // The position of a game, to check it or to change it
+    pub fn from_game(game: &Game) -> PositionBuilder {
+        let mut board = game.board().clone();
+        let mut pieces = HashMap::new();
+        for piece in game.all_pieces() {
+            board.set_emptiness(piece.square, true);
+            pieces.insert(piece.square, (piece.piece_type, piece.color));
+        }
+        let mut builder = PositionBuilder::on(board);
+        builder.pieces = pieces;
+        builder.whites_turn = game.is_whites_turn();
+        builder.castling = game.castling_rights().to_vec();
+        builder.en_passant = game.en_passant();
+        builder.halfmove_clock = game.halfmove_clock();
+        builder.fullmove_number = game.fullmove_number();
+        builder
+    }
+
+    fn on(board: Board) -> PositionBuilder {
+        PositionBuilder {
+            board,
+            pieces: HashMap::new(),
+            whites_turn: true,
+            castling: Vec::new(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    // Puts a piece on the square, replacing whatever stood there
+    pub fn place(&mut self, square: Square, piece_type: PieceType, color: Color) -> &mut Self {
+        self.pieces.insert(square, (piece_type, color));
+        self
+    }
+
+    pub fn remove(&mut self, square: Square) -> &mut Self {
+        self.pieces.remove(&square);
+        self
+    }
+
+    pub fn side_to_move(&mut self, color: Color) -> &mut Self {
+        self.whites_turn = color.is_white();
+        self
+    }
+
+    // Allows the side to castle with the rook on the file of its back rank
+    pub fn castling(&mut self, color: Color, rook: File) -> &mut Self {
+        let right = CastlingRight {
+            color,
+            rook_file: rook,
+        };
+        if !self.castling.contains(&right) {
+            self.castling.push(right);
+        }
+        self
+    }
+
+    // The square a pawn just skipped with its double step
+    pub fn en_passant(&mut self, square: Option<Square>) -> &mut Self {
+        self.en_passant = square;
+        self
+    }
+
+    pub fn halfmove_clock(&mut self, halfmove_clock: u32) -> &mut Self {
+        self.halfmove_clock = halfmove_clock;
+        self
+    }
+
+    pub fn fullmove_number(&mut self, fullmove_number: u32) -> &mut Self {
+        self.fullmove_number = fullmove_number;
+        self
+    }
+
+    // The position as a game of regular chess, or the first thing that
+    // makes it impossible
+    pub fn build(&self) -> Result<Game, Error> {
+        let invalid = |s: &str| Err(Error::InvalidPosition(s.to_string()));
+        let last_rank = self.board.size_y - 1;
+        for (square, (piece_type, color)) in self.pieces.iter() {
+            if !self.board.is_valid_tile(*square) {
+                return Err(Error::InvalidSquare(square.to_string()));
+            }
+            if let Color::Player(_) = color {
+                return invalid("only white, black and neutral pieces can be placed");
+            }
+            if *piece_type == PieceType::Pawn && (square.rank.0 == 0 || square.rank.0 == last_rank)
+            {
+                return Err(Error::InvalidPosition(format!(
+                    "pawn on the back rank at {}",
+                    square
+                )));
+            }
+        }
+        for color in [Color::White, Color::Black].iter() {
+            let kings = self
+                .pieces
+                .values()
+                .filter(|p| **p == (PieceType::King, *color))
+                .count();
+            if kings != 1 {
+                return Err(Error::InvalidPosition(format!(
+                    "{} has {} kings instead of one",
+                    if color.is_white() { "white" } else { "black" },
+                    kings
+                )));
+            }
+        }
+        if self.fullmove_number == 0 {
+            return invalid("move numbers start at 1");
+        }
+        self.check_castling()?;
+        self.check_en_passant()?;
+
+        let mut board = self.board.clone();
+        let pieces = self
+            .pieces
+            .iter()
+            .map(|(square, (piece_type, color))| {
+                Piece::new(&mut board, *piece_type, *square, *color)
+            })
+            .collect();
+        let mut castling = self.castling.clone();
+        castling.sort();
+        let game = Game::from_parts(
+            board,
+            pieces,
+            self.whites_turn,
+            castling,
+            self.en_passant,
+            self.halfmove_clock,
+            self.fullmove_number,
+        );
+
+        let color = game.side_to_move();
+        if game.king_attacked(color.opponent()) {
+            return invalid("the side not to move is in check");
+        }
+        // A move gives at most two checks, one of them discovered and so
+        // from a piece that slides
+        if let Some(king) = game.king(color) {
+            let checkers = game.attackers(king.square, color.opponent());
+            let leaper = |p: &&Piece| matches!(p.piece_type, PieceType::Pawn | PieceType::Knight);
+            if checkers.len() > 2 || (checkers.len() == 2 && checkers.iter().all(leaper)) {
+                return invalid("no move could have given these checks");
+            }
+        }
+        Ok(game)
+    }
+
+    // Castling needs the king and the rook on their start squares
+    fn check_castling(&self) -> Result<(), Error> {
+        for right in self.castling.iter() {
+            let color = right.color;
+            let rank = self.board.relative_rank(Rank(0), color);
+            let on_rank = |piece_type: PieceType| {
+                self.pieces
+                    .iter()
+                    .any(|(s, p)| s.rank.0 == rank && *p == (piece_type, color))
+            };
+            let rook = Square::new(right.rook_file, Rank(rank));
+            if !on_rank(PieceType::King)
+                || self.pieces.get(&rook) != Some(&(PieceType::Rook, color))
+            {
+                return Err(Error::InvalidPosition(format!(
+                    "castling with the rook on {} needs the king and that rook on the back rank",
+                    rook
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // The square has to be empty with the pawn of the side that just moved
+    // in front of it
+    fn check_en_passant(&self) -> Result<(), Error> {
+        let square = match self.en_passant {
+            Some(square) => square,
+            None => return Ok(()),
+        };
+        let mover = Color::from_white(!self.whites_turn);
+        let forward = self.board.forward(mover);
+        let behind = square.offset((-forward.0, -forward.1), &self.board);
+        let pawn = square.offset(forward, &self.board);
+        let valid = self.board.is_valid_tile(square)
+            && !self.pieces.contains_key(&square)
+            && behind.is_some_and(|b| !self.pieces.contains_key(&b))
+            && pawn.is_some_and(|p| self.pieces.get(&p) == Some(&(PieceType::Pawn, mover)));
+        if !valid {
+            return Err(Error::InvalidPosition(format!(
+                "no pawn can have skipped the en passant square {}",
+                square
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PositionBuilder {
+    fn default() -> PositionBuilder {
+        PositionBuilder::new()
+    }
+}
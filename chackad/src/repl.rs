@@ -0,0 +1,334 @@
+use crate::display::{game_diagram, DiagramStyle};
+use crate::error::Error;
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::pgn::{self, Pgn};
+use crate::san::{move_from_san, move_to_san};
+use crate::search::{SearchLimits, Searcher, MATE};
+use crate::uci::move_from_uci;
+use crate::variant::Outcome;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const TT_SIZE: usize = 1 << 16;
+const DEFAULT_DEPTH: u32 = 4;
+
+const HELP: &str = "\
+Moves are entered in SAN like Nf3, exd5 and O-O or as coordinates like g1f3.
+Commands:
+  board          show the position
+  moves          list the legal moves
+  undo           take back the last move
+  fen            show the FEN of the position
+  load <fen>     set up a position
+  load <file>    read the first game of a PGN file
+  save <file>    write the game as PGN
+  pgn            show the game as PGN
+  go [depth]     let the engine play a move
+  flip           turn the board around
+  new            start a new game
+  help           show this text
+  quit           leave";
+
+// Interactive play on the command line. Everything it prints goes to the
+// output it is run with, so scripts can be piped through it.
+pub struct Repl {
+    start: Game,
+    game: Game,
+    moves: Vec<Move>,
+    // The positions before every move, for undo
+    history: Vec<Game>,
+    flipped: bool,
+    searcher: Searcher,
+    // Scripts piped through it are better off without prompts
+    prompt: bool,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    // Starts from the regular setup
+    pub fn new() -> Repl {
+        Repl::with_game(fen::from_fen(fen::STARTPOS).unwrap())
+    }
+
+    pub fn with_game(game: Game) -> Repl {
+        Repl {
+            start: game.clone(),
+            game,
+            moves: Vec::new(),
+            history: Vec::new(),
+            flipped: false,
+            searcher: Searcher::new(TT_SIZE),
+            prompt: false,
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    // Asks for every line with the move number and side to move
+    pub fn show_prompt(&mut self, prompt: bool) {
+        self.prompt = prompt;
+    }
+
+    // Runs until quit or end of input and hands the output back
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<W> {
+        writeln!(output, "{}", self.diagram())?;
+        self.prompt(&mut output)?;
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line == "quit" || line == "exit" {
+                break;
+            }
+            if !line.is_empty() {
+                match self.handle(line) {
+                    Ok(text) => {
+                        if !text.is_empty() {
+                            writeln!(output, "{}", text)?;
+                        }
+                    }
+                    Err(e) => writeln!(output, "error: {}", e)?,
+                }
+            }
+            self.prompt(&mut output)?;
+        }
+        if self.prompt {
+            writeln!(output)?;
+        }
+        Ok(output)
+    }
+
+    fn prompt<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        if !self.prompt {
+            return Ok(());
+        }
+        let side = if self.game.is_whites_turn() {
+            "white"
+        } else {
+            "black"
+        };
+        write!(output, "{} {}> ", self.game.fullmove_number(), side)?;
+        output.flush()
+    }
+
+    // Carries out one line of input and returns what to print
+    fn handle(&mut self, line: &str) -> Result<String, Error> {
+        let (command, argument) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "help" | "?" => Ok(HELP.to_string()),
+            "board" => Ok(self.diagram()),
+            "moves" => {
+                let mut sans: Vec<String> = self
+                    .game
+                    .legal_moves()
+                    .iter()
+                    .map(|m| move_to_san(&self.game, m))
+                    .collect();
+                sans.sort();
+                Ok(sans.join(" "))
+            }
+            "undo" => {
+                let game = self.history.pop().ok_or_else(|| {
+                    Error::InvalidPosition("there is no move to take back".to_string())
+                })?;
+                self.game = game;
+                self.moves.pop();
+                Ok(self.diagram())
+            }
+            "fen" => Ok(fen::to_fen(&self.game)),
+            "load" => self.load(argument),
+            "save" => {
+                if argument.is_empty() {
+                    return Err(Error::ParseError("save needs a file name".to_string()));
+                }
+                fs::write(argument, self.pgn().to_string()).map_err(|e| {
+                    Error::ParseError(format!("could not write {}: {}", argument, e))
+                })?;
+                Ok(format!("saved to {}", argument))
+            }
+            "pgn" => Ok(self.pgn().to_string().trim_end().to_string()),
+            "go" => {
+                let depth =
+                    match argument {
+                        "" => DEFAULT_DEPTH,
+                        depth => depth.parse().ok().filter(|d| *d > 0).ok_or_else(|| {
+                            Error::ParseError(format!("invalid depth '{}'", depth))
+                        })?,
+                    };
+                self.go(depth)
+            }
+            "flip" => {
+                self.flipped = !self.flipped;
+                Ok(self.diagram())
+            }
+            "new" => {
+                self.reset(Game::with_variant(self.game.variant().clone()));
+                Ok(self.diagram())
+            }
+            _ => self.play_input(line),
+        }
+    }
+
+    // A move in SAN or as coordinates, which may be written e2e4, e2-e4 or
+    // e2 e4
+    fn play_input(&mut self, line: &str) -> Result<String, Error> {
+        if self.game.outcome().is_some() {
+            return Err(Error::GameOver);
+        }
+        let coordinates: String = line
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect();
+        let m = move_from_san(&self.game, line)
+            .or_else(|| move_from_uci(&self.game, &coordinates, false))
+            .ok_or_else(|| {
+                Error::IllegalMove(format!(
+                    "'{}', enter a legal move in SAN or coordinates, or help",
+                    line
+                ))
+            })?;
+        Ok(self.play(m, ""))
+    }
+
+    // Plays the move and returns it numbered, with the comment after it
+    fn play(&mut self, m: Move, comment: &str) -> String {
+        let san = move_to_san(&self.game, &m);
+        let number = if self.game.is_whites_turn() {
+            format!("{}.", self.game.fullmove_number())
+        } else {
+            format!("{}...", self.game.fullmove_number())
+        };
+        self.history.push(self.game.clone());
+        self.game.play_legal(m);
+        self.moves.push(m);
+        let mut text = format!("{} {}{}", number, san, comment);
+        if let Some(outcome) = self.game.outcome() {
+            text.push_str(&format!("\n{}", self.describe_end(outcome)));
+        }
+        text
+    }
+
+    fn go(&mut self, depth: u32) -> Result<String, Error> {
+        if self.game.outcome().is_some() {
+            return Err(Error::GameOver);
+        }
+        let result = self
+            .searcher
+            .search(&self.game, &SearchLimits::depth(depth));
+        let m = result.best_move.ok_or(Error::GameOver)?;
+        let moves_to_mate = (MATE - result.score.abs() + 1) / 2;
+        let score = if result.score > MATE - 1000 {
+            format!("mate in {}", moves_to_mate)
+        } else if result.score < 1000 - MATE {
+            format!("mated in {}", moves_to_mate)
+        } else {
+            format!("{:+.2}", result.score as f64 / 100.0)
+        };
+        Ok(self.play(m, &format!(" ({})", score)))
+    }
+
+    fn load(&mut self, argument: &str) -> Result<String, Error> {
+        if argument.is_empty() {
+            return Err(Error::ParseError(
+                "load needs a FEN or the name of a PGN file".to_string(),
+            ));
+        }
+        // Anything that isn't a FEN is taken for a file name
+        let mut game = self.game.clone();
+        let fen_error = match game.load_fen(argument) {
+            Ok(()) => {
+                self.reset(game);
+                return Ok(self.diagram());
+            }
+            Err(e) => e,
+        };
+        let text = fs::read_to_string(argument).map_err(|e| {
+            Error::ParseError(format!(
+                "{} is neither a FEN ({}) nor a file that can be read ({})",
+                argument, fen_error, e
+            ))
+        })?;
+        let pgn = pgn::parse(&text)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::ParseError(format!("no game in {}", argument)))?;
+        let start = pgn.start_position()?;
+        let mut game = start.clone();
+        let mut moves = Vec::new();
+        for san in pgn.moves.iter() {
+            let m = move_from_san(&game, san).ok_or_else(|| {
+                Error::IllegalMove(format!("{} after {} moves", san, moves.len()))
+            })?;
+            game.play_legal(m);
+            moves.push(m);
+        }
+        self.reset(start);
+        for m in moves {
+            self.play(m, "");
+        }
+        Ok(self.diagram())
+    }
+
+    // A new game from the position, keeping the settings
+    fn reset(&mut self, game: Game) {
+        self.start = game.clone();
+        self.game = game;
+        self.moves.clear();
+        self.history.clear();
+    }
+
+    fn pgn(&self) -> Pgn {
+        Pgn::new(&self.start, &self.moves)
+    }
+
+    // The board with the last move highlighted, and how the game ended
+    fn diagram(&self) -> String {
+        let mut style = DiagramStyle::new();
+        style.flipped = self.flipped;
+        if let Some(m) = self.moves.last() {
+            style.highlight_move(m);
+        }
+        let mut text = game_diagram(&self.game, &style);
+        match self.game.outcome() {
+            Some(outcome) => text.push_str(&self.describe_end(outcome)),
+            None if self.game.in_check() => text.push_str("Check"),
+            None => {
+                text.pop();
+            }
+        }
+        text
+    }
+
+    fn describe_end(&self, outcome: Outcome) -> String {
+        let reason = if !self.game.no_moves() {
+            ""
+        } else if self.game.in_check() {
+            " by checkmate"
+        } else if outcome == Outcome::Draw {
+            " by stalemate"
+        } else {
+            ""
+        };
+        let winner = match outcome {
+            Outcome::WhiteWins => "White wins",
+            Outcome::BlackWins => "Black wins",
+            Outcome::Draw => "Draw",
+        };
+        format!(
+            "{}{} ({})",
+            winner,
+            reason,
+            pgn::result_string(Some(outcome))
+        )
+    }
+}
@@ -0,0 +1,218 @@
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::piece::PieceType;
+use crate::square::{File, Rank, Square};
+
+// Standard algebraic notation of a legal move, like Nbd7, exd5, e8=Q+,
+// O-O, N@f7 for a drop or @d5 for placing the duck
+pub fn move_to_san(game: &Game, m: &Move) -> String {
+    let mut san = move_body(game, m);
+    let mut after = game.clone();
+    after.play_legal(*m);
+    if after.in_check() {
+        san.push(if after.no_moves() { '#' } else { '+' });
+    }
+    san
+}
+
+// A move of Duck chess followed by the placement of the duck, like e4@d5
+pub fn duck_move_to_san(game: &Game, m: &Move, duck: &Move) -> String {
+    let mut after = game.clone();
+    after.play_legal(*m);
+    move_to_san(game, m) + &move_to_san(&after, duck)
+}
+
+// Splits a Duck chess move like e4@d5 into the move and the placement
+pub fn duck_move_from_san(game: &Game, s: &str) -> Option<(Move, Move)> {
+    let i = s.rfind('@')?;
+    let m = move_from_san(game, &s[..i])?;
+    let mut after = game.clone();
+    after.play_legal(m);
+    let duck = move_from_san(&after, &s[i..])?;
+    Some((m, duck))
+}
+
+// Finds the legal move written in SAN. The notation is taken apart into
+// the piece, the squares and the promotion, so check marks, annotations
+// like !?, castling written with zeros, more disambiguation than needed
+// (Ngf3) and long algebraic notation (e2e4, Ng1-f3) are accepted too.
+pub fn move_from_san(game: &Game, s: &str) -> Option<Move> {
+    let s = s.trim().trim_end_matches(|c| "+#!?".contains(c));
+    let board = game.board();
+    let letter = |c: char| {
+        if c.is_ascii_uppercase() {
+            fen::piece_type_from_char(board, c)
+        } else {
+            None
+        }
+    };
+    let moves = game.legal_moves();
+
+    // Only castling starts with a zero, ranks like 10 have them too
+    if s.starts_with(&['O', '0'][..]) {
+        let queenside = match s.replace('0', "O").as_str() {
+            "O-O" => false,
+            "O-O-O" => true,
+            _ => return None,
+        };
+        return moves
+            .into_iter()
+            .find(|m| game.is_castling(m) && (m.to.file < m.from.file) == queenside);
+    }
+
+    // Drops name the piece before the @, the duck has nothing there
+    if let Some(i) = s.find('@') {
+        let to: Square = s[i + 1..].parse().ok()?;
+        let mut chars = s[..i].chars();
+        let piece_type = match (chars.next(), chars.next()) {
+            (None, _) => PieceType::Duck,
+            (Some(c), None) => letter(c)?,
+            _ => return None,
+        };
+        return moves
+            .into_iter()
+            .find(|m| m.is_drop() && m.to == to && m.piece == piece_type);
+    }
+
+    let (body, promotion) = match s.find('=') {
+        Some(i) => {
+            let mut chars = s[i + 1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => (&s[..i], Some(letter(c)?)),
+                _ => return None,
+            }
+        }
+        None => match s.chars().last() {
+            Some(c) if c.is_ascii_uppercase() => (&s[..s.len() - 1], Some(letter(c)?)),
+            _ => (s, None),
+        },
+    };
+    let (piece_type, body) = match body.chars().next() {
+        Some(c) if c.is_ascii_uppercase() => (letter(c)?, &body[1..]),
+        _ => (PieceType::Pawn, body),
+    };
+    let body = body.replace('-', "");
+
+    // The destination is the rank at the end and some of the letters before
+    // it. Files of wide boards have several letters and x is a file too, so
+    // every way of reading the rest is tried.
+    let rank_at = body.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let to_rank: Rank = body[rank_at..].parse().ok()?;
+    let letters_at = body[..rank_at]
+        .trim_end_matches(|c: char| c.is_ascii_lowercase())
+        .len();
+    let mut found: Vec<Move> = Vec::new();
+    for file_at in letters_at..rank_at {
+        let to_file: File = match body[file_at..rank_at].parse() {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let prefix = &body[..file_at];
+        let mut readings = vec![(prefix, false)];
+        if let Some(prefix) = prefix.strip_suffix('x') {
+            readings.push((prefix, true));
+        }
+        for (prefix, capture) in readings {
+            let (from_file, from_rank) = match disambiguation(prefix) {
+                Some(parts) => parts,
+                None => continue,
+            };
+            // Long algebraic notation may leave out the x of captures
+            let long = from_file.is_some() && from_rank.is_some();
+            found.extend(moves.iter().filter(|m| {
+                !m.is_drop()
+                    && !game.is_castling(m)
+                    && m.to == Square::new(to_file, to_rank)
+                    && m.piece == promotion.unwrap_or(piece_type)
+                    && from_file.is_none_or(|f| f == m.from.file)
+                    && from_rank.is_none_or(|r| r == m.from.rank)
+                    && (capture == game.is_capture(m) || long && !capture)
+                    && game.piece_at(m.from).map(|p| p.piece_type) == Some(piece_type)
+            }));
+        }
+    }
+    found.sort();
+    found.dedup();
+    match found.as_slice() {
+        [m] => Some(*m),
+        _ => None,
+    }
+}
+
+// The file, the rank, both or none of them in front of the destination
+fn disambiguation(s: &str) -> Option<(Option<File>, Option<Rank>)> {
+    let split = s.find(|c: char| !c.is_ascii_lowercase()).unwrap_or(s.len());
+    let file = match &s[..split] {
+        "" => None,
+        file => Some(file.parse().ok()?),
+    };
+    let rank = match &s[split..] {
+        "" => None,
+        rank => Some(rank.parse().ok()?),
+    };
+    Some((file, rank))
+}
+
+// SAN without the check mark
+fn move_body(game: &Game, m: &Move) -> String {
+    let board = game.board();
+    let letter =
+        |piece_type: PieceType| fen::piece_type_char(board, piece_type).to_ascii_uppercase();
+
+    if m.places_duck() {
+        return format!("@{}", m.to);
+    }
+    if m.is_drop() {
+        return format!("{}@{}", letter(m.piece), m.to);
+    }
+    if game.is_castling(m) {
+        return if m.to.file > m.from.file {
+            "O-O"
+        } else {
+            "O-O-O"
+        }
+        .to_string();
+    }
+    let piece_type = match game.piece_at(m.from) {
+        Some(piece) => piece.piece_type,
+        None => return String::new(),
+    };
+
+    let mut san = String::new();
+    let capture = game.is_capture(m);
+    if piece_type == PieceType::Pawn {
+        if capture {
+            san.push_str(&m.from.file.to_string());
+        }
+    } else {
+        san.push(letter(piece_type));
+        // Name the file, the rank or both when another piece of the same
+        // type can go to the same square
+        let others: Vec<Square> = game
+            .legal_moves()
+            .iter()
+            .filter(|o| o.from != m.from && !o.is_drop() && o.to == m.to)
+            .filter(|o| !game.is_castling(o))
+            .filter(|o| game.piece_at(o.from).map(|p| p.piece_type) == Some(piece_type))
+            .map(|o| o.from)
+            .collect();
+        if !others.is_empty() {
+            if others.iter().all(|o| o.file != m.from.file) {
+                san.push_str(&m.from.file.to_string());
+            } else if others.iter().all(|o| o.rank != m.from.rank) {
+                san.push_str(&m.from.rank.to_string());
+            } else {
+                san.push_str(&m.from.to_string());
+            }
+        }
+    }
+    if capture {
+        san.push('x');
+    }
+    san.push_str(&m.to.to_string());
+    if piece_type != m.piece {
+        san.push('=');
+        san.push(letter(m.piece));
+    }
+    san
+}
@@ -0,0 +1,695 @@
+use crate::board::Board;
+use crate::game::{Game, Move};
+use crate::piece::{Color, PieceType};
+use crate::skill::Skill;
+use crate::time::TimeManager;
+use crate::tt::{Bound, TranspositionTable, TtEntry};
+use crate::variant::Outcome;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const MATE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+pub const MAX_DEPTH: u32 = 64;
+
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub depth: u32,
+    // Number of search threads. With a single thread and a fresh Searcher
+    // the search is fully deterministic.
+    pub threads: usize,
+    // Number of best lines to report
+    pub multi_pv: usize,
+    // Keep deepening until stopped, ignoring depth
+    pub infinite: bool,
+    // Clock based limits for this move
+    pub time: Option<TimeManager>,
+    // Stop after roughly this many nodes
+    pub nodes: Option<u64>,
+    // Strength limit and the seed for its random move choice
+    pub skill: Option<Skill>,
+    pub seed: u64,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits {
+            depth,
+            threads: 1,
+            multi_pv: 1,
+            infinite: false,
+            time: None,
+            nodes: None,
+            skill: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    pub pv: Vec<Move>,
+    // Best lines ranked by score, the first one is the same as pv
+    pub lines: Vec<PvLine>,
+    pub nodes: u64,
+}
+
+// Progress report sent after every completed line of every iteration
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub multi_pv: usize,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+}
+
+pub trait SearchObserver: Send {
+    fn progress(&mut self, _info: &SearchInfo) {}
+    fn finished(&mut self, _result: &SearchResult) {}
+}
+
+impl SearchObserver for () {}
+
+#[derive(Clone)]
+struct Signals {
+    stop: Arc<AtomicBool>,
+    // While pondering the search keeps deepening as if it was infinite
+    ponder: Arc<AtomicBool>,
+    nodes: Arc<AtomicU64>,
+}
+
+impl Signals {
+    fn new(ponder: bool) -> Signals {
+        Signals {
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(ponder)),
+            nodes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+// Handle to a search running on a background thread
+pub struct SearchHandle {
+    signals: Signals,
+    thread: Option<thread::JoinHandle<SearchResult>>,
+}
+
+impl SearchHandle {
+    pub fn stop(&self) {
+        self.signals.stop.store(true, Ordering::Relaxed);
+    }
+
+    // The opponent played the expected move, continue as a normal search
+    pub fn ponderhit(&self) {
+        self.signals.ponder.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.signals.ponder.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .map(|t| t.is_finished())
+            .unwrap_or(true)
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.signals.nodes.load(Ordering::Relaxed)
+    }
+
+    pub fn wait(mut self) -> SearchResult {
+        self.thread
+            .take()
+            .expect("search thread already joined")
+            .join()
+            .expect("search thread panicked")
+    }
+}
+
+impl Drop for SearchHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop();
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Searcher {
+    tt: Arc<TranspositionTable>,
+}
+
+impl Searcher {
+    pub fn new(tt_size: usize) -> Searcher {
+        Searcher {
+            tt: Arc::new(TranspositionTable::new(tt_size)),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tt.clear();
+    }
+
+    pub fn search(&mut self, game: &Game, limits: &SearchLimits) -> SearchResult {
+        run(&self.tt, game, limits, &Signals::new(false), &mut ())
+    }
+
+    // Starts the search on its own thread. In ponder mode the search keeps
+    // going until ponderhit() or stop() is called.
+    pub fn spawn<O: SearchObserver + 'static>(
+        &self,
+        game: Game,
+        limits: SearchLimits,
+        ponder: bool,
+        mut observer: O,
+    ) -> SearchHandle {
+        let signals = Signals::new(ponder);
+        let tt = self.tt.clone();
+        let thread_signals = signals.clone();
+        let thread = thread::spawn(move || {
+            let result = run(&tt, &game, &limits, &thread_signals, &mut observer);
+            observer.finished(&result);
+            result
+        });
+        SearchHandle {
+            signals,
+            thread: Some(thread),
+        }
+    }
+}
+
+// Lazy SMP: every thread runs its own iterative deepening on a clone of
+// the game and they only cooperate through the shared transposition table.
+// Helpers skip depths in different patterns and try quiet moves in
+// different orders, so they fill the table with entries the main thread
+// will want next instead of repeating its work.
+fn run(
+    tt: &TranspositionTable,
+    game: &Game,
+    limits: &SearchLimits,
+    signals: &Signals,
+    observer: &mut dyn SearchObserver,
+) -> SearchResult {
+    let helpers_done = AtomicBool::new(false);
+    let mut limits = limits.clone();
+    if let Some(skill) = limits.skill {
+        skill.limit(&mut limits);
+    }
+    let limits = &limits;
+
+    let mut result = thread::scope(|s| {
+        for id in 1..limits.threads.max(1) {
+            let game = game.clone();
+            let helpers_done = &helpers_done;
+            s.spawn(move || {
+                let mut worker = Worker::new(tt, helpers_done, signals, None);
+                worker.can_stop = true;
+                worker.helper = id;
+                worker.iterate(&game, 1, MAX_DEPTH, 1);
+            });
+        }
+
+        let mut main = Worker::new(tt, &signals.stop, signals, Some(observer));
+        main.time = limits.time.clone();
+        main.node_limit = limits.nodes;
+        if game.legal_moves().len() == 1 {
+            if let Some(time) = main.time.as_mut() {
+                time.set_single_reply();
+            }
+        }
+        let depth = if limits.infinite {
+            MAX_DEPTH
+        } else {
+            limits.depth.clamp(1, MAX_DEPTH)
+        };
+        let result = main.iterate(game, 1, depth, limits.multi_pv.max(1));
+
+        // Never return early from an infinite or ponder search, the caller
+        // decides when it is over
+        while !signals.stop.load(Ordering::Relaxed)
+            && (limits.infinite || signals.ponder.load(Ordering::Relaxed))
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+        helpers_done.store(true, Ordering::Relaxed);
+        result
+    });
+
+    if let Some(skill) = limits.skill {
+        if let Some(line) = skill.pick(&result.lines, limits.seed).cloned() {
+            result.best_move = line.pv.first().copied();
+            result.score = line.score;
+            result.pv = line.pv;
+        }
+    }
+
+    result.nodes = signals.nodes.load(Ordering::Relaxed);
+    result
+}
+
+pub fn piece_value(board: &Board, piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::Archbishop => 825,
+        PieceType::Chancellor => 875,
+        PieceType::King | PieceType::Duck => 0,
+        PieceType::Custom(_) => board.piece_def(piece_type).map(|d| d.value).unwrap_or(0),
+    }
+}
+
+// Material balance from the point of view of the side to move, counting
+// the pieces in the pockets of drop variants too
+pub fn evaluate(game: &Game) -> i32 {
+    let pocket = |color: Color| -> i32 {
+        game.pocket(color)
+            .iter()
+            .map(|t| piece_value(game.board(), *t))
+            .sum()
+    };
+    let white: i32 = game
+        .white_pieces_iter()
+        .map(|p| piece_value(game.board(), p.piece_type))
+        .sum::<i32>()
+        + pocket(Color::White);
+    let black: i32 = game
+        .black_pieces_iter()
+        .map(|p| piece_value(game.board(), p.piece_type))
+        .sum::<i32>()
+        + pocket(Color::Black);
+    if game.is_whites_turn() {
+        white - black
+    } else {
+        black - white
+    }
+}
+
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE - 1000 {
+        score + ply as i32
+    } else if score < -MATE + 1000 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE - 1000 {
+        score - ply as i32
+    } else if score < -MATE + 1000 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// Depths helper n skips: blocks of SKIP_SIZE[n] depths, every other one,
+// shifted by SKIP_PHASE[n]. The patterns repeat after twenty helpers.
+const SKIP_SIZE: [u32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [u32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+struct Worker<'a> {
+    tt: &'a TranspositionTable,
+    stop: &'a AtomicBool,
+    signals: &'a Signals,
+    observer: Option<&'a mut dyn SearchObserver>,
+    local_nodes: u64,
+    // Only the main thread keeps time, the clock starts when pondering ends
+    time: Option<TimeManager>,
+    clock: Option<Instant>,
+    node_limit: Option<u64>,
+    // The main thread always finishes depth one so there is a move to play
+    can_stop: bool,
+    // Root moves skipped by the current MultiPV iteration
+    excluded: Vec<Move>,
+    // Number of the helper thread, 0 for the main one
+    helper: usize,
+}
+
+impl<'a> Worker<'a> {
+    fn new(
+        tt: &'a TranspositionTable,
+        stop: &'a AtomicBool,
+        signals: &'a Signals,
+        observer: Option<&'a mut dyn SearchObserver>,
+    ) -> Worker<'a> {
+        Worker {
+            tt,
+            stop,
+            signals,
+            observer,
+            local_nodes: 0,
+            time: None,
+            clock: None,
+            node_limit: None,
+            can_stop: false,
+            excluded: Vec::new(),
+            helper: 0,
+        }
+    }
+
+    fn skips(&self, depth: u32) -> bool {
+        if self.helper == 0 {
+            return false;
+        }
+        let n = (self.helper - 1) % SKIP_SIZE.len();
+        (depth + SKIP_PHASE[n]) / SKIP_SIZE[n] % 2 == 1
+    }
+
+    // Next depth to search after the given one
+    fn next_depth(&self, mut depth: u32) -> u32 {
+        depth += 1;
+        while depth < MAX_DEPTH && self.skips(depth) {
+            depth += 1;
+        }
+        depth
+    }
+
+    fn stopped(&self) -> bool {
+        self.can_stop && self.stop.load(Ordering::Relaxed)
+    }
+
+    // Elapsed time on our clock, None while pondering or without time limits
+    fn elapsed(&mut self) -> Option<Duration> {
+        if self.time.is_none() || self.signals.ponder.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(self.clock.get_or_insert_with(Instant::now).elapsed())
+    }
+
+    fn check_limits(&mut self) {
+        if let Some(limit) = self.node_limit {
+            if self.signals.nodes.load(Ordering::Relaxed) + self.local_nodes >= limit {
+                self.signals.stop.store(true, Ordering::Relaxed);
+            }
+        }
+        if let Some(elapsed) = self.elapsed() {
+            if self.time.as_ref().unwrap().out_of_time(elapsed) {
+                self.signals.stop.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush_nodes(&mut self) {
+        self.signals
+            .nodes
+            .fetch_add(self.local_nodes, Ordering::Relaxed);
+        self.local_nodes = 0;
+    }
+
+    fn iterate(
+        &mut self,
+        game: &Game,
+        from_depth: u32,
+        to_depth: u32,
+        multi_pv: usize,
+    ) -> SearchResult {
+        let mut result = SearchResult {
+            best_move: None,
+            score: 0,
+            depth: 0,
+            pv: Vec::new(),
+            lines: Vec::new(),
+            nodes: 0,
+        };
+
+        let root_moves = game.legal_moves();
+        let mut depth = if self.skips(from_depth) {
+            self.next_depth(from_depth)
+        } else {
+            from_depth
+        };
+        loop {
+            let lines = self.search_root(game, depth, multi_pv);
+            if self.stopped() {
+                break;
+            }
+            self.can_stop = true;
+            self.flush_nodes();
+
+            if let Some(best) = lines.first() {
+                result.best_move = best.pv.first().copied();
+                result.score = best.score;
+                result.pv = best.pv.clone();
+            } else {
+                result.score = self.negamax(game, depth, 0, -INFINITY, INFINITY, &mut Vec::new());
+            }
+            result.depth = depth;
+            result.lines = lines;
+
+            if let Some(observer) = self.observer.as_mut() {
+                for (i, line) in result.lines.iter().enumerate() {
+                    observer.progress(&SearchInfo {
+                        depth,
+                        multi_pv: i + 1,
+                        score: line.score,
+                        pv: line.pv.clone(),
+                        nodes: self.signals.nodes.load(Ordering::Relaxed),
+                    });
+                }
+            }
+
+            if let (Some(elapsed), Some(best)) = (self.elapsed(), result.best_move) {
+                let best = root_moves.iter().position(|m| *m == best).unwrap_or(0);
+                let time = self.time.as_mut().unwrap();
+                if !time.iteration_done(elapsed, best, result.score) {
+                    break;
+                }
+            }
+
+            let pondering = self.signals.ponder.load(Ordering::Relaxed);
+            if depth >= MAX_DEPTH || (depth >= to_depth && !pondering) {
+                break;
+            }
+            if multi_pv == 1 && result.score.abs() > MATE - 1000 && !pondering {
+                break;
+            }
+            depth = self.next_depth(depth);
+        }
+
+        self.flush_nodes();
+        result
+    }
+
+    // Searches the root once per requested line, every time excluding the
+    // moves already reported
+    fn search_root(&mut self, game: &Game, depth: u32, multi_pv: usize) -> Vec<PvLine> {
+        let mut lines: Vec<PvLine> = Vec::new();
+        self.excluded.clear();
+        while lines.len() < multi_pv {
+            let mut pv = Vec::new();
+            let score = self.negamax(game, depth, 0, -INFINITY, INFINITY, &mut pv);
+            if self.stopped() || pv.is_empty() {
+                break;
+            }
+            self.excluded.push(pv[0]);
+            lines.push(PvLine { score, pv });
+        }
+        self.excluded.clear();
+        // Keep the lines ranked even if the table changed scores in between
+        lines.sort_by_key(|line| -line.score);
+        lines
+    }
+
+    fn order_moves(&self, game: &Game, moves: &[Move], tt_move: Option<usize>) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..moves.len()).collect();
+        // Stable sort keeps the generation order for equal keys, which the
+        // deterministic single thread mode relies on. Helpers start the quiet
+        // moves at a different one each.
+        order.sort_by_key(|&i| {
+            if Some(i) == tt_move {
+                return i32::MIN;
+            }
+            let m = &moves[i];
+            if game.is_capture(m) {
+                let victim = game
+                    .piece_at(m.to)
+                    .map(|p| piece_value(game.board(), p.piece_type))
+                    .unwrap_or(0);
+                -victim - 1
+            } else {
+                ((i + moves.len() - self.helper % moves.len()) % moves.len()) as i32
+            }
+        });
+        order
+    }
+
+    // Score of the child position from the point of view of the side to move
+    // in the parent. The turn doesn't pass while the duck is placed in Duck
+    // chess, so the child's score is only negated when it did.
+    fn child_score(
+        &mut self,
+        game: &Game,
+        child: &Game,
+        depth: u32,
+        ply: usize,
+        (alpha, beta): (i32, i32),
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        if child.is_whites_turn() == game.is_whites_turn() {
+            self.negamax(child, depth, ply, alpha, beta, pv)
+        } else {
+            -self.negamax(child, depth, ply, -beta, -alpha, pv)
+        }
+    }
+
+    fn negamax(
+        &mut self,
+        game: &Game,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        self.local_nodes += 1;
+        if self.local_nodes.is_multiple_of(64) {
+            self.check_limits();
+        }
+        if self.stopped() {
+            return 0;
+        }
+
+        let moves = game.legal_moves();
+        if let Some(outcome) = game.outcome() {
+            return match outcome {
+                Outcome::Draw => 0,
+                _ if outcome == Outcome::win_for(game.side_to_move()) => MATE - ply as i32,
+                _ => -MATE + ply as i32,
+            };
+        }
+        if depth == 0 {
+            return evaluate(game);
+        }
+
+        let key = game.hash_key();
+        let mut tt_move = None;
+        if let Some(entry) = self.tt.probe(key) {
+            tt_move = entry
+                .move_index
+                .map(|i| i as usize)
+                .filter(|i| *i < moves.len());
+            // Only cut in null window nodes so the principal variation survives
+            if ply > 0 && beta - alpha == 1 && entry.depth as u32 >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_index = None;
+
+        let mut n = 0;
+        // Every move is played on the same copy, which keeps its allocations
+        let mut child = game.clone();
+        for i in self.order_moves(game, &moves, tt_move) {
+            let m = moves[i];
+            if ply == 0 && self.excluded.contains(&m) {
+                continue;
+            }
+            if n > 0 {
+                child.clone_from(game);
+            }
+            child.play_legal(m);
+
+            let mut child_pv = Vec::new();
+            let mut score;
+            if n == 0 {
+                score = self.child_score(
+                    game,
+                    &child,
+                    depth - 1,
+                    ply + 1,
+                    (alpha, beta),
+                    &mut child_pv,
+                );
+            } else {
+                score = self.child_score(
+                    game,
+                    &child,
+                    depth - 1,
+                    ply + 1,
+                    (alpha, alpha + 1),
+                    &mut child_pv,
+                );
+                if score > alpha && score < beta {
+                    child_pv.clear();
+                    score = self.child_score(
+                        game,
+                        &child,
+                        depth - 1,
+                        ply + 1,
+                        (alpha, beta),
+                        &mut child_pv,
+                    );
+                }
+            }
+            if self.stopped() {
+                return 0;
+            }
+            n += 1;
+
+            if score > best_score {
+                best_score = score;
+                best_index = Some(i);
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(m);
+                    pv.append(&mut child_pv);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // A root search with excluded moves is not a real result for the position
+        if !self.excluded.is_empty() && ply == 0 {
+            return best_score;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(
+            key,
+            TtEntry {
+                score: score_to_tt(best_score, ply),
+                depth: depth.min(u8::MAX as u32) as u8,
+                bound,
+                move_index: best_index.map(|i| i as u16),
+            },
+        );
+
+        best_score
+    }
+}
@@ -0,0 +1,371 @@
+use crate::betza::PieceDef;
+use crate::board::{Board, PawnRules};
+use crate::error::Error;
+use crate::game::{CastlingRight, Game, Move, DROP};
+use crate::piece::{Color, Piece, PieceType};
+use crate::square::Square;
+use crate::time::Clock;
+use crate::variant;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Serde support, built with the serde feature. PieceType, Color, Piece,
+// CastlingRight, PawnRules and Outcome derive their serde traits and a
+// Square is written as its file and rank, so in JSON a move looks like
+//
+//   {"from":[4,1],"to":[4,3],"piece":"Pawn"}
+//
+// with drops and duck placements coming from null instead of a square.
+// Board and Game go through the structs below instead of their fields,
+// which are free to change. Both carry the version of the schema and refuse
+// any other, as well as pieces that don't match the board:
+//
+//   board: version, size_x, size_y, origin, pawn_rules, custom_pieces
+//          (name, symbol, value, betza), holes, player_directions,
+//          move_margin, occupied ([[x, y], color] of every square with
+//          something on it) and moved (squares that lost their original
+//          piece)
+//   game:  version, variant (its name), board, pieces, whites_turn,
+//          castling, en_passant, halfmove_clock, fullmove_number,
+//          checks_given, pockets, promoted, placing_duck, history (the
+//          moves played since the position was set up) and clock
+//          (remaining_ms of white and black and increment_ms, or null)
+//
+// Everything a game remembers of the moves that led to it is included, so
+// a loaded game plays on exactly like the one that was saved and still
+// knows how it got there. Fields are only ever added with a new version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CustomPiece {
+    name: String,
+    symbol: char,
+    value: i32,
+    betza: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BoardData {
+    version: u32,
+    size_x: usize,
+    size_y: usize,
+    origin: (usize, usize),
+    pawn_rules: PawnRules,
+    custom_pieces: Vec<CustomPiece>,
+    holes: Vec<(usize, usize)>,
+    player_directions: Vec<(i64, i64)>,
+    move_margin: Option<usize>,
+    occupied: Vec<((usize, usize), Color)>,
+    moved: Vec<(usize, usize)>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GameData {
+    version: u32,
+    variant: String,
+    board: Board,
+    pieces: Vec<Piece>,
+    whites_turn: bool,
+    castling: Vec<CastlingRight>,
+    en_passant: Option<Square>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    checks_given: [u32; 2],
+    pockets: [Vec<PieceType>; 2],
+    promoted: Vec<Square>,
+    placing_duck: bool,
+    history: Vec<Move>,
+    clock: Option<ClockData>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MoveData {
+    from: Option<Square>,
+    to: Square,
+    piece: PieceType,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClockData {
+    remaining_ms: [u64; 2],
+    increment_ms: u64,
+}
+
+fn check_version(version: u32) -> Result<(), Error> {
+    if version != SCHEMA_VERSION {
+        return Err(Error::ParseError(format!(
+            "schema version {} is not supported, only {} is",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(())
+}
+
+// Custom piece types have to be registered on the board
+fn check_piece_type(board: &Board, piece_type: PieceType) -> Result<(), Error> {
+    match piece_type {
+        PieceType::Custom(i) if board.piece_def(piece_type).is_none() => Err(Error::InvalidPiece(
+            format!("custom piece {} is not on the board", i),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl MoveData {
+    fn new(m: &Move) -> MoveData {
+        MoveData {
+            from: if m.is_drop() { None } else { Some(m.from) },
+            to: m.to,
+            piece: m.piece,
+        }
+    }
+
+    fn into_move(self) -> Result<Move, Error> {
+        let from = match self.from {
+            Some(DROP) => {
+                return Err(Error::ParseError(
+                    "drops are written with a null from".to_string(),
+                ))
+            }
+            Some(from) => from,
+            None => DROP,
+        };
+        Ok(Move::new(from, self.to, self.piece))
+    }
+}
+
+impl BoardData {
+    fn new(board: &Board) -> BoardData {
+        BoardData {
+            version: SCHEMA_VERSION,
+            size_x: board.size_x,
+            size_y: board.size_y,
+            origin: board.origin,
+            pawn_rules: board.pawn_rules.clone(),
+            custom_pieces: board
+                .custom_pieces
+                .iter()
+                .map(|def| CustomPiece {
+                    name: def.name.clone(),
+                    symbol: def.symbol,
+                    value: def.value,
+                    betza: def.betza.clone(),
+                })
+                .collect(),
+            holes: board.holes(),
+            player_directions: board.player_directions.clone(),
+            move_margin: board.move_margin,
+            occupied: board.occupied(),
+            moved: board.moved(),
+        }
+    }
+
+    fn into_board(self) -> Result<Board, Error> {
+        check_version(self.version)?;
+        let (size_x, size_y) = (self.size_x, self.size_y);
+        let mut board = Board::new(size_x, size_y)?;
+        let on_board = |(x, y): (usize, usize)| {
+            if x < size_x && y < size_y {
+                Ok(Square::at(x, y))
+            } else {
+                Err(Error::InvalidSquare(format!("({}, {})", x, y)))
+            }
+        };
+        board.origin = self.origin;
+        on_board(board.origin)?;
+        board.custom_pieces = Arc::new(
+            self.custom_pieces
+                .iter()
+                .map(|p| PieceDef::new(&p.name, p.symbol, p.value, &p.betza))
+                .collect::<Result<_, _>>()?,
+        );
+        let rules = &self.pawn_rules;
+        for piece_type in rules
+            .promotion_pieces
+            .iter()
+            .chain(rules.promotion_limits.iter().map(|(p, _)| p))
+        {
+            check_piece_type(&board, *piece_type)?;
+        }
+        board.pawn_rules = self.pawn_rules;
+        for hole in self.holes.iter() {
+            board.remove_tile(on_board(*hole)?);
+        }
+        board.player_directions = self.player_directions;
+        board.move_margin = self.move_margin;
+        for (square, color) in self.occupied.iter() {
+            let square = on_board(*square)?;
+            board.set_emptiness(square, false);
+            board.set_piece_color(square, *color);
+        }
+        for square in self.moved.iter() {
+            board.false_orig_piece(on_board(*square)?);
+        }
+        Ok(board)
+    }
+}
+
+impl ClockData {
+    fn new(clock: &Clock) -> ClockData {
+        let ms = |time: Duration| time.as_millis() as u64;
+        ClockData {
+            remaining_ms: [ms(clock.remaining(true)), ms(clock.remaining(false))],
+            increment_ms: ms(clock.increment()),
+        }
+    }
+
+    fn into_clock(self) -> Clock {
+        let [white, black] = self.remaining_ms;
+        let mut clock = Clock::new(Duration::ZERO, Duration::from_millis(self.increment_ms));
+        clock.set_remaining(true, Duration::from_millis(white));
+        clock.set_remaining(false, Duration::from_millis(black));
+        clock
+    }
+}
+
+impl GameData {
+    fn new(game: &Game) -> GameData {
+        let mut pieces: Vec<Piece> = game.all_pieces().cloned().collect();
+        pieces.sort_by_key(|p| p.square);
+        GameData {
+            version: SCHEMA_VERSION,
+            variant: game.variant().name().to_string(),
+            board: game.board().clone(),
+            pieces,
+            whites_turn: game.is_whites_turn(),
+            castling: game.castling_rights().to_vec(),
+            en_passant: game.en_passant(),
+            halfmove_clock: game.halfmove_clock(),
+            fullmove_number: game.fullmove_number(),
+            checks_given: [
+                game.checks_given(Color::White),
+                game.checks_given(Color::Black),
+            ],
+            pockets: [
+                game.pocket(Color::White).to_vec(),
+                game.pocket(Color::Black).to_vec(),
+            ],
+            promoted: game.promoted_squares(),
+            placing_duck: game.is_placing_duck(),
+            history: game.history(),
+            clock: game.clock().map(ClockData::new),
+        }
+    }
+
+    fn into_game(self) -> Result<Game, Error> {
+        check_version(self.version)?;
+        let variant = variant::by_name(&self.variant)
+            .ok_or_else(|| Error::ParseError(format!("unknown variant {}", self.variant)))?;
+        let pawn_rules = self.board.pawn_rules.clone();
+        let occupied: HashMap<_, _> = self.board.occupied().into_iter().collect();
+        let mut squares = HashMap::new();
+        for piece in self.pieces.iter() {
+            if !self.board.is_valid_tile(piece.square) {
+                return Err(Error::InvalidSquare(piece.square.to_string()));
+            }
+            if let Color::Player(_) = piece.color {
+                return Err(Error::InvalidPosition(format!(
+                    "the piece on {} belongs to a multiplayer game",
+                    piece.square
+                )));
+            }
+            check_piece_type(&self.board, piece.piece_type)?;
+            if squares.insert(piece.square.coords(), piece.color).is_some() {
+                return Err(Error::InvalidPosition(format!(
+                    "two pieces stand on {}",
+                    piece.square
+                )));
+            }
+        }
+        if squares != occupied {
+            return Err(Error::InvalidPosition(
+                "the pieces don't match the occupied squares of the board and their colours"
+                    .to_string(),
+            ));
+        }
+        let pockets = self.pockets.iter().flatten();
+        let history = self.history.iter().map(|m| &m.piece);
+        for piece_type in pockets.chain(history) {
+            check_piece_type(&self.board, *piece_type)?;
+        }
+        let mut game = Game::from_parts(
+            self.board,
+            self.pieces,
+            self.whites_turn,
+            self.castling,
+            self.en_passant,
+            self.halfmove_clock,
+            self.fullmove_number,
+        );
+        // The variant changes the pawn rules, which are already the saved
+        // ones
+        game.set_variant(variant);
+        game.set_pawn_rules(pawn_rules);
+        let [white, black] = self.pockets;
+        game.set_pockets(white, black);
+        game.set_checks_given(self.checks_given[0], self.checks_given[1]);
+        game.set_promoted(self.promoted);
+        game.set_placing_duck(self.placing_duck);
+        game.set_history(self.history);
+        game.set_clock(self.clock.map(ClockData::into_clock));
+        Ok(game)
+    }
+}
+
+impl Serialize for Square {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.coords().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Square {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Square, D::Error> {
+        let (x, y) = <(usize, usize)>::deserialize(deserializer)?;
+        Ok(Square::at(x, y))
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoveData::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Move, D::Error> {
+        MoveData::deserialize(deserializer)?
+            .into_move()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Board {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BoardData::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Board {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Board, D::Error> {
+        BoardData::deserialize(deserializer)?
+            .into_board()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GameData::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Game {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Game, D::Error> {
+        GameData::deserialize(deserializer)?
+            .into_game()
+            .map_err(de::Error::custom)
+    }
+}
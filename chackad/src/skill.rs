@@ -0,0 +1,159 @@
+use crate::game::{Game, Move};
+use crate::search::{evaluate, PvLine, SearchLimits, Searcher};
+use crate::variant::Outcome;
+
+pub const MAX_LEVEL: u32 = 20;
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2800;
+
+// Strength limit for play against humans. Lower levels search shallower
+// with a small node budget, and then deliberately pick a worse line out of
+// the MultiPV candidates.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Skill {
+    pub fn new(level: u32) -> Skill {
+        Skill {
+            level: level.min(MAX_LEVEL),
+        }
+    }
+
+    // Maps UCI_Elo linearly onto the skill levels
+    pub fn from_elo(elo: u32) -> Skill {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        Skill::new((elo - MIN_ELO) * MAX_LEVEL / (MAX_ELO - MIN_ELO))
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.level < MAX_LEVEL
+    }
+
+    pub fn depth(&self) -> u32 {
+        1 + self.level / 5
+    }
+
+    pub fn nodes(&self) -> u64 {
+        200 << (self.level / 2)
+    }
+
+    // Number of candidate lines to choose from
+    pub fn multi_pv(&self) -> usize {
+        4
+    }
+
+    // Applies the depth, node and MultiPV limits of this level
+    pub fn limit(&self, limits: &mut SearchLimits) {
+        if !self.is_limited() {
+            return;
+        }
+        limits.depth = limits.depth.min(self.depth());
+        limits.nodes = Some(limits.nodes.unwrap_or(u64::MAX).min(self.nodes()));
+        limits.multi_pv = limits.multi_pv.max(self.multi_pv());
+    }
+
+    // Picks a line from the ranked candidates. Every line gets a random
+    // bonus that grows with the weakness of the level and with how far it
+    // is behind the best line, so weak levels mostly err by a little and
+    // only sometimes by a lot.
+    pub fn pick<'a>(&self, lines: &'a [PvLine], seed: u64) -> Option<&'a PvLine> {
+        let best = lines.first()?;
+        if !self.is_limited() {
+            return Some(best);
+        }
+
+        let weakness = 120 - 2 * self.level as i32;
+        let worst = lines.last().map(|l| l.score).unwrap_or(best.score);
+        let delta = (best.score - worst).min(100);
+        let mut rng = seed | 1;
+
+        let mut choice = best;
+        let mut max_push = i32::MIN;
+        for line in lines.iter() {
+            rng = xorshift(rng);
+            let push = (weakness * (best.score - line.score)
+                + delta * (rng % weakness as u64) as i32)
+                / 128;
+            if line.score + push >= max_push {
+                max_push = line.score + push;
+                choice = line;
+            }
+        }
+        Some(choice)
+    }
+}
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+// Plays every pair of levels against each other from the start position
+// with both colours and returns the points scored by each level. Games are
+// adjudicated on material after max_plies.
+pub fn calibrate(levels: &[u32], games_per_pair: u32, max_plies: u32) -> Vec<f64> {
+    let mut points = vec![0.0; levels.len()];
+    let mut seed = 1;
+    for a in 0..levels.len() {
+        for b in a + 1..levels.len() {
+            for n in 0..games_per_pair {
+                seed += 1;
+                let (white, black) = if n % 2 == 0 { (a, b) } else { (b, a) };
+                let score = play(
+                    Skill::new(levels[white]),
+                    Skill::new(levels[black]),
+                    max_plies,
+                    seed,
+                );
+                points[white] += score;
+                points[black] += 1.0 - score;
+            }
+        }
+    }
+    points
+}
+
+// Result for white: 1 for a win, 0.5 for a draw and 0 for a loss
+fn play(white: Skill, black: Skill, max_plies: u32, seed: u64) -> f64 {
+    let mut game = Game::new();
+    game.regular_chess_setup();
+    let mut searchers = [Searcher::new(1 << 12), Searcher::new(1 << 12)];
+
+    for ply in 0..max_plies {
+        if let Some(outcome) = game.outcome() {
+            return match outcome {
+                Outcome::WhiteWins => 1.0,
+                Outcome::BlackWins => 0.0,
+                Outcome::Draw => 0.5,
+            };
+        }
+        let (skill, searcher) = if game.is_whites_turn() {
+            (white, &mut searchers[0])
+        } else {
+            (black, &mut searchers[1])
+        };
+        let mut limits = SearchLimits::depth(3);
+        limits.skill = Some(skill);
+        limits.seed = seed.wrapping_mul(31).wrapping_add(ply as u64);
+        let result = searcher.search(&game, &limits);
+        let m: Move = result.best_move.expect("position has legal moves");
+        game.play_legal(m);
+    }
+
+    let material = evaluate(&game) * if game.is_whites_turn() { 1 } else { -1 };
+    if material > 100 {
+        1.0
+    } else if material < -100 {
+        0.0
+    } else {
+        0.5
+    }
+}
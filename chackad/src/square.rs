@@ -0,0 +1,144 @@
+use crate::board::Board;
+use crate::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// A column of the board, 0 being the a-file
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct File(pub usize);
+
+// A row of the board, 0 being the first rank
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rank(pub usize);
+
+// A square given by its file and rank. Keeping the two apart in their own
+// types catches swapped coordinates, which square boards would hide.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub struct Square {
+    pub file: File,
+    pub rank: Rank,
+}
+
+impl File {
+    // The file the given number of columns to the right, if the board is
+    // that wide
+    pub fn offset(self, by: i64, board: &Board) -> Option<File> {
+        let x = (self.0 as i64).checked_add(by)?;
+        if x < 0 || x as usize >= board.size_x {
+            return None;
+        }
+        Some(File(x as usize))
+    }
+}
+
+impl Rank {
+    // The rank the given number of rows up, if the board is that high
+    pub fn offset(self, by: i64, board: &Board) -> Option<Rank> {
+        let y = (self.0 as i64).checked_add(by)?;
+        if y < 0 || y as usize >= board.size_y {
+            return None;
+        }
+        Some(Rank(y as usize))
+    }
+}
+
+impl Square {
+    pub fn new(file: File, rank: Rank) -> Square {
+        Square { file, rank }
+    }
+
+    // The square in column x and row y
+    pub fn at(x: usize, y: usize) -> Square {
+        Square::new(File(x), Rank(y))
+    }
+
+    pub fn coords(self) -> (usize, usize) {
+        (self.file.0, self.rank.0)
+    }
+
+    // The square the given number of files and ranks away, if it is a tile
+    // of the board
+    pub fn offset(self, by: (i64, i64), board: &Board) -> Option<Square> {
+        board.offset(self, by)
+    }
+}
+
+// Letters like spreadsheet columns, so z is followed by aa, ab and so on
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut letters = Vec::new();
+        let mut n = self.0 + 1;
+        while n > 0 {
+            n -= 1;
+            letters.push((b'a' + (n % 26) as u8) as char);
+            n /= 26;
+        }
+        write!(f, "{}", letters.iter().rev().collect::<String>())
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.file, self.rank)
+    }
+}
+
+// One or more letters, where upper case is accepted too
+impl FromStr for File {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<File, Error> {
+        let invalid = || Error::InvalidSquare(s.to_string());
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        let mut n: usize = 0;
+        for c in s.chars() {
+            if !c.is_ascii_alphabetic() {
+                return Err(invalid());
+            }
+            let digit = (c.to_ascii_lowercase() as u8 - b'a') as usize + 1;
+            n = n
+                .checked_mul(26)
+                .and_then(|n| n.checked_add(digit))
+                .ok_or_else(invalid)?;
+        }
+        Ok(File(n - 1))
+    }
+}
+
+// A number from 1 without leading zeros
+impl FromStr for Rank {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rank, Error> {
+        if s.starts_with('0') || !s.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidSquare(s.to_string()));
+        }
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(Rank(n - 1)),
+            _ => Err(Error::InvalidSquare(s.to_string())),
+        }
+    }
+}
+
+// The file followed by the rank, like "e4"
+impl FromStr for Square {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Square, Error> {
+        let invalid = || Error::InvalidSquare(s.to_string());
+        let split = s.find(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+        let file = s[..split].parse().map_err(|_| invalid())?;
+        let rank = s[split..].parse().map_err(|_| invalid())?;
+        Ok(Square::new(file, rank))
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+// Time kept back for communication lag between the engine and the GUI
+pub const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+// Assumed number of moves left in sudden death games
+const DEFAULT_MOVES_TO_GO: u32 = 50;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TimeControl {
+    // Time left on the clock of the side to move
+    pub remaining: Duration,
+    pub increment: Duration,
+    // Moves until the next time control, None for sudden death
+    pub moves_to_go: Option<u32>,
+}
+
+// Time left for both sides of a board, kept by whoever runs the game
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Clock {
+    remaining: [Duration; 2],
+    increment: Duration,
+}
+
+impl Clock {
+    pub fn new(time: Duration, increment: Duration) -> Clock {
+        Clock {
+            remaining: [time, time],
+            increment,
+        }
+    }
+
+    pub fn remaining(&self, white: bool) -> Duration {
+        self.remaining[if white { 0 } else { 1 }]
+    }
+
+    pub fn increment(&self) -> Duration {
+        self.increment
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_remaining(&mut self, white: bool, remaining: Duration) {
+        self.remaining[if white { 0 } else { 1 }] = remaining;
+    }
+
+    // Takes the time spent on a move and adds the increment, false when the
+    // flag fell before the move was made
+    pub fn spend(&mut self, white: bool, spent: Duration) -> bool {
+        let remaining = &mut self.remaining[if white { 0 } else { 1 }];
+        if spent >= *remaining {
+            *remaining = Duration::from_secs(0);
+            return false;
+        }
+        *remaining = *remaining - spent + self.increment;
+        true
+    }
+}
+
+// Decides how long to think on one move. The soft limit is where the search
+// normally stops after a finished iteration, the hard limit is never crossed.
+#[derive(Debug, Clone)]
+pub struct TimeManager {
+    soft: Duration,
+    hard: Duration,
+    single_reply: bool,
+    instability: f64,
+    last_best: Option<usize>,
+    last_score: Option<i32>,
+    score_dropped: bool,
+}
+
+impl TimeManager {
+    pub fn new(control: &TimeControl) -> TimeManager {
+        let moves_to_go = control
+            .moves_to_go
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .clamp(1, DEFAULT_MOVES_TO_GO);
+
+        // Everything we can spend until the next time control, keeping the
+        // lag of every one of those moves in reserve
+        let budget = (control.remaining + control.increment * (moves_to_go - 1))
+            .saturating_sub(MOVE_OVERHEAD * (moves_to_go + 1));
+        let available = control.remaining.saturating_sub(MOVE_OVERHEAD);
+
+        let hard = if moves_to_go == 1 {
+            available.mul_f64(0.9)
+        } else {
+            (budget / moves_to_go * 4).min(available / 2)
+        };
+        let soft = (budget / moves_to_go).min(hard);
+
+        TimeManager::with_limits(soft, hard)
+    }
+
+    // Fixed time per move as with "go movetime"
+    pub fn fixed(movetime: Duration) -> TimeManager {
+        let limit = movetime.saturating_sub(MOVE_OVERHEAD);
+        TimeManager::with_limits(limit, limit)
+    }
+
+    fn with_limits(soft: Duration, hard: Duration) -> TimeManager {
+        TimeManager {
+            soft,
+            hard,
+            single_reply: false,
+            instability: 0.0,
+            last_best: None,
+            last_score: None,
+            score_dropped: false,
+        }
+    }
+
+    pub fn soft_limit(&self) -> Duration {
+        self.soft
+    }
+
+    pub fn hard_limit(&self) -> Duration {
+        self.hard
+    }
+
+    // With only one legal move there is nothing to think about
+    pub fn set_single_reply(&mut self) {
+        self.single_reply = true;
+    }
+
+    pub fn out_of_time(&self, elapsed: Duration) -> bool {
+        elapsed >= self.hard
+    }
+
+    // The current soft limit after extensions for an unstable best move or a
+    // falling score
+    pub fn target(&self) -> Duration {
+        let mut scale = 1.0 + 0.5 * self.instability;
+        if self.score_dropped {
+            scale *= 1.5;
+        }
+        self.soft.mul_f64(scale).min(self.hard)
+    }
+
+    // Called after every finished iteration with the best root move (as an
+    // index into the legal moves) and its score. Returns whether another
+    // iteration should be started.
+    pub fn iteration_done(&mut self, elapsed: Duration, best: usize, score: i32) -> bool {
+        if self.single_reply {
+            return false;
+        }
+
+        self.instability *= 0.5;
+        if self.last_best.map(|b| b != best).unwrap_or(false) {
+            self.instability += 1.0;
+        }
+        self.score_dropped = self.last_score.map(|s| score < s - 30).unwrap_or(false);
+        self.last_best = Some(best);
+        self.last_score = Some(score);
+
+        // The next iteration takes longer than all earlier ones together,
+        // don't start it if it can't finish before the target
+        elapsed < self.target().mul_f64(0.5)
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TtEntry {
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    // Index into the sorted legal move list of the position
+    pub move_index: Option<u16>,
+}
+
+const NO_MOVE: u64 = 0xffff;
+// Set in every stored entry, since an entry may pack to all zeros and the
+// empty slots of the table are zero too
+const FILLED: u64 = 1 << 47;
+
+impl TtEntry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let move_index = self.move_index.map(|i| i as u64).unwrap_or(NO_MOVE);
+        (self.score as u32 as u64)
+            | (self.depth as u64) << 32
+            | bound << 40
+            | FILLED
+            | move_index << 48
+    }
+
+    fn unpack(data: u64) -> TtEntry {
+        let bound = match (data >> 40) & 0x7f {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let move_index = match data >> 48 {
+            NO_MOVE => None,
+            i => Some(i as u16),
+        };
+        TtEntry {
+            score: data as u32 as i32,
+            depth: (data >> 32) as u8,
+            bound,
+            move_index,
+        }
+    }
+}
+
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+// Lock-free table shared between search threads. Every slot stores the key
+// xor'ed with the data, so a torn write from two threads racing on the same
+// slot simply fails verification on the next probe instead of returning
+// garbage.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl TranspositionTable {
+    pub fn new(size: usize) -> TranspositionTable {
+        let size = size.max(1).next_power_of_two();
+        let slots = (0..size)
+            .map(|_| Slot {
+                key: AtomicU64::new(0),
+                data: AtomicU64::new(0),
+            })
+            .collect();
+        TranspositionTable { slots }
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        let stored = slot.key.load(Ordering::Relaxed);
+        if data & FILLED != 0 && stored ^ data == key {
+            Some(TtEntry::unpack(data))
+        } else {
+            None
+        }
+    }
+
+    pub fn store(&self, key: u64, entry: TtEntry) {
+        let slot = self.slot(key);
+        if let Some(old) = self.probe(key) {
+            // Keep deeper results for the same position
+            if old.depth > entry.depth && entry.bound != Bound::Exact {
+                return;
+            }
+        }
+        let data = entry.pack();
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+}
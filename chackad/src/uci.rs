@@ -0,0 +1,398 @@
+use crate::fen;
+use crate::game::{Game, Move};
+use crate::search::{
+    SearchHandle, SearchInfo, SearchLimits, SearchObserver, SearchResult, Searcher, MATE, MAX_DEPTH,
+};
+use crate::skill::{Skill, MAX_ELO, MAX_LEVEL, MIN_ELO};
+use crate::time::{TimeControl, TimeManager};
+use crate::variant::{self, Standard, Variant};
+use std::io;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TT_SIZE: usize = 1 << 16;
+
+// Castling is written as king takes rook in Chess960 and as the two square
+// king move otherwise. Drops are written as the upper case piece, an @ and
+// the square, like P@e4. Placing the duck in Duck chess is a move of its
+// own written without the piece, like @d5.
+pub fn move_to_uci(game: &Game, m: &Move, chess960: bool) -> String {
+    if m.places_duck() {
+        return format!("@{}", m.to);
+    }
+    if m.is_drop() {
+        return format!(
+            "{}@{}",
+            fen::piece_type_char(game.board(), m.piece).to_ascii_uppercase(),
+            m.to
+        );
+    }
+    let to = if chess960 { m.to } else { game.king_target(m) };
+    let mut s = format!("{}{}", m.from, to);
+    let promotes = game
+        .piece_at(m.from)
+        .map(|p| p.piece_type != m.piece)
+        .unwrap_or(false);
+    if promotes {
+        s.push(fen::piece_type_char(game.board(), m.piece));
+    }
+    s
+}
+
+pub fn move_from_uci(game: &Game, s: &str, chess960: bool) -> Option<Move> {
+    let s = s.trim();
+    game.legal_moves()
+        .into_iter()
+        .find(|m| move_to_uci(game, m, chess960) == s)
+}
+
+fn format_score(score: i32) -> String {
+    if score > MATE - 1000 {
+        format!("mate {}", (MATE - score + 1) / 2)
+    } else if score < -MATE + 1000 {
+        format!("mate -{}", (MATE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+struct UciObserver<W: Write> {
+    game: Game,
+    chess960: bool,
+    output: Arc<Mutex<W>>,
+}
+
+impl<W: Write> UciObserver<W> {
+    fn pv_string(&self, pv: &[Move]) -> String {
+        let mut game = self.game.clone();
+        let mut moves = Vec::new();
+        for m in pv.iter() {
+            moves.push(move_to_uci(&game, m, self.chess960));
+            game.play_legal(*m);
+        }
+        moves.join(" ")
+    }
+}
+
+impl<W: Write + Send> SearchObserver for UciObserver<W> {
+    fn progress(&mut self, info: &SearchInfo) {
+        let line = format!(
+            "info depth {} multipv {} score {} nodes {} pv {}",
+            info.depth,
+            info.multi_pv,
+            format_score(info.score),
+            info.nodes,
+            self.pv_string(&info.pv)
+        );
+        let mut output = self.output.lock().unwrap();
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+
+    fn finished(&mut self, result: &SearchResult) {
+        let line = match result.best_move {
+            Some(m) => {
+                let mut line = format!("bestmove {}", move_to_uci(&self.game, &m, self.chess960));
+                if let Some(reply) = result.pv.get(1) {
+                    let mut game = self.game.clone();
+                    game.play_legal(m);
+                    line.push_str(&format!(
+                        " ponder {}",
+                        move_to_uci(&game, reply, self.chess960)
+                    ));
+                }
+                line
+            }
+            None => "bestmove 0000".to_string(),
+        };
+        let mut output = self.output.lock().unwrap();
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+}
+
+// Universal Chess Interface front end, reads commands from any reader so it
+// can be driven by a GUI over stdin or by a test with a string. Searches
+// run in the background so stop and ponderhit are handled while thinking.
+pub struct Uci {
+    game: Game,
+    searcher: Searcher,
+    threads: usize,
+    multi_pv: usize,
+    search: Option<SearchHandle>,
+    search_infinite: bool,
+    skill_level: u32,
+    limit_strength: bool,
+    elo: u32,
+    chess960: bool,
+    variant: Arc<dyn Variant>,
+}
+
+impl Default for Uci {
+    fn default() -> Self {
+        Uci::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Uci {
+        Uci {
+            game: Game::with_variant(Arc::new(Standard)),
+            searcher: Searcher::new(TT_SIZE),
+            threads: 1,
+            multi_pv: 1,
+            search: None,
+            search_infinite: false,
+            skill_level: MAX_LEVEL,
+            limit_strength: false,
+            elo: MAX_ELO,
+            chess960: false,
+            variant: Arc::new(Standard),
+        }
+    }
+
+    // Runs until quit or end of input and hands the output back
+    pub fn run<R: BufRead, W: Write + Send + 'static>(
+        &mut self,
+        input: R,
+        output: W,
+    ) -> io::Result<W> {
+        let output = Arc::new(Mutex::new(output));
+        for line in input.lines() {
+            if !self.handle(&line?, &output)? {
+                break;
+            }
+        }
+        self.finish_search(false);
+
+        match Arc::try_unwrap(output) {
+            Ok(output) => Ok(output.into_inner().unwrap()),
+            Err(_) => panic!("search thread still holds the output"),
+        }
+    }
+
+    // Waits for the running search, which prints its bestmove. Searches that
+    // would never end on their own are always stopped.
+    fn finish_search(&mut self, stop: bool) {
+        if let Some(handle) = self.search.take() {
+            if stop || self.search_infinite || handle.is_pondering() {
+                handle.stop();
+            }
+            handle.wait();
+        }
+    }
+
+    // Returns false when the engine should quit
+    fn handle<W: Write + Send + 'static>(
+        &mut self,
+        line: &str,
+        output: &Arc<Mutex<W>>,
+    ) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"uci") => {
+                let mut output = output.lock().unwrap();
+                writeln!(output, "id name chackad")?;
+                writeln!(output, "id author Björn Thorén")?;
+                writeln!(
+                    output,
+                    "option name Threads type spin default 1 min 1 max 512"
+                )?;
+                writeln!(
+                    output,
+                    "option name MultiPV type spin default 1 min 1 max 256"
+                )?;
+                writeln!(output, "option name Ponder type check default false")?;
+                writeln!(
+                    output,
+                    "option name Skill Level type spin default {} min 0 max {}",
+                    MAX_LEVEL, MAX_LEVEL
+                )?;
+                writeln!(
+                    output,
+                    "option name UCI_LimitStrength type check default false"
+                )?;
+                writeln!(
+                    output,
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    MAX_ELO, MIN_ELO, MAX_ELO
+                )?;
+                writeln!(output, "option name UCI_Chess960 type check default false")?;
+                let names: Vec<String> = variant::variants()
+                    .iter()
+                    .map(|v| format!("var {}", v.name()))
+                    .collect();
+                writeln!(
+                    output,
+                    "option name UCI_Variant type combo default chess {}",
+                    names.join(" ")
+                )?;
+                writeln!(output, "uciok")?;
+            }
+            Some(&"isready") => writeln!(output.lock().unwrap(), "readyok")?,
+            Some(&"ucinewgame") => {
+                self.finish_search(true);
+                self.searcher.clear();
+                self.game = Game::with_variant(self.variant.clone());
+            }
+            Some(&"setoption") => {
+                self.finish_search(true);
+                self.set_option(&words[1..], &mut *output.lock().unwrap())?
+            }
+            Some(&"position") => {
+                self.finish_search(true);
+                self.position(&words[1..], &mut *output.lock().unwrap())?
+            }
+            Some(&"go") => {
+                self.finish_search(true);
+                self.go(&words[1..], output);
+            }
+            Some(&"stop") => self.finish_search(true),
+            Some(&"ponderhit") => {
+                if let Some(handle) = self.search.as_ref() {
+                    handle.ponderhit();
+                }
+            }
+            Some(&"quit") => {
+                self.finish_search(true);
+                return Ok(false);
+            }
+            Some(other) => writeln!(
+                output.lock().unwrap(),
+                "info string unknown command {}",
+                other
+            )?,
+            None => {}
+        }
+        output.lock().unwrap().flush()?;
+        Ok(true)
+    }
+
+    fn set_option<W: Write>(&mut self, words: &[&str], output: &mut W) -> io::Result<()> {
+        // setoption name <id> value <x>, where the id can contain spaces
+        let name_at = words.iter().position(|w| *w == "name");
+        let value_at = words.iter().position(|w| *w == "value");
+        let name = match name_at {
+            Some(i) => words[i + 1..value_at.unwrap_or(words.len())].join(" "),
+            None => String::new(),
+        };
+        let value = value_at.and_then(|i| words.get(i + 1)).unwrap_or(&"");
+        let number = value.parse::<usize>().ok();
+        match (name.as_str(), number) {
+            ("Threads", Some(v)) => self.threads = v.max(1),
+            ("MultiPV", Some(v)) => self.multi_pv = v.max(1),
+            ("Skill Level", Some(v)) => self.skill_level = v as u32,
+            ("UCI_Elo", Some(v)) => self.elo = v as u32,
+            ("UCI_LimitStrength", _) => self.limit_strength = *value == "true",
+            ("UCI_Chess960", _) => self.chess960 = *value == "true",
+            ("UCI_Variant", _) => match variant::by_name(value) {
+                Some(variant) => {
+                    self.variant = variant;
+                    self.game = Game::with_variant(self.variant.clone());
+                }
+                None => writeln!(output, "info string unknown variant {}", value)?,
+            },
+            ("Ponder", _) => {}
+            _ => writeln!(output, "info string unsupported option {}", name)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn skill(&self) -> Skill {
+        if self.limit_strength {
+            Skill::from_elo(self.elo)
+        } else {
+            Skill::new(self.skill_level)
+        }
+    }
+
+    fn position<W: Write>(&mut self, words: &[&str], output: &mut W) -> io::Result<()> {
+        let moves_at = words.iter().position(|w| *w == "moves");
+        let mut game = match words.first() {
+            Some(&"startpos") => Game::with_variant(self.variant.clone()),
+            Some(&"fen") => {
+                let fen = words[1..moves_at.unwrap_or(words.len())].join(" ");
+                let mut game = Game::with_variant(self.variant.clone());
+                match game.load_fen(&fen) {
+                    Ok(()) => game,
+                    Err(e) => {
+                        writeln!(output, "info string invalid fen: {}", e)?;
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {
+                writeln!(output, "info string expected startpos or fen")?;
+                return Ok(());
+            }
+        };
+        if let Some(i) = moves_at {
+            for s in &words[i + 1..] {
+                match move_from_uci(&game, s, self.chess960) {
+                    Some(m) => game.play_legal(m),
+                    None => {
+                        writeln!(output, "info string illegal move {}", s)?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        self.game = game;
+        Ok(())
+    }
+
+    fn go<W: Write + Send + 'static>(&mut self, words: &[&str], output: &Arc<Mutex<W>>) {
+        let value = |name: &str| {
+            words
+                .iter()
+                .position(|w| *w == name)
+                .and_then(|i| words.get(i + 1))
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        let infinite = words.contains(&"infinite");
+        let ponder = words.contains(&"ponder");
+
+        let (clock, increment) = if self.game.is_whites_turn() {
+            (value("wtime"), value("winc"))
+        } else {
+            (value("btime"), value("binc"))
+        };
+        let time = match (value("movetime"), clock) {
+            (Some(movetime), _) => Some(TimeManager::fixed(Duration::from_millis(movetime))),
+            (None, Some(clock)) => Some(TimeManager::new(&TimeControl {
+                remaining: Duration::from_millis(clock),
+                increment: Duration::from_millis(increment.unwrap_or(0)),
+                moves_to_go: value("movestogo").map(|m| m as u32),
+            })),
+            (None, None) => None,
+        };
+        let nodes = value("nodes");
+        // Without a depth the clock or the node count decides when to stop
+        let depth = match value("depth") {
+            Some(depth) => depth as u32,
+            None if time.is_some() || nodes.is_some() => MAX_DEPTH,
+            None => 4,
+        };
+
+        let mut limits = SearchLimits::depth(depth);
+        limits.threads = self.threads;
+        limits.multi_pv = self.multi_pv;
+        limits.infinite = infinite;
+        limits.time = time;
+        limits.nodes = nodes;
+        limits.skill = Some(self.skill());
+        limits.seed = self.game.hash_key();
+
+        let observer = UciObserver {
+            game: self.game.clone(),
+            chess960: self.chess960,
+            output: output.clone(),
+        };
+        self.search_infinite = infinite;
+        self.search = Some(
+            self.searcher
+                .spawn(self.game.clone(), limits, ponder, observer),
+        );
+    }
+}
@@ -0,0 +1,557 @@
+use crate::board::PawnRules;
+use crate::fog::Visibility;
+use crate::game::{Game, Move};
+use crate::piece::{Color, PieceType};
+use crate::square::Square;
+use std::fmt;
+use std::sync::Arc;
+
+const HORDE: &str = "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1";
+const RACING_KINGS: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl Outcome {
+    // A win for white or black, a draw for any other colour
+    pub fn win_for(color: Color) -> Outcome {
+        match color {
+            Color::White => Outcome::WhiteWins,
+            Color::Black => Outcome::BlackWins,
+            _ => Outcome::Draw,
+        }
+    }
+}
+
+// Rules of a chess variant. Game asks the variant for the start position,
+// which moves are legal, what else happens when a move is made and when the
+// game is over. The defaults are the rules of regular chess.
+pub trait Variant: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn setup(&self, game: &mut Game) {
+        game.regular_chess_setup();
+    }
+
+    // Changes to the pawn rules of the board the variant is played on
+    fn pawn_rules(&self, _rules: &mut PawnRules) {}
+
+    // Whether kings can capture and so attack squares
+    fn king_captures(&self) -> bool {
+        true
+    }
+
+    fn is_check(&self, game: &Game, color: Color) -> bool {
+        game.king_attacked(color)
+    }
+
+    // Called with the position after the move, before the turn has passed
+    fn is_legal(&self, game: &Game, _m: &Move, after: &Game) -> bool {
+        !self.is_check(after, game.side_to_move())
+    }
+
+    // Narrows down the legal moves, for example to forced captures
+    fn restrict(&self, _game: &Game, moves: Vec<Move>) -> Vec<Move> {
+        moves
+    }
+
+    // Effects of a move on top of moving the piece, with the square it
+    // ended on and whether it captured
+    fn after_move(&self, _game: &mut Game, _to: Square, _captured: bool) {}
+
+    // Ends the game even though there are moves left
+    fn early_outcome(&self, _game: &Game) -> Option<Outcome> {
+        None
+    }
+
+    // Result when the side to move has no legal moves
+    fn no_moves_outcome(&self, game: &Game) -> Outcome {
+        if game.in_check() {
+            Outcome::win_for(game.side_to_move().opponent())
+        } else {
+            Outcome::Draw
+        }
+    }
+
+    // Number of checks that win, written to FEN when set
+    fn check_limit(&self) -> Option<u32> {
+        None
+    }
+
+    // Whether captured pieces go to the pocket of the capturer and can be
+    // dropped back on the board instead of moving
+    fn drops(&self) -> bool {
+        false
+    }
+
+    // Whether captures go to the pocket of the capturer. Bughouse hands them
+    // to the partner on the other board instead.
+    fn keeps_captures(&self) -> bool {
+        self.drops()
+    }
+
+    // How much of the opponent's army a player sees
+    fn visibility(&self) -> Visibility {
+        Visibility::Full
+    }
+
+    // Whether every move is followed by placing the duck
+    fn duck(&self) -> bool {
+        false
+    }
+}
+
+// Result of variants without check, where the king is lost by being captured
+fn king_captured(game: &Game) -> Option<Outcome> {
+    match (
+        game.king(Color::White).is_some(),
+        game.king(Color::Black).is_some(),
+    ) {
+        (true, false) => Some(Outcome::WhiteWins),
+        (false, true) => Some(Outcome::BlackWins),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "chess"
+    }
+}
+
+#[derive(Debug)]
+pub struct Capablanca;
+
+impl Variant for Capablanca {
+    fn name(&self) -> &'static str {
+        "capablanca"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.capablanca_setup();
+    }
+}
+
+#[derive(Debug)]
+pub struct Gothic;
+
+impl Variant for Gothic {
+    fn name(&self) -> &'static str {
+        "gothic"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.gothic_setup();
+    }
+}
+
+#[derive(Debug)]
+pub struct Grand;
+
+impl Variant for Grand {
+    fn name(&self) -> &'static str {
+        "grand"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.grand_setup();
+    }
+}
+
+// Regular chess on a board without edges worth mentioning
+#[derive(Debug)]
+pub struct InfiniteChess;
+
+impl Variant for InfiniteChess {
+    fn name(&self) -> &'static str {
+        "infinite"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.infinite_chess_setup();
+    }
+}
+
+// Captures explode, removing the capturing piece and every piece but pawns
+// next to the square. Blowing up the enemy king wins, so kings can't
+// capture and touching kings can't give check.
+#[derive(Debug)]
+pub struct Atomic;
+
+impl Atomic {
+    fn kings_touch(game: &Game) -> bool {
+        match (game.king(Color::White), game.king(Color::Black)) {
+            (Some(white), Some(black)) => {
+                (white.square.file.0 as i128 - black.square.file.0 as i128).abs() <= 1
+                    && (white.square.rank.0 as i128 - black.square.rank.0 as i128).abs() <= 1
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "atomic"
+    }
+
+    fn king_captures(&self) -> bool {
+        false
+    }
+
+    fn is_check(&self, game: &Game, color: Color) -> bool {
+        !Atomic::kings_touch(game) && game.king_attacked(color)
+    }
+
+    fn is_legal(&self, game: &Game, _m: &Move, after: &Game) -> bool {
+        let own = game.side_to_move();
+        if after.king(own).is_none() {
+            return false;
+        }
+        after.king(own.opponent()).is_none() || !self.is_check(after, own)
+    }
+
+    fn after_move(&self, game: &mut Game, to: Square, captured: bool) {
+        if !captured {
+            return;
+        }
+        game.remove_piece(to);
+        let (to_x, to_y) = to.coords();
+        for x in to_x.saturating_sub(1)..=to_x + 1 {
+            for y in to_y.saturating_sub(1)..=to_y + 1 {
+                let square = Square::at(x, y);
+                let explodes = game
+                    .piece_at(square)
+                    .map(|p| p.piece_type != PieceType::Pawn)
+                    .unwrap_or(false);
+                if explodes {
+                    game.remove_piece(square);
+                }
+            }
+        }
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        match (
+            game.king(Color::White).is_some(),
+            game.king(Color::Black).is_some(),
+        ) {
+            (true, false) => Some(Outcome::WhiteWins),
+            (false, true) => Some(Outcome::BlackWins),
+            (false, false) => Some(Outcome::Draw),
+            (true, true) => None,
+        }
+    }
+}
+
+// Bringing the king to one of the four centre squares wins
+#[derive(Debug)]
+pub struct KingOfTheHill;
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "kingofthehill"
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        let board = game.board();
+        let centre_x = [board.size_x / 2 - 1, board.size_x / 2];
+        let centre_y = [board.size_y / 2 - 1, board.size_y / 2];
+        [Color::White, Color::Black].iter().find_map(|color| {
+            game.king(*color)
+                .filter(|k| {
+                    centre_x.contains(&k.square.file.0) && centre_y.contains(&k.square.rank.0)
+                })
+                .map(|_| Outcome::win_for(*color))
+        })
+    }
+}
+
+// Giving the third check wins
+#[derive(Debug)]
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "3check"
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        let limit = self.check_limit().unwrap();
+        [Color::White, Color::Black]
+            .iter()
+            .find(|color| game.checks_given(**color) >= limit)
+            .map(|color| Outcome::win_for(*color))
+    }
+
+    fn check_limit(&self) -> Option<u32> {
+        Some(3)
+    }
+}
+
+// Captured pieces change sides and can be dropped back on the board
+#[derive(Debug)]
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "crazyhouse"
+    }
+
+    fn drops(&self) -> bool {
+        true
+    }
+}
+
+// Crazyhouse on one of the two boards of a bughouse match, captured pieces
+// are passed on by the BughouseMatch
+#[derive(Debug)]
+pub struct Bughouse;
+
+impl Variant for Bughouse {
+    fn name(&self) -> &'static str {
+        "bughouse"
+    }
+
+    fn drops(&self) -> bool {
+        true
+    }
+
+    fn keeps_captures(&self) -> bool {
+        false
+    }
+}
+
+// Fog of war: players only see the squares their pieces can reach. There
+// is no check, the king is lost by being captured.
+#[derive(Debug)]
+pub struct DarkChess;
+
+impl Variant for DarkChess {
+    fn name(&self) -> &'static str {
+        "darkchess"
+    }
+
+    fn is_check(&self, _game: &Game, _color: Color) -> bool {
+        false
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        king_captured(game)
+    }
+
+    fn visibility(&self) -> Visibility {
+        Visibility::Dark
+    }
+}
+
+// After every move the mover places the duck on any empty square. The duck
+// belongs to nobody and blocks both sides. There is no check, the king is
+// lost by being captured, and a player without moves wins.
+#[derive(Debug)]
+pub struct DuckChess;
+
+impl Variant for DuckChess {
+    fn name(&self) -> &'static str {
+        "duck"
+    }
+
+    fn is_check(&self, _game: &Game, _color: Color) -> bool {
+        false
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        king_captured(game)
+    }
+
+    fn no_moves_outcome(&self, game: &Game) -> Outcome {
+        Outcome::win_for(game.side_to_move())
+    }
+
+    fn duck(&self) -> bool {
+        true
+    }
+}
+
+// Regular chess where players only see their own pieces, an Umpire tells
+// them what they need to know
+#[derive(Debug)]
+pub struct Kriegspiel;
+
+impl Variant for Kriegspiel {
+    fn name(&self) -> &'static str {
+        "kriegspiel"
+    }
+
+    fn visibility(&self) -> Visibility {
+        Visibility::OwnPieces
+    }
+}
+
+// Losing chess: captures are forced, the king is an ordinary piece, and the
+// side that runs out of pieces or moves wins
+#[derive(Debug)]
+pub struct Antichess;
+
+impl Variant for Antichess {
+    fn name(&self) -> &'static str {
+        "antichess"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.regular_chess_setup();
+        game.clear_castling();
+    }
+
+    fn pawn_rules(&self, rules: &mut PawnRules) {
+        rules.promotion_pieces.push(PieceType::King);
+    }
+
+    fn is_check(&self, _game: &Game, _color: Color) -> bool {
+        false
+    }
+
+    fn restrict(&self, game: &Game, moves: Vec<Move>) -> Vec<Move> {
+        if moves.iter().any(|m| game.is_capture(m)) {
+            moves.into_iter().filter(|m| game.is_capture(m)).collect()
+        } else {
+            moves
+        }
+    }
+
+    fn no_moves_outcome(&self, game: &Game) -> Outcome {
+        Outcome::win_for(game.side_to_move())
+    }
+}
+
+// White has 36 pawns and no king against a regular black army. White pawns
+// on the first rank may also move two squares. Black wins by capturing
+// everything.
+#[derive(Debug)]
+pub struct Horde;
+
+impl Variant for Horde {
+    fn name(&self) -> &'static str {
+        "horde"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.load_fen(HORDE).unwrap();
+    }
+
+    fn pawn_rules(&self, rules: &mut PawnRules) {
+        if !rules.double_step_ranks.contains(&0) {
+            rules.double_step_ranks.push(0);
+        }
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        if game.white_pieces_iter().next().is_none() {
+            Some(Outcome::BlackWins)
+        } else {
+            None
+        }
+    }
+}
+
+// Both kings race to the last rank and nobody may give check. If black
+// reaches it right after white the game is drawn.
+#[derive(Debug)]
+pub struct RacingKings;
+
+impl RacingKings {
+    fn on_goal(game: &Game, color: Color) -> bool {
+        let last = game.board().size_y - 1;
+        game.king(color)
+            .map(|k| k.square.rank.0 == last)
+            .unwrap_or(false)
+    }
+
+    // Whether black can answer a white king on the goal by reaching it too
+    fn black_can_follow(&self, game: &Game) -> bool {
+        let king = match game.king(Color::Black) {
+            Some(king) => king.clone(),
+            None => return false,
+        };
+        let last = game.board().size_y - 1;
+        if king.square.rank.0 + 1 != last {
+            return false;
+        }
+        let x = king.square.file.0;
+        (x.saturating_sub(1)..=x + 1).any(|x| {
+            let to = Square::at(x, last);
+            if !king.check_to(to, game.board(), PieceType::King) {
+                return false;
+            }
+            let m = Move::new(king.square, to, PieceType::King);
+            let mut after = game.clone();
+            after.move_now(&m);
+            self.is_legal(game, &m, &after)
+        })
+    }
+}
+
+impl Variant for RacingKings {
+    fn name(&self) -> &'static str {
+        "racingkings"
+    }
+
+    fn setup(&self, game: &mut Game) {
+        game.load_fen(RACING_KINGS).unwrap();
+    }
+
+    fn is_legal(&self, _game: &Game, _m: &Move, after: &Game) -> bool {
+        !after.king_attacked(Color::White) && !after.king_attacked(Color::Black)
+    }
+
+    fn early_outcome(&self, game: &Game) -> Option<Outcome> {
+        match (
+            RacingKings::on_goal(game, Color::White),
+            RacingKings::on_goal(game, Color::Black),
+        ) {
+            (true, true) => Some(Outcome::Draw),
+            (false, true) => Some(Outcome::BlackWins),
+            (true, false) => {
+                if !game.is_whites_turn() && self.black_can_follow(game) {
+                    None
+                } else {
+                    Some(Outcome::WhiteWins)
+                }
+            }
+            (false, false) => None,
+        }
+    }
+}
+
+pub fn variants() -> Vec<Arc<dyn Variant>> {
+    vec![
+        Arc::new(Standard),
+        Arc::new(Capablanca),
+        Arc::new(Gothic),
+        Arc::new(Grand),
+        Arc::new(InfiniteChess),
+        Arc::new(Atomic),
+        Arc::new(KingOfTheHill),
+        Arc::new(ThreeCheck),
+        Arc::new(Crazyhouse),
+        Arc::new(Bughouse),
+        Arc::new(DarkChess),
+        Arc::new(Kriegspiel),
+        Arc::new(DuckChess),
+        Arc::new(Antichess),
+        Arc::new(Horde),
+        Arc::new(RacingKings),
+    ]
+}
+
+pub fn by_name(name: &str) -> Option<Arc<dyn Variant>> {
+    variants().into_iter().find(|v| v.name() == name)
+}
@@ -0,0 +1,454 @@
+use chackad::fen;
+use chackad::game::Game;
+use chackad::pgn::{self, Pgn};
+use chackad::position::PositionBuilder;
+use chackad::san::{move_from_san, move_to_san};
+use chackad::search::{SearchLimits, Searcher, MATE};
+use chackad::uci::move_to_uci;
+use chackad::variant::{self, Standard, Variant};
+use chackad::Error;
+
+use serde_json::{json, Map, Value};
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
+
+const TT_SIZE: usize = 1 << 20;
+
+// Every command returns whether all its input was valid, or what kept it
+// from running at all
+pub type CommandResult = Result<bool, String>;
+
+// Takes --variant NAME out of the arguments, regular chess without it
+fn variant_option(args: &[String]) -> Result<(Arc<dyn Variant>, Vec<String>), String> {
+    let mut variant: Arc<dyn Variant> = Arc::new(Standard);
+    let mut rest = Vec::new();
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        if word == "--variant" {
+            let name = words.next().ok_or("--variant needs a name")?;
+            variant =
+                variant::by_name(name).ok_or_else(|| format!("unknown variant '{}'", name))?;
+        } else {
+            rest.push(word.clone());
+        }
+    }
+    Ok((variant, rest))
+}
+
+// The start of the variant for "startpos", a FEN played by its rules
+// otherwise
+fn parse_position(text: &str, variant: &Arc<dyn Variant>) -> Result<Game, Error> {
+    let mut game = Game::with_variant(variant.clone());
+    if text != "startpos" && !text.is_empty() {
+        game.load_fen(text)?;
+    }
+    Ok(game)
+}
+
+// "startpos" or a FEN, which may be split over several arguments
+fn position(words: &[String], variant: &Arc<dyn Variant>) -> Result<Game, String> {
+    let text = words.join(" ");
+    parse_position(&text, variant).map_err(|e| format!("invalid FEN '{}': {}", text, e))
+}
+
+fn depth(word: Option<&String>) -> Result<u32, String> {
+    word.and_then(|w| w.parse().ok())
+        .ok_or_else(|| "the depth has to be a number".to_string())
+}
+
+// A file name or - for standard input
+fn read_input(name: &str) -> Result<String, String> {
+    if name == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("could not read standard input: {}", e))?;
+        return Ok(text);
+    }
+    fs::read_to_string(name).map_err(|e| format!("could not read {}: {}", name, e))
+}
+
+// A closed pipe, as when the output goes through head, ends the command
+// quietly
+fn print(text: &str) {
+    if write!(io::stdout(), "{}", text).is_err() {
+        process::exit(0);
+    }
+}
+
+fn print_json(value: &Value) {
+    print(&format!("{}\n", value));
+}
+
+// perft <fen|startpos> <depth> [--variant NAME]
+pub fn perft(args: &[String]) -> CommandResult {
+    let (variant, args) = variant_option(args)?;
+    let (depth_arg, fen_args) = args
+        .split_last()
+        .ok_or("perft needs a position and a depth")?;
+    let game = position(fen_args, &variant)?;
+    let depth = depth(Some(depth_arg))?;
+    let start = Instant::now();
+    let nodes = game.perft(depth);
+    let elapsed = start.elapsed();
+    print_json(&json!({
+        "fen": fen::to_fen(&game),
+        "depth": depth,
+        "nodes": nodes,
+        "time_ms": elapsed.as_millis() as u64,
+        "nps": (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64,
+    }));
+    Ok(true)
+}
+
+// divide <fen|startpos> <depth> [--variant NAME], the perft count below every legal move
+pub fn divide(args: &[String]) -> CommandResult {
+    let (variant, args) = variant_option(args)?;
+    let (depth_arg, fen_args) = args
+        .split_last()
+        .ok_or("divide needs a position and a depth")?;
+    let game = position(fen_args, &variant)?;
+    let depth = depth(Some(depth_arg))?;
+    let mut moves = Map::new();
+    let mut total = 0;
+    for (m, nodes) in game.divide(depth) {
+        moves.insert(move_to_uci(&game, &m, false), json!(nodes));
+        total += nodes;
+    }
+    print_json(&json!({
+        "fen": fen::to_fen(&game),
+        "depth": depth,
+        "moves": moves,
+        "nodes": total,
+    }));
+    Ok(true)
+}
+
+// fen-validate [fen] [--variant NAME], reading one FEN per line from
+// standard input without one. Positions of regular chess that parse are
+// also checked for being reachable.
+pub fn fen_validate(args: &[String]) -> CommandResult {
+    let (variant, args) = variant_option(args)?;
+    let fens: Vec<String> = if args.is_empty() {
+        read_input("-")?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect()
+    } else {
+        vec![args.join(" ")]
+    };
+    let mut all_valid = true;
+    for text in fens {
+        // The reachability checks only know the rules of regular chess
+        let checked = parse_position(&text, &variant).and_then(|game| {
+            if game.variant().name() == Standard.name() {
+                PositionBuilder::from_game(&game).build()?;
+            }
+            Ok(game)
+        });
+        let report = match checked {
+            Ok(game) => json!({
+                "fen": text,
+                "valid": true,
+                "side_to_move": if game.is_whites_turn() { "white" } else { "black" },
+                "legal_moves": game.legal_moves().len(),
+                "in_check": game.in_check(),
+                "result": pgn::result_string(game.outcome()),
+            }),
+            Err(e) => {
+                all_valid = false;
+                json!({ "fen": text, "valid": false, "error": e.to_string() })
+            }
+        };
+        print_json(&report);
+    }
+    Ok(all_valid)
+}
+
+fn tags_json(game: &Pgn) -> Value {
+    let tags: Map<String, Value> = game
+        .tags
+        .iter()
+        .map(|(name, value)| (name.clone(), json!(value)))
+        .collect();
+    Value::Object(tags)
+}
+
+fn read_pgn(args: &[String], command: &str) -> Result<Vec<Pgn>, String> {
+    let name = args
+        .first()
+        .ok_or_else(|| format!("{} needs a PGN file, or - for standard input", command))?;
+    pgn::parse(&read_input(name)?).map_err(|e| format!("{}: {}", name, e))
+}
+
+// pgn-validate <file>, replays every game and reports the first illegal
+// move of each
+pub fn pgn_validate(args: &[String]) -> CommandResult {
+    let mut all_valid = true;
+    for (i, game) in read_pgn(args, "pgn-validate")?.iter().enumerate() {
+        let mut report = json!({
+            "game": i + 1,
+            "tags": tags_json(game),
+            "plies": game.moves.len(),
+        });
+        match game.replay() {
+            Ok(positions) => {
+                report["valid"] = json!(true);
+                let last = positions.last().unwrap();
+                let result = pgn::result_string(last.outcome());
+                // Games may end in resignation, but not on the board with
+                // another result
+                if result != "*" && result != game.result {
+                    report["valid"] = json!(false);
+                    report["error"] = json!(format!(
+                        "the game ends in {} but the result is {}",
+                        result, game.result
+                    ));
+                    all_valid = false;
+                }
+            }
+            Err(e) => {
+                all_valid = false;
+                report["valid"] = json!(false);
+                report["error"] = json!(e.to_string());
+            }
+        }
+        print_json(&report);
+    }
+    Ok(all_valid)
+}
+
+// pgn-to-fen <file>, the position after every ply of every game
+pub fn pgn_to_fen(args: &[String]) -> CommandResult {
+    let mut all_valid = true;
+    for (i, game) in read_pgn(args, "pgn-to-fen")?.iter().enumerate() {
+        let mut position = match game.start_position() {
+            Ok(position) => position,
+            Err(e) => {
+                all_valid = false;
+                print_json(&json!({ "game": i + 1, "error": e.to_string() }));
+                continue;
+            }
+        };
+        print_json(&json!({
+            "game": i + 1,
+            "ply": 0,
+            "move": null,
+            "fen": fen::to_fen(&position),
+        }));
+        for (ply, san) in game.moves.iter().enumerate() {
+            let m = match move_from_san(&position, san) {
+                Some(m) => m,
+                None => {
+                    all_valid = false;
+                    print_json(&json!({
+                        "game": i + 1,
+                        "ply": ply + 1,
+                        "move": san,
+                        "error": format!("illegal move {}", san),
+                    }));
+                    break;
+                }
+            };
+            let san = move_to_san(&position, &m);
+            if let Err(e) = position.next(m) {
+                all_valid = false;
+                print_json(&json!({
+                    "game": i + 1,
+                    "ply": ply + 1,
+                    "move": san,
+                    "error": e.to_string(),
+                }));
+                break;
+            }
+            print_json(&json!({
+                "game": i + 1,
+                "ply": ply + 1,
+                "move": san,
+                "fen": fen::to_fen(&position),
+            }));
+        }
+    }
+    Ok(all_valid)
+}
+
+// analyse <fen|startpos> [--depth N] [--multipv N] [--variant NAME]
+pub fn analyse(args: &[String]) -> CommandResult {
+    let (variant, args) = variant_option(args)?;
+    let mut fen_args = Vec::new();
+    let mut limits = SearchLimits::depth(6);
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "--depth" => limits.depth = depth(words.next())?,
+            "--multipv" => {
+                limits.multi_pv = depth(words.next())
+                    .map_err(|_| "--multipv needs a number".to_string())?
+                    .max(1) as usize
+            }
+            _ => fen_args.push(word.clone()),
+        }
+    }
+    let game = position(&fen_args, &variant)?;
+    let result = Searcher::new(TT_SIZE).search(&game, &limits);
+    let lines: Vec<Value> = result
+        .lines
+        .iter()
+        .map(|line| {
+            let mut position = game.clone();
+            let mut pv = Vec::new();
+            for m in line.pv.iter() {
+                pv.push(move_to_san(&position, m));
+                if position.next(*m).is_err() {
+                    break;
+                }
+            }
+            json!({ "score": score_json(line.score), "pv": pv })
+        })
+        .collect();
+    print_json(&json!({
+        "fen": fen::to_fen(&game),
+        "depth": result.depth,
+        "nodes": result.nodes,
+        "best_move": result.best_move.map(|m| move_to_uci(&game, &m, false)),
+        "score": score_json(result.score),
+        "lines": lines,
+    }));
+    Ok(true)
+}
+
+// Positions searched by bench: the opening, a busy middlegame and an endgame
+const BENCH_POSITIONS: [&str; 4] = [
+    fen::STARTPOS,
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+// bench [--depth N] [--threads N], searches fixed positions and reports the
+// speed, to compare builds and thread counts
+pub fn bench(args: &[String]) -> CommandResult {
+    let mut limits = SearchLimits::depth(5);
+    let mut words = args.iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "--depth" => limits.depth = depth(words.next())?,
+            "--threads" => {
+                limits.threads = depth(words.next())
+                    .map_err(|_| "--threads needs a number".to_string())?
+                    .max(1) as usize
+            }
+            other => return Err(format!("unknown bench option '{}'", other)),
+        }
+    }
+    let mut total_nodes = 0;
+    let start = Instant::now();
+    for text in BENCH_POSITIONS.iter() {
+        let game = fen::from_fen(text).map_err(|e| e.to_string())?;
+        let position_start = Instant::now();
+        let result = Searcher::new(TT_SIZE).search(&game, &limits);
+        let elapsed = position_start.elapsed();
+        total_nodes += result.nodes;
+        print_json(&json!({
+            "fen": text,
+            "depth": result.depth,
+            "nodes": result.nodes,
+            "best_move": result.best_move.map(|m| move_to_uci(&game, &m, false)),
+            "time_ms": elapsed.as_millis() as u64,
+            "nps": (result.nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64,
+        }));
+    }
+    let elapsed = start.elapsed();
+    print_json(&json!({
+        "threads": limits.threads,
+        "depth": limits.depth,
+        "nodes": total_nodes,
+        "time_ms": elapsed.as_millis() as u64,
+        "nps": (total_nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64,
+    }));
+    Ok(true)
+}
+
+// Centipawns or moves to mate from the side to move, as UCI reports them
+fn score_json(score: i32) -> Value {
+    if score.abs() > MATE - 1000 {
+        let moves = (MATE - score.abs() + 1) / 2;
+        json!({ "mate": if score < 0 { -moves } else { moves } })
+    } else {
+        json!({ "cp": score })
+    }
+}
+
+// convert <file>, PGN games to a JSON array and such an array back to PGN
+pub fn convert(args: &[String]) -> CommandResult {
+    let name = args
+        .first()
+        .ok_or("convert needs a PGN or JSON file, or - for standard input")?;
+    let text = read_input(name)?;
+    if let Ok(value) = serde_json::from_str::<Value>(&text) {
+        let games = value
+            .as_array()
+            .ok_or("the JSON has to be an array of games")?;
+        for (i, game) in games.iter().enumerate() {
+            let game = pgn_from_json(game).map_err(|e| format!("game {}: {}", i + 1, e))?;
+            if i > 0 {
+                print("\n");
+            }
+            print(&game.to_string());
+        }
+        return Ok(true);
+    }
+    let games = pgn::parse(&text).map_err(|e| format!("{}: {}", name, e))?;
+    let games: Vec<Value> = games
+        .iter()
+        .map(|game| {
+            json!({
+                "tags": tags_json(game),
+                "moves": game.moves,
+                "result": game.result,
+            })
+        })
+        .collect();
+    print(&format!(
+        "{}\n",
+        serde_json::to_string_pretty(&games).unwrap()
+    ));
+    Ok(true)
+}
+
+fn pgn_from_json(value: &Value) -> Result<Pgn, String> {
+    let tags = match value.get("tags") {
+        Some(Value::Object(tags)) => tags
+            .iter()
+            .map(|(name, value)| match value {
+                Value::String(s) => Ok((name.clone(), s.clone())),
+                _ => Err(format!("tag {} is not a string", name)),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+        Some(_) => return Err("tags has to be an object".to_string()),
+    };
+    let moves = match value.get("moves") {
+        Some(Value::Array(moves)) => moves
+            .iter()
+            .map(|m| m.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("moves have to be strings")?,
+        _ => return Err("moves has to be an array".to_string()),
+    };
+    let result = value
+        .get("result")
+        .and_then(Value::as_str)
+        .unwrap_or("*")
+        .to_string();
+    Ok(Pgn {
+        tags,
+        moves,
+        result,
+    })
+}
@@ -0,0 +1,82 @@
+mod commands;
+
+use chackad::repl::Repl;
+use chackad::uci::Uci;
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::process;
+
+const USAGE: &str = "\
+usage: chackad [command] [arguments]
+
+Without a command chackad plays chess in the terminal.
+
+Commands:
+  play                                  play in the terminal, type help there
+  uci                                   speak UCI to a chess GUI
+  perft <fen|startpos> <depth>          count the positions depth plies ahead
+  divide <fen|startpos> <depth>         perft below every legal move
+  fen-validate [fen]                    check a FEN, or one per line of input
+  pgn-validate <file>                   replay games and report illegal moves
+  pgn-to-fen <file>                     the FEN after every ply of every game
+  analyse <fen|startpos> [--depth N] [--multipv N]
+                                        search the position
+  convert <file>                        PGN to JSON or JSON back to PGN
+  bench [--depth N] [--threads N]       search fixed positions, nodes per second
+
+perft, divide, fen-validate and analyse take --variant NAME to play by the
+rules of a variant such as crazyhouse or atomic, startpos then being its
+start. Files may be - for standard input. Results are printed as JSON, one object
+per line. The exit status is 1 when some input was invalid and 2 when the
+command could not run.";
+
+fn play() -> commands::CommandResult {
+    let stdin = io::stdin();
+    let mut repl = Repl::new();
+    repl.show_prompt(stdin.is_terminal());
+    repl.run(stdin.lock(), io::stdout())
+        .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn uci() -> commands::CommandResult {
+    let stdin = io::stdin();
+    Uci::new()
+        .run(stdin.lock(), io::stdout())
+        .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => ("play", &args[..]),
+    };
+    let result = match command {
+        "play" => play(),
+        "uci" => uci(),
+        "perft" => commands::perft(rest),
+        "divide" => commands::divide(rest),
+        "fen-validate" => commands::fen_validate(rest),
+        "pgn-validate" => commands::pgn_validate(rest),
+        "pgn-to-fen" => commands::pgn_to_fen(rest),
+        "analyse" | "analyze" => commands::analyse(rest),
+        "convert" => commands::convert(rest),
+        "bench" => commands::bench(rest),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        other => Err(format!("unknown command '{}'\n\n{}", other, USAGE)),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("chackad: {}", e);
+            process::exit(2);
+        }
+    }
+}
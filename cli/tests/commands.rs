@@ -0,0 +1,241 @@
+use serde_json::{json, Value};
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs chackad with the arguments and the input on standard input, and
+// returns the exit status and what it printed
+fn run(args: &[&str], input: &str) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chackad"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Commands that fail early may not read their input at all
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    let status = output.status.code().unwrap();
+    (status, String::from_utf8(output.stdout).unwrap())
+}
+
+// One JSON object per line
+fn json_lines(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+const GAMES: &str = "[Event \"Good\"]\n\n1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n\n\
+                     [Event \"Illegal\"]\n\n1. e4 e5 2. Ke3 *\n\n\
+                     [Event \"Wrong result\"]\n\n1. f3 e5 2. g4 Qh4# 1-0\n";
+
+#[test]
+fn perft() {
+    let (status, output) = run(&["perft", "startpos", "3"], "");
+    assert_eq!(status, 0);
+    let lines = json_lines(&output);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["nodes"], json!(8902));
+    assert_eq!(lines[0]["depth"], json!(3));
+    assert_eq!(
+        lines[0]["fen"],
+        json!("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+    );
+
+    // A FEN may come as several arguments
+    let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let mut args = vec!["perft"];
+    args.extend(kiwipete.split(' '));
+    args.push("1");
+    let (status, output) = run(&args, "");
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output)[0]["nodes"], json!(48));
+
+    let (status, output) = run(&["divide", "startpos", "2"], "");
+    assert_eq!(status, 0);
+    let lines = json_lines(&output);
+    assert_eq!(lines[0]["moves"].as_object().unwrap().len(), 20);
+    assert_eq!(lines[0]["moves"]["e2e4"], json!(20));
+    assert_eq!(lines[0]["nodes"], json!(400));
+
+    // Commands that can't run print nothing and exit with 2
+    assert_eq!(run(&["perft", "startpos", "deep"], ""), (2, String::new()));
+    assert_eq!(run(&["perft", "8/8", "1"], ""), (2, String::new()));
+    assert_eq!(run(&["perft"], ""), (2, String::new()));
+}
+
+#[test]
+fn fen_validate() {
+    let input = "startpos\n\n4k3/8/8/8/8/8/8/4K3 b - - 0 1\nnot a fen\n\
+                 kK6/8/8/8/8/8/8/8 w - - 0 1\n";
+    let (status, output) = run(&["fen-validate"], input);
+    assert_eq!(status, 1);
+    let lines = json_lines(&output);
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0]["valid"], json!(true));
+    assert_eq!(lines[0]["legal_moves"], json!(20));
+    assert_eq!(lines[0]["side_to_move"], json!("white"));
+    assert_eq!(lines[1]["valid"], json!(true));
+    assert_eq!(lines[1]["side_to_move"], json!("black"));
+    assert_eq!(lines[1]["result"], json!("*"));
+    assert_eq!(lines[2]["fen"], json!("not a fen"));
+    assert_eq!(lines[2]["valid"], json!(false));
+    assert!(lines[2]["error"].is_string());
+    // Kings next to each other parse but can't be reached
+    assert_eq!(lines[3]["valid"], json!(false));
+
+    let (status, output) = run(&["fen-validate", "startpos"], "");
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output).len(), 1);
+    let (status, output) = run(&["fen-validate"], "startpos\n");
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output)[0]["valid"], json!(true));
+}
+
+#[test]
+fn variants() {
+    // startpos is the start of the variant
+    let (status, output) = run(&["perft", "--variant", "horde", "startpos", "1"], "");
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output)[0]["nodes"], json!(8));
+    let (status, output) = run(&["divide", "startpos", "1", "--variant", "crazyhouse"], "");
+    assert_eq!(status, 0);
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1";
+    assert_eq!(json_lines(&output)[0]["fen"], json!(fen));
+
+    // Horde has no white king, which only regular chess would reject
+    let (status, output) = run(&["fen-validate", "--variant", "horde"], "startpos\n");
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output)[0]["valid"], json!(true));
+
+    // Taking the queen explodes the king next to it
+    let atomic = ["4k3/3q4/8/8/8/8/8/3QK3", "w", "-", "-", "0", "1"];
+    let mut args = vec!["analyse", "--depth", "1", "--variant", "atomic"];
+    args.extend(atomic.iter());
+    let (status, output) = run(&args, "");
+    assert_eq!(status, 0);
+    let lines = json_lines(&output);
+    assert_eq!(lines[0]["best_move"], json!("d1d7"));
+    assert_eq!(lines[0]["score"], json!({ "mate": 1 }));
+
+    let unknown = run(&["perft", "startpos", "1", "--variant", "nope"], "");
+    assert_eq!(unknown, (2, String::new()));
+    assert_eq!(run(&["analyse", "--variant"], ""), (2, String::new()));
+}
+
+#[test]
+fn pgn_to_fen() {
+    let (status, output) = run(&["pgn-to-fen", "-"], GAMES);
+    assert_eq!(status, 1);
+    let lines = json_lines(&output);
+    let game = |n: u64| -> Vec<&Value> { lines.iter().filter(|l| l["game"] == json!(n)).collect() };
+    assert_eq!(game(1).len(), 8);
+    assert_eq!(game(1)[0]["move"], Value::Null);
+    assert_eq!(game(1)[7]["move"], json!("Qxf7#"));
+    // The illegal move ends its game with an error, the next game goes on
+    let illegal = game(2);
+    assert_eq!(illegal.len(), 4);
+    assert_eq!(illegal[3]["ply"], json!(3));
+    assert_eq!(illegal[3]["error"], json!("illegal move Ke3"));
+    assert_eq!(game(3).len(), 5);
+
+    let good = GAMES.split("\n\n[").next().unwrap();
+    let (status, _) = run(&["pgn-to-fen", "-"], good);
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn pgn_validate() {
+    let (status, output) = run(&["pgn-validate", "-"], GAMES);
+    assert_eq!(status, 1);
+    let lines = json_lines(&output);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["game"], json!(1));
+    assert_eq!(lines[0]["tags"]["Event"], json!("Good"));
+    assert_eq!(lines[0]["plies"], json!(7));
+    assert_eq!(lines[0]["valid"], json!(true));
+    assert_eq!(lines[1]["valid"], json!(false));
+    assert_eq!(lines[1]["error"], json!("illegal move Ke3 at ply 3"));
+    assert_eq!(lines[2]["valid"], json!(false));
+    assert_eq!(
+        lines[2]["error"],
+        json!("the game ends in 0-1 but the result is 1-0")
+    );
+
+    let good = GAMES.split("\n\n[").next().unwrap();
+    let (status, output) = run(&["pgn-validate", "-"], good);
+    assert_eq!(status, 0);
+    assert_eq!(json_lines(&output).len(), 1);
+
+    assert_eq!(run(&["pgn-validate"], ""), (2, String::new()));
+    assert_eq!(
+        run(&["pgn-validate", "-"], "1. e4 {open"),
+        (2, String::new())
+    );
+    let missing = run(&["pgn-validate", "no/such/file.pgn"], "");
+    assert_eq!(missing, (2, String::new()));
+}
+
+#[test]
+fn convert() {
+    let (status, output) = run(&["convert", "-"], GAMES);
+    assert_eq!(status, 0);
+    let games: Value = serde_json::from_str(&output).unwrap();
+    let games = games.as_array().unwrap();
+    assert_eq!(games.len(), 3);
+    assert_eq!(games[0]["tags"], json!({ "Event": "Good" }));
+    assert_eq!(
+        games[0]["moves"],
+        json!(["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"])
+    );
+    assert_eq!(games[0]["result"], json!("1-0"));
+    assert_eq!(games[1]["result"], json!("*"));
+
+    // And back to the same games in PGN
+    let (status, pgn) = run(&["convert", "-"], &output);
+    assert_eq!(status, 0);
+    assert!(pgn.starts_with("[Event \"Good\"]\n\n1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n"));
+    let (status, again) = run(&["convert", "-"], &pgn);
+    assert_eq!(status, 0);
+    assert_eq!(again, output);
+
+    assert_eq!(run(&["convert", "-"], "{\"tags\": {}}"), (2, String::new()));
+    assert_eq!(
+        run(&["convert", "-"], "[{\"moves\": 4}]"),
+        (2, String::new())
+    );
+    assert_eq!(run(&["convert"], ""), (2, String::new()));
+}
+
+#[test]
+fn bench() {
+    let (status, output) = run(&["bench", "--depth", "2", "--threads", "2"], "");
+    assert_eq!(status, 0);
+    let lines = json_lines(&output);
+    assert_eq!(lines.len(), 5);
+    for line in lines[..4].iter() {
+        assert_eq!(line["depth"], json!(2));
+        assert!(line["best_move"].is_string());
+        assert!(line["nodes"].as_u64().unwrap() > 0);
+    }
+    let total: u64 = lines[..4]
+        .iter()
+        .map(|l| l["nodes"].as_u64().unwrap())
+        .sum();
+    assert_eq!(lines[4]["nodes"], json!(total));
+    assert_eq!(lines[4]["threads"], json!(2));
+
+    assert_eq!(run(&["bench", "--threads"], ""), (2, String::new()));
+    assert_eq!(run(&["bench", "--fast"], ""), (2, String::new()));
+}
+
+#[test]
+fn unknown_commands() {
+    assert_eq!(run(&["castle"], ""), (2, String::new()));
+    let (status, output) = run(&["help"], "");
+    assert_eq!(status, 0);
+    assert!(output.starts_with("usage: chackad"));
+}
@@ -9,11 +9,29 @@ use ggez::input::*;
 use ggez::nalgebra as na;
 use ggez::{Context, GameResult};
 
+use chackad::board::Board;
+use chackad::bughouse::BughouseMatch;
+use chackad::fen;
+use chackad::fog::Visibility;
 use chackad::game::Game;
-use chackad::piece::{Piece, PieceType};
+use chackad::multiplayer::{Mode, MultiplayerGame};
+use chackad::piece::{Color, Piece, PieceType};
+use chackad::search::{SearchHandle, SearchInfo, SearchLimits, SearchObserver, Searcher};
+use chackad::uci::move_to_uci;
+use chackad::variant::{DarkChess, DuckChess, Kriegspiel};
+use chackad::Square;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Red, blue, yellow and green armies of four player chess
+const PLAYER_COLORS: [u32; 4] = [0xbf3b43, 0x4185bf, 0xc09526, 0x4e9161];
+const PLAYER_NAMES: [&str; 4] = ["red", "blue", "yellow", "green"];
+
+// Minutes on each clock of a bughouse match
+const BUGHOUSE_MINUTES: u64 = 3;
 
 struct Assets {
-    chessboard: graphics::Image,
     pieces: Vec<graphics::Image>,
     checkmate_text: graphics::Text,
     checkmate_draw_param: graphics::DrawParam,
@@ -21,7 +39,6 @@ struct Assets {
 
 impl Assets {
     fn new(ctx: &mut Context, board_size: f32) -> GameResult<Assets> {
-        let chessboard = graphics::Image::new(ctx, "/chessboard.png")?;
         const PIECE_TEXTURE_PATHS: [&str; 12] = [
             "/pieces/white_pawn.png",
             "/pieces/white_rook.png",
@@ -57,29 +74,153 @@ impl Assets {
                 ));
 
         Ok(Assets {
-            chessboard,
             pieces,
             checkmate_text,
             checkmate_draw_param,
         })
     }
 
-    fn piece_image(&self, white: bool, piece: PieceType) -> &graphics::Image {
+    // Pieces without an image of their own are drawn as letters
+    fn piece_image(&self, white: bool, piece: PieceType) -> Option<&graphics::Image> {
         let offset = if white { 0 } else { 6 };
-        &self.pieces[piece as usize + offset]
+        if piece.index() < 6 {
+            Some(&self.pieces[piece.index() + offset])
+        } else {
+            None
+        }
+    }
+
+    fn draw_piece(
+        &self,
+        ctx: &mut Context,
+        board: &Board,
+        piece_type: PieceType,
+        color: Color,
+        dest: na::Point2<f32>,
+        tile_size: f32,
+    ) -> GameResult {
+        let image = match color {
+            Color::White | Color::Black => self.piece_image(color == Color::White, piece_type),
+            _ => None,
+        };
+        if let Some(image) = image {
+            let scale = tile_size / image.width() as f32;
+            return graphics::draw(
+                ctx,
+                image,
+                graphics::DrawParam::new().dest(dest).scale([scale, scale]),
+            );
+        }
+        let letter = fen::piece_type_char(board, piece_type);
+        let mut text = graphics::Text::new(letter.to_ascii_uppercase());
+        text.set_font(
+            graphics::Font::default(),
+            graphics::Scale::uniform(tile_size * 0.8),
+        );
+        let color = match color {
+            Color::White => graphics::WHITE,
+            Color::Black => graphics::BLACK,
+            Color::Neutral => graphics::Color::from_rgb_u32(0xf5c518),
+            Color::Player(i) => graphics::Color::from_rgb_u32(PLAYER_COLORS[i as usize % 4]),
+        };
+        graphics::draw(
+            ctx,
+            &text,
+            graphics::DrawParam::new().dest(dest).color(color),
+        )
+    }
+}
+
+// Buttons for the moves of the piece on the square, None unless the side
+// to move has a piece there
+fn move_buttons_from(game: &Game, square: Square) -> Option<Vec<MoveButton>> {
+    let board = game.board();
+    let own = Color::from_white(game.is_whites_turn());
+    if board.is_empty_tile(square) || board.piece_color(square) != own {
+        return None;
+    }
+    let moves = game
+        .moves_from(square)
+        .map(|moves| moves.as_slice())
+        .unwrap_or(&[]);
+    Some(moves.iter().map(|m| MoveButton::new(*m)).collect())
+}
+
+// The kinds of pieces in a pocket and how many there are of each
+fn pocket_groups(pocket: &[PieceType]) -> Vec<(PieceType, usize)> {
+    let mut groups: Vec<(PieceType, usize)> = Vec::new();
+    for piece_type in pocket {
+        match groups.iter_mut().find(|(p, _)| p == piece_type) {
+            Some((_, count)) => *count += 1,
+            None => groups.push((*piece_type, 1)),
+        }
+    }
+    groups
+}
+
+// Column and row a square of a bughouse board is drawn in. Board B is
+// turned around, so partners sit on the same side of the table.
+fn bughouse_cell(board: usize, x: usize, y: usize) -> (usize, usize) {
+    if board == 0 {
+        (x, y)
+    } else {
+        (7 - x, 7 - y)
+    }
+}
+
+fn clock_text(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+// Writes the latest search progress to a string shared with the draw loop
+struct AnalysisText {
+    game: Game,
+    text: Arc<Mutex<String>>,
+}
+
+impl SearchObserver for AnalysisText {
+    fn progress(&mut self, info: &SearchInfo) {
+        let mut game = self.game.clone();
+        let mut pv = Vec::new();
+        for m in info.pv.iter() {
+            pv.push(move_to_uci(&game, m, false));
+            if game.next(*m).is_err() {
+                break;
+            }
+        }
+        *self.text.lock().unwrap() = format!(
+            "depth {}\nscore {}\nnodes {}\n\n{}",
+            info.depth,
+            info.score,
+            info.nodes,
+            pv.join("\n")
+        );
     }
 }
 
 struct GameState {
     game: Game,
-    selected_tile: Option<(usize, usize)>,
+    // Shown instead of game while four players are at the board
+    four_player: Option<MultiplayerGame>,
+    // Shown instead of game during a bughouse match, with the time the side
+    // to move on each board started thinking
+    bughouse: Option<BughouseMatch>,
+    thinking_since: [Instant; 2],
+    // Bughouse board the selection and the move buttons belong to
+    selected_board: usize,
+    selected_tile: Option<Square>,
     move_buttons: Vec<MoveButton>,
 
+    // Background analysis of the current position
+    searcher: Searcher,
+    analysis: Option<SearchHandle>,
+    analysis_text: Arc<Mutex<String>>,
+
     assets: Assets,
     // Graphical
     board_size: f32,
     tile_size: f32,
-    scale_factor: f32,
 }
 
 impl GameState {
@@ -89,44 +230,377 @@ impl GameState {
 
         let size = graphics::size(ctx);
         let min = size.0.min(size.1);
-        let s = GameState {
+        let mut s = GameState {
             game,
+            four_player: None,
+            bughouse: None,
+            thinking_since: [Instant::now(); 2],
+            selected_board: 0,
             selected_tile: None,
             move_buttons: Vec::with_capacity(10),
 
+            searcher: Searcher::new(1 << 16),
+            analysis: None,
+            analysis_text: Arc::new(Mutex::new(String::new())),
+
             assets: Assets::new(ctx, min)?,
 
             board_size: min,
             tile_size: min / 8.0,
-            scale_factor: min / 1000.0,
         };
+        s.new_game();
         Ok(s)
     }
 }
 
 impl GameState {
-    fn select_tile(&mut self, x: usize, y: usize) {
-        let board = self.game.board();
+    // Sizes the tiles after the board of the current game. The two boards
+    // of bughouse and the pockets above and below them share the window.
+    fn new_game(&mut self) {
+        let board = self.board();
+        self.tile_size = if self.bughouse.is_some() {
+            self.board_size / 13.0
+        } else {
+            self.board_size / board.size_x.max(board.size_y) as f32
+        };
+        self.thinking_since = [Instant::now(); 2];
+        self.selected_tile = None;
+        self.move_buttons.clear();
+        self.start_analysis();
+    }
+
+    fn start_analysis(&mut self) {
+        // Dropping the old handle stops its search
+        self.analysis = None;
+        self.analysis_text.lock().unwrap().clear();
+        // The engine would give away what the players can't see, and it
+        // only knows two player games
+        if self.is_over()
+            || self.four_player.is_some()
+            || self.bughouse.is_some()
+            || self.game.variant().visibility() != Visibility::Full
+        {
+            return;
+        }
+
+        let mut limits = SearchLimits::depth(1);
+        limits.infinite = true;
+        let observer = AnalysisText {
+            game: self.game.clone(),
+            text: self.analysis_text.clone(),
+        };
+        self.analysis = Some(
+            self.searcher
+                .spawn(self.game.clone(), limits, false, observer),
+        );
+    }
+
+    fn board(&self) -> &Board {
+        if let Some(bughouse) = &self.bughouse {
+            return bughouse.board(0).board();
+        }
+        match &self.four_player {
+            Some(game) => game.board(),
+            None => self.game.board(),
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        if let Some(bughouse) = &self.bughouse {
+            return bughouse.end().is_some();
+        }
+        match &self.four_player {
+            Some(game) => game.winners().is_some(),
+            None => self.game.no_moves(),
+        }
+    }
 
-        if !board.is_empty_tile(x, y) && self.game.is_whites_turn() == board.is_piece_white(x, y) {
-            self.selected_tile = Some((x, y));
+    fn select_tile(&mut self, square: Square) {
+        if let Some(game) = &self.four_player {
+            let own = Color::Player(game.turn() as u8);
+            if game.piece_at(square).map(|p| p.color) == Some(own) {
+                self.selected_tile = Some(square);
+                self.move_buttons = game
+                    .legal_moves()
+                    .iter()
+                    .filter(|m| m.from == square)
+                    .map(|m| MoveButton::new(*m))
+                    .collect();
+            }
+            return;
+        }
+        if let Some(buttons) = move_buttons_from(&self.game, square) {
+            self.selected_tile = Some(square);
+            self.move_buttons = buttons;
+        }
+    }
+
+    // The duck goes wherever it is clicked, so every placement gets a button
+    fn show_duck_placements(&mut self) {
+        self.move_buttons = self
+            .game
+            .legal_moves()
+            .iter()
+            .map(|m| MoveButton::new(*m))
+            .collect();
+    }
+}
+
+// Bughouse: two boards side by side with the pockets of the players above
+// and below their boards
+impl GameState {
+    // Top left corner of a bughouse board
+    fn bughouse_origin(&self, board: usize) -> (f32, f32) {
+        (board as f32 * 8.5 * self.tile_size, self.tile_size)
+    }
+
+    fn bughouse_click(&mut self, x: f32, y: f32) {
+        let tile_size = self.tile_size;
+        let board = if x < 8.5 * tile_size { 0 } else { 1 };
+        let column = ((x - self.bughouse_origin(board).0) / tile_size).floor();
+        let row = (y / tile_size).floor();
+        if column < 0.0 || column >= 8.0 || row < 0.0 || row > 9.0 {
+            return;
+        }
+        let (column, row) = (column as usize, row as usize);
+        let bughouse = match &mut self.bughouse {
+            Some(bughouse) => bughouse,
+            None => return,
+        };
+        let game = bughouse.board(board);
 
-            // Add move buttons
+        // Rows 0 and 9 hold the pockets, the side on top of board A is white
+        if row == 0 || row == 9 {
+            let color = Color::from_white((row == 0) == (board == 0));
+            let groups = pocket_groups(game.pocket(color));
+            self.selected_tile = None;
             self.move_buttons.clear();
-            if let Some(moves) = self.game.moves_from((x, y)) {
-                for m in moves {
-                    self.move_buttons.push(MoveButton::new((x, y), *m));
+            if let Some((piece_type, _)) = groups.get(column) {
+                if game.side_to_move() == color {
+                    self.selected_board = board;
+                    self.move_buttons = game
+                        .legal_moves()
+                        .iter()
+                        .filter(|m| m.is_drop() && m.piece == *piece_type)
+                        .map(|m| MoveButton::new(*m))
+                        .collect();
                 }
             }
+            return;
+        }
+
+        let (file, rank) = bughouse_cell(board, column, row - 1);
+        let square = Square::at(file, rank);
+        if board == self.selected_board {
+            if let Some(button) = self.move_buttons.iter().find(|b| b.inside(square)) {
+                let m = button.get_move();
+                let spent = self.thinking_since[board].elapsed();
+                if bughouse.play(board, m, spent).is_ok() {
+                    self.thinking_since[board] = Instant::now();
+                }
+                self.selected_tile = None;
+                self.move_buttons.clear();
+                return;
+            }
+        }
+        self.selected_tile = None;
+        self.move_buttons.clear();
+        if let Some(buttons) = move_buttons_from(game, square) {
+            self.selected_board = board;
+            self.selected_tile = Some(square);
+            self.move_buttons = buttons;
+        }
+    }
+
+    fn draw_bughouse(&self, ctx: &mut Context, bughouse: &BughouseMatch) -> GameResult {
+        let tile_size = self.tile_size;
+        let rect = |column: usize, row: f32, origin: (f32, f32)| {
+            graphics::Rect::new(
+                origin.0 + column as f32 * tile_size,
+                origin.1 + row * tile_size,
+                tile_size,
+                tile_size,
+            )
+        };
+        for board in 0..2 {
+            let game = bughouse.board(board);
+            let origin = self.bughouse_origin(board);
+            for x in 0..8 {
+                for y in 0..8 {
+                    let (column, row) = bughouse_cell(board, x, y);
+                    let selected = board == self.selected_board
+                        && self.selected_tile == Some(Square::at(x, y));
+                    let color = if selected {
+                        0x89beb3
+                    } else if (x + y) % 2 == 0 {
+                        0xeeeed2
+                    } else {
+                        0x769656
+                    };
+                    let tile = graphics::Mesh::new_rectangle(
+                        ctx,
+                        graphics::DrawMode::fill(),
+                        rect(column, row as f32, origin),
+                        graphics::Color::from_rgb_u32(color),
+                    )?;
+                    graphics::draw(ctx, &tile, graphics::DrawParam::new())?;
+                }
+            }
+
+            for piece in game.view_for(Color::White).pieces() {
+                let (column, row) = bughouse_cell(board, piece.square.file.0, piece.square.rank.0);
+                let at = rect(column, row as f32, origin);
+                self.assets.draw_piece(
+                    ctx,
+                    game.board(),
+                    piece.piece_type,
+                    piece.color,
+                    na::Point2::new(at.x, at.y),
+                    tile_size,
+                )?;
+            }
+
+            // The pieces each player holds, with their number when there
+            // is more than one
+            for color in [Color::White, Color::Black].iter() {
+                let row = if color.is_white() == (board == 0) {
+                    -1.0
+                } else {
+                    8.0
+                };
+                for (column, (piece_type, count)) in
+                    pocket_groups(game.pocket(*color)).into_iter().enumerate()
+                {
+                    let at = rect(column, row, origin);
+                    let dest = na::Point2::new(at.x, at.y);
+                    self.assets.draw_piece(
+                        ctx,
+                        game.board(),
+                        piece_type,
+                        *color,
+                        dest,
+                        tile_size,
+                    )?;
+                    if count > 1 {
+                        let text = graphics::Text::new(count.to_string());
+                        graphics::draw(
+                            ctx,
+                            &text,
+                            graphics::DrawParam::new().color(graphics::WHITE).dest(dest),
+                        )?;
+                    }
+                }
+            }
+
+            if board == self.selected_board {
+                let circle = graphics::Mesh::new_circle(
+                    ctx,
+                    graphics::DrawMode::fill(),
+                    na::Point2::new(tile_size * 0.5, tile_size * 0.5),
+                    tile_size / 4.0,
+                    1.0,
+                    graphics::Color::from_rgba_u32(0x89beb3aa),
+                )?;
+                for button in &self.move_buttons {
+                    let (x, y) = button.to_f32();
+                    let (column, row) = bughouse_cell(board, x as usize, y as usize);
+                    let at = rect(column, row as f32, origin);
+                    graphics::draw(
+                        ctx,
+                        &circle,
+                        graphics::DrawParam::new().dest(na::Point2::new(at.x, at.y)),
+                    )?;
+                }
+            }
+
+            // The clocks, with the thinking time of the side to move taken
+            // off, and how the match ended
+            let clock = bughouse.clock(board);
+            let thinking = self.thinking_since[board].elapsed();
+            let remaining = |white: bool| {
+                let remaining = clock.remaining(white);
+                if bughouse.end().is_none() && game.is_whites_turn() == white {
+                    remaining.checked_sub(thinking).unwrap_or_default()
+                } else {
+                    remaining
+                }
+            };
+            let mut text = format!(
+                "white {}   black {}",
+                clock_text(remaining(true)),
+                clock_text(remaining(false))
+            );
+            if let Some(end) = bughouse.end() {
+                if end.board == board {
+                    text.push_str(&format!("\n{}", end.describe()));
+                }
+            }
+            let text = graphics::Text::new(text);
+            graphics::draw(
+                ctx,
+                &text,
+                graphics::DrawParam::new()
+                    .color(graphics::WHITE)
+                    .dest(na::Point2::new(origin.0, origin.1 + 9.5 * tile_size)),
+            )?;
         }
+        Ok(())
     }
 }
 
 impl event::EventHandler for GameState {
+    // Bughouse clocks run out while nobody clicks
     fn update(&mut self, _ctx: &mut Context) -> GameResult {
+        if let Some(bughouse) = &mut self.bughouse {
+            for board in 0..2 {
+                bughouse.check_flag(board, self.thinking_since[board].elapsed());
+            }
+        }
         Ok(())
     }
 
+    fn key_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        keycode: keyboard::KeyCode,
+        _keymods: keyboard::KeyMods,
+        _repeat: bool,
+    ) {
+        // Number keys start a new game of another variant, 8 and 9 one of
+        // four players for themselves or in teams and 0 a bughouse match
+        if keycode == keyboard::KeyCode::Key0 {
+            let time = Duration::from_secs(BUGHOUSE_MINUTES * 60);
+            self.bughouse = Some(BughouseMatch::new(time, Duration::from_secs(0)));
+            self.four_player = None;
+            self.new_game();
+            return;
+        }
+        let four_player = match keycode {
+            keyboard::KeyCode::Key8 => Some(Mode::FreeForAll),
+            keyboard::KeyCode::Key9 => Some(Mode::Teams),
+            _ => None,
+        };
+        if let Some(mode) = four_player {
+            self.four_player = Some(MultiplayerGame::four_player(mode));
+            self.bughouse = None;
+            self.new_game();
+            return;
+        }
+        match keycode {
+            keyboard::KeyCode::Key1 => self.game.regular_chess_setup(),
+            keyboard::KeyCode::Key2 => self.game.capablanca_setup(),
+            keyboard::KeyCode::Key3 => self.game.gothic_setup(),
+            keyboard::KeyCode::Key4 => self.game.grand_setup(),
+            keyboard::KeyCode::Key5 => self.game = Game::with_variant(Arc::new(DarkChess)),
+            keyboard::KeyCode::Key6 => self.game = Game::with_variant(Arc::new(Kriegspiel)),
+            keyboard::KeyCode::Key7 => self.game = Game::with_variant(Arc::new(DuckChess)),
+            _ => return,
+        }
+        self.four_player = None;
+        self.bughouse = None;
+        self.new_game();
+    }
+
     fn mouse_button_down_event(
         &mut self,
         _ctx: &mut Context,
@@ -134,34 +608,86 @@ impl event::EventHandler for GameState {
         x: f32,
         y: f32,
     ) {
-        if self.game.no_moves() {
+        if self.is_over() {
+            return;
+        }
+        if self.bughouse.is_some() {
+            if button == mouse::MouseButton::Left {
+                self.bughouse_click(x, y);
+            }
             return;
         }
 
         if button == mouse::MouseButton::Left && x < self.board_size {
-            let x = (x / self.tile_size) as usize;
-            let y = (y / self.tile_size) as usize;
+            let square = Square::at((x / self.tile_size) as usize, (y / self.tile_size) as usize);
             for button in &self.move_buttons {
-                if button.inside(x, y) {
-                    self.game.next(button.from(), button.to());
+                if button.inside(square) {
+                    let m = button.get_move();
+                    let played = match &mut self.four_player {
+                        Some(game) => game.play(m),
+                        None => self.game.next(m),
+                    };
                     self.selected_tile = None;
                     self.move_buttons.clear();
+                    if played.is_err() {
+                        return;
+                    }
+                    if self.game.is_placing_duck() {
+                        self.show_duck_placements();
+                    }
+                    self.start_analysis();
 
                     return;
                 }
             }
-            self.select_tile(x, y);
+            self.select_tile(square);
         }
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         graphics::clear(ctx, [0.1, 0.2, 0.3, 1.0].into());
+        if let Some(bughouse) = &self.bughouse {
+            self.draw_bughouse(ctx, bughouse)?;
+            return graphics::present(ctx);
+        }
 
-        let texture_draw_param =
-            graphics::DrawParam::new().scale([self.scale_factor, self.scale_factor]);
+        // Only what the side to move can see is drawn
+        let view = self.game.view_for(self.game.side_to_move());
+        let pieces: Vec<Piece> = match &self.four_player {
+            Some(game) => game.pieces().cloned().collect(),
+            None => view.pieces().to_vec(),
+        };
 
-        // Draw the chessboard
-        graphics::draw(ctx, &self.assets.chessboard, texture_draw_param)?;
+        // Draw the chessboard, holes in it show the background
+        let board = self.board();
+        let (size_x, size_y) = (board.size_x, board.size_y);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                let square = Square::at(x, y);
+                if !board.is_valid_tile(square) {
+                    continue;
+                }
+                let color = if self.four_player.is_none() && !view.is_visible(square) {
+                    graphics::Color::from_rgb_u32(0x404040)
+                } else if (x + y) % 2 == 0 {
+                    graphics::Color::from_rgb_u32(0xeeeed2)
+                } else {
+                    graphics::Color::from_rgb_u32(0x769656)
+                };
+                let rect = graphics::Mesh::new_rectangle(
+                    ctx,
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(
+                        x as f32 * self.tile_size,
+                        y as f32 * self.tile_size,
+                        self.tile_size,
+                        self.tile_size,
+                    ),
+                    color,
+                )?;
+                graphics::draw(ctx, &rect, graphics::DrawParam::new())?;
+            }
+        }
 
         // Draw selected tile
         if let Some(tile) = self.selected_tile {
@@ -169,8 +695,8 @@ impl event::EventHandler for GameState {
                 ctx,
                 graphics::DrawMode::fill(),
                 graphics::Rect::new(
-                    tile.0 as f32 * self.tile_size,
-                    tile.1 as f32 * self.tile_size,
+                    tile.file.0 as f32 * self.tile_size,
+                    tile.rank.0 as f32 * self.tile_size,
                     self.tile_size,
                     self.tile_size,
                 ),
@@ -181,25 +707,15 @@ impl event::EventHandler for GameState {
         }
 
         // Draw the pieces
-        let mut draw_pieces =
-            |iter: std::collections::hash_set::Iter<'_, Piece>, white: bool| -> GameResult {
-                for piece in iter {
-                    let draw_param = texture_draw_param.dest(na::Point2::new(
-                        self.tile_size * piece.pos_x as f32,
-                        self.tile_size * piece.pos_y as f32,
-                    ));
-
-                    graphics::draw(
-                        ctx,
-                        self.assets.piece_image(white, piece.piece_type),
-                        draw_param,
-                    )?;
-                }
-                Ok(())
-            };
-
-        draw_pieces(self.game.white_pieces_iter(), true)?;
-        draw_pieces(self.game.black_pieces_iter(), false)?;
+        let tile_size = self.tile_size;
+        let assets = &self.assets;
+        for piece in pieces.iter() {
+            let dest = na::Point2::new(
+                tile_size * piece.square.file.0 as f32,
+                tile_size * piece.square.rank.0 as f32,
+            );
+            assets.draw_piece(ctx, board, piece.piece_type, piece.color, dest, tile_size)?;
+        }
 
         // Draw move buttons
         let circle = graphics::Mesh::new_circle(
@@ -223,7 +739,33 @@ impl event::EventHandler for GameState {
             )?;
         }
 
-        if self.game.no_moves() {
+        // Draw the analysis next to the board, or the score of four players
+        let text = match &self.four_player {
+            Some(game) => (0..game.players())
+                .map(|p| {
+                    let state = if game.is_eliminated(p) {
+                        " out"
+                    } else if p == game.turn() {
+                        " to move"
+                    } else {
+                        ""
+                    };
+                    format!("{} {}{}", PLAYER_NAMES[p % 4], game.points(p), state)
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            None => self.analysis_text.lock().unwrap().clone(),
+        };
+        let analysis = graphics::Text::new(text);
+        graphics::draw(
+            ctx,
+            &analysis,
+            graphics::DrawParam::new()
+                .color(graphics::WHITE)
+                .dest(na::Point2::new(self.board_size + 20.0, 20.0)),
+        )?;
+
+        if self.is_over() {
             graphics::draw(
                 ctx,
                 &self.assets.checkmate_text,
@@ -247,7 +789,7 @@ pub fn main() -> GameResult {
 
     let mode: conf::WindowMode = Default::default();
     let cb = ggez::ContextBuilder::new("chess", "ggez")
-        .window_mode(mode.dimensions(1000.0, 1000.0))
+        .window_mode(mode.dimensions(1300.0, 1000.0))
         .add_resource_path(resource_dir);
     let (ctx, event_loop) = &mut cb.build()?;
     let state = &mut GameState::new(ctx)?;
@@ -1,28 +1,26 @@
-use chackad::piece::PieceType;
+use chackad::game::Move;
+use chackad::Square;
 
 pub struct MoveButton {
-    from: (usize, usize),
-    to: (usize, usize, PieceType),
+    m: Move,
+    // Square the button is drawn on
+    square: Square,
 }
 
 impl MoveButton {
-    pub fn new(from: (usize, usize), to: (usize, usize, PieceType)) -> Self {
-        Self { from, to }
+    pub fn new(m: Move) -> Self {
+        Self { m, square: m.to }
     }
 
-    pub fn from(&self) -> (usize, usize) {
-        self.from
-    }
-
-    pub fn to(&self) -> (usize, usize, PieceType) {
-        self.to
+    pub fn get_move(&self) -> Move {
+        self.m
     }
 
     pub fn to_f32(&self) -> (f32, f32) {
-        (self.to.0 as f32, self.to.1 as f32)
+        (self.square.file.0 as f32, self.square.rank.0 as f32)
     }
 
-    pub fn inside(&self, x: usize, y: usize) -> bool {
-        x == self.to.0 && y == self.to.1
+    pub fn inside(&self, square: Square) -> bool {
+        square == self.square
     }
 }
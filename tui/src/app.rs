@@ -0,0 +1,527 @@
+use chackad::bughouse::Clock;
+use chackad::fog::Visibility;
+use chackad::game::{Game, Move};
+use chackad::piece::{Color, PieceType};
+use chackad::san::move_to_san;
+use chackad::search::{SearchHandle, SearchInfo, SearchLimits, SearchObserver, Searcher, MATE};
+use chackad::variant::Outcome;
+use chackad::Square;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const ENGINE_DEPTH: u32 = 4;
+const TIME: Duration = Duration::from_secs(5 * 60);
+const INCREMENT: Duration = Duration::from_secs(3);
+
+// Writes the latest search progress to a string shared with the draw loop
+struct AnalysisText {
+    game: Game,
+    text: Arc<Mutex<String>>,
+}
+
+impl SearchObserver for AnalysisText {
+    fn progress(&mut self, info: &SearchInfo) {
+        let mut game = self.game.clone();
+        let mut pv = Vec::new();
+        for m in info.pv.iter() {
+            pv.push(move_to_san(&game, m));
+            if game.next(*m).is_err() {
+                break;
+            }
+        }
+        *self.text.lock().unwrap() = format!(
+            "depth {}  score {}  nodes {}\n{}",
+            info.depth,
+            score_text(info.score, self.game.is_whites_turn()),
+            info.nodes,
+            pv.join(" ")
+        );
+    }
+}
+
+// Scores are from the side to move, shown from white's point of view
+fn score_text(score: i32, whites_turn: bool) -> String {
+    let score = if whites_turn { score } else { -score };
+    if score.abs() > MATE - 1000 {
+        let moves = (MATE - score.abs() + 1) / 2;
+        format!("#{}{}", if score < 0 { "-" } else { "" }, moves)
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
+
+// Everything the terminal shows, changed by the keys and clicks main reads
+pub struct App {
+    pub game: Game,
+    // The positions before every move and what it captured, for undo
+    history: Vec<(Game, Option<(PieceType, Color)>)>,
+    pub moves: Vec<Move>,
+    pub sans: Vec<String>,
+
+    pub cursor: Square,
+    pub selected: Option<Square>,
+    // Moves to the squares that can be picked next
+    pub targets: Vec<Move>,
+    pub flipped: bool,
+    // Lines of the move list scrolled back from the latest move
+    pub scroll: usize,
+
+    clock: Clock,
+    // Time and increment new games start with
+    time_control: (Duration, Duration),
+    // Since when the side to move is thinking
+    turn_start: Instant,
+    // The side whose flag fell
+    pub flag: Option<bool>,
+
+    searcher: Searcher,
+    analysis: Option<SearchHandle>,
+    pub analysis_text: Arc<Mutex<String>>,
+    pub analysing: bool,
+
+    pub message: String,
+}
+
+impl App {
+    pub fn new() -> App {
+        App::with_clock(TIME, INCREMENT)
+    }
+
+    // Both sides start with the time and get the increment after every move
+    pub fn with_clock(time: Duration, increment: Duration) -> App {
+        let mut game = Game::new();
+        game.regular_chess_setup();
+        let mut app = App {
+            game,
+            history: Vec::new(),
+            moves: Vec::new(),
+            sans: Vec::new(),
+            cursor: Square::at(4, 1),
+            selected: None,
+            targets: Vec::new(),
+            flipped: false,
+            scroll: 0,
+            clock: Clock::new(time, increment),
+            time_control: (time, increment),
+            turn_start: Instant::now(),
+            flag: None,
+            searcher: Searcher::new(1 << 16),
+            analysis: None,
+            analysis_text: Arc::new(Mutex::new(String::new())),
+            analysing: true,
+            message: String::new(),
+        };
+        app.start_analysis();
+        app
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.flag.is_some() || self.game.outcome().is_some()
+    }
+
+    // Time left for the side, counting down while it is to move
+    pub fn clock(&self, white: bool) -> Duration {
+        let remaining = self.clock.remaining(white);
+        if white == self.game.is_whites_turn() && !self.is_over() {
+            remaining.saturating_sub(self.turn_start.elapsed())
+        } else {
+            remaining
+        }
+    }
+
+    // Called regularly so the flag falls without a move being made
+    pub fn tick(&mut self) {
+        let white = self.game.is_whites_turn();
+        if !self.is_over() && self.clock(white) == Duration::from_secs(0) {
+            self.flag = Some(white);
+            self.clear_selection();
+            self.start_analysis();
+        }
+    }
+
+    // What the pieces of one side have taken, in the order they were taken
+    pub fn captured_by(&self, white: bool) -> Vec<PieceType> {
+        self.history
+            .iter()
+            .filter_map(|(_, captured)| *captured)
+            .filter(|(_, color)| *color == Color::from_white(!white))
+            .map(|(piece_type, _)| piece_type)
+            .collect()
+    }
+
+    // Result and reason once the game is over, check or the last message
+    // before that
+    pub fn status(&self) -> String {
+        if let Some(white) = self.flag {
+            let winner = if white { "Black" } else { "White" };
+            return format!("{} wins on time", winner);
+        }
+        match self.game.outcome() {
+            Some(Outcome::WhiteWins) => "White wins".to_string(),
+            Some(Outcome::BlackWins) => "Black wins".to_string(),
+            Some(Outcome::Draw) => "Draw".to_string(),
+            None if self.game.is_placing_duck() => "Place the duck".to_string(),
+            None if self.game.in_check() => "Check".to_string(),
+            None => self.message.clone(),
+        }
+    }
+
+    // The numbered lines of the move list that fit in the rows, the latest
+    // ones unless scrolled back
+    pub fn move_list(&self, rows: usize) -> Vec<String> {
+        let lines: Vec<String> = self
+            .sans
+            .chunks(2)
+            .enumerate()
+            .map(|(i, pair)| format!("{:>3}. {}", i + 1, pair.join(" ")))
+            .collect();
+        let end = lines.len() - self.scroll.min(lines.len());
+        let start = end.saturating_sub(rows);
+        lines[start..end].to_vec()
+    }
+
+    // Scrolls the move list one line back, up to the first move
+    pub fn scroll_back(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.sans.len().div_ceil(2));
+    }
+
+    pub fn scroll_forward(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    // Moves the cursor as seen on the screen, so up goes towards black
+    // unless the board is flipped
+    pub fn move_cursor(&mut self, right: i64, up: i64) {
+        let (right, up) = if self.flipped {
+            (-right, -up)
+        } else {
+            (right, up)
+        };
+        let board = self.game.board();
+        if let Some(file) = self.cursor.file.offset(right, board) {
+            self.cursor.file = file;
+        }
+        if let Some(rank) = self.cursor.rank.offset(up, board) {
+            self.cursor.rank = rank;
+        }
+    }
+
+    pub fn click(&mut self, square: Square) {
+        self.cursor = square;
+        self.select();
+    }
+
+    // Plays the move to the cursor when it is on a target, otherwise picks
+    // up the piece under it
+    pub fn select(&mut self) {
+        if self.is_over() {
+            return;
+        }
+        let square = self.cursor;
+        let mut moves = self.targets.iter().filter(|m| m.to == square).copied();
+        // Pawns reaching the last rank become queens where they can
+        let first = moves.next();
+        let queen = moves.find(|m| m.piece == PieceType::Queen);
+        if let Some(m) = queen.or(first) {
+            self.play(m);
+            return;
+        }
+
+        let own = Color::from_white(self.game.is_whites_turn());
+        let moves = match self.game.piece_at(square) {
+            Some(piece) if piece.color == own && !self.game.is_placing_duck() => {
+                self.game.moves_from(square)
+            }
+            _ => None,
+        };
+        match moves {
+            Some(moves) => {
+                self.selected = Some(square);
+                self.targets = moves.clone();
+            }
+            None => self.clear_selection(),
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selected = None;
+        // The duck has to be placed somewhere
+        self.targets = if self.game.is_placing_duck() && !self.is_over() {
+            self.game.legal_moves()
+        } else {
+            Vec::new()
+        };
+    }
+
+    fn play(&mut self, m: Move) {
+        let white = self.game.is_whites_turn();
+        let captured = if self.game.is_capture(&m) {
+            // Only en passant captures on an empty square
+            let piece_type = self
+                .game
+                .piece_at(m.to)
+                .map(|p| p.piece_type)
+                .unwrap_or(PieceType::Pawn);
+            Some((piece_type, Color::from_white(!white)))
+        } else {
+            None
+        };
+        let san = move_to_san(&self.game, &m);
+        let before = self.game.clone();
+        if let Err(e) = self.game.next(m) {
+            self.message = e.to_string();
+            return;
+        }
+        self.history.push((before, captured));
+        self.moves.push(m);
+        self.sans.push(san);
+        self.scroll = 0;
+        self.message.clear();
+
+        // The clock only changes hands once the duck is placed too
+        if self.game.is_whites_turn() != white {
+            if !self.clock.spend(white, self.turn_start.elapsed()) {
+                self.flag = Some(white);
+            }
+            self.turn_start = Instant::now();
+        }
+        self.clear_selection();
+        self.start_analysis();
+    }
+
+    pub fn undo(&mut self) {
+        match self.history.pop() {
+            Some((game, _)) => {
+                self.game = game;
+                self.moves.pop();
+                self.sans.pop();
+                self.flag = None;
+                self.turn_start = Instant::now();
+                self.message.clear();
+            }
+            None => self.message = "No move to take back".to_string(),
+        }
+        self.clear_selection();
+        self.start_analysis();
+    }
+
+    pub fn engine_move(&mut self) {
+        if self.is_over() {
+            return;
+        }
+        // Dropping the handle stops the analysis, which shares the table
+        self.analysis = None;
+        let result = self
+            .searcher
+            .search(&self.game, &SearchLimits::depth(ENGINE_DEPTH));
+        match result.best_move {
+            Some(m) => self.play(m),
+            None => self.start_analysis(),
+        }
+    }
+
+    pub fn new_game(&mut self) {
+        let flipped = self.flipped;
+        let analysing = self.analysing;
+        let (time, increment) = self.time_control;
+        *self = App::with_clock(time, increment);
+        self.flipped = flipped;
+        self.analysing = analysing;
+        self.start_analysis();
+    }
+
+    pub fn toggle_analysis(&mut self) {
+        self.analysing = !self.analysing;
+        self.start_analysis();
+    }
+
+    fn start_analysis(&mut self) {
+        // Dropping the old handle stops its search
+        self.analysis = None;
+        self.analysis_text.lock().unwrap().clear();
+        // The engine would give away what the players can't see
+        if !self.analysing
+            || self.is_over()
+            || self.game.is_placing_duck()
+            || self.game.variant().visibility() != Visibility::Full
+        {
+            return;
+        }
+        let mut limits = SearchLimits::depth(1);
+        limits.infinite = true;
+        let observer = AnalysisText {
+            game: self.game.clone(),
+            text: self.analysis_text.clone(),
+        };
+        self.analysis = Some(
+            self.searcher
+                .spawn(self.game.clone(), limits, false, observer),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn square(name: &str) -> Square {
+        name.parse().unwrap()
+    }
+
+    // Plays moves given as the squares clicked, like "e2e4"
+    fn play(app: &mut App, moves: &[&str]) {
+        for m in moves.iter() {
+            app.click(square(&m[..2]));
+            app.click(square(&m[2..]));
+        }
+    }
+
+    fn quiet_app(time: Duration, increment: Duration) -> App {
+        let mut app = App::with_clock(time, increment);
+        app.toggle_analysis();
+        app
+    }
+
+    #[test]
+    fn clicks_select_and_play() {
+        let mut app = quiet_app(TIME, INCREMENT);
+        app.click(square("g1"));
+        assert_eq!(app.selected, Some(square("g1")));
+        assert_eq!(app.targets.len(), 2);
+        // Another own piece is picked up instead
+        app.click(square("b1"));
+        assert_eq!(app.selected, Some(square("b1")));
+        app.click(square("c3"));
+        assert_eq!(app.sans, ["Nc3"]);
+        assert_eq!(app.selected, None);
+        assert!(app.targets.is_empty());
+
+        // Clicking an empty square or a piece of the other side drops it
+        app.click(square("e7"));
+        app.click(square("e4"));
+        assert_eq!(app.selected, None);
+
+        play(&mut app, &["d7d5", "c3d5"]);
+        assert_eq!(app.sans, ["Nc3", "d5", "Nxd5"]);
+        assert_eq!(app.captured_by(true), [PieceType::Pawn]);
+        app.undo();
+        assert_eq!(app.sans, ["Nc3", "d5"]);
+        assert!(app.captured_by(true).is_empty());
+    }
+
+    #[test]
+    fn clocks_run_for_the_side_to_move() {
+        let mut app = quiet_app(Duration::from_secs(60), Duration::from_secs(2));
+        assert_eq!(app.clock(false), Duration::from_secs(60));
+        thread::sleep(Duration::from_millis(20));
+        assert!(app.clock(true) < Duration::from_secs(60));
+        play(&mut app, &["e2e4"]);
+        // White got the increment, and black's clock runs now
+        assert!(app.clock(true) > Duration::from_secs(61));
+        assert!(app.clock(true) <= Duration::from_secs(62));
+        thread::sleep(Duration::from_millis(20));
+        assert!(app.clock(false) < Duration::from_secs(60));
+        app.tick();
+        assert_eq!(app.flag, None);
+    }
+
+    #[test]
+    fn flag_falls_on_tick() {
+        let mut app = quiet_app(Duration::from_millis(500), Duration::from_secs(0));
+        app.tick();
+        assert_eq!(app.flag, None);
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(app.clock(true), Duration::from_secs(0));
+        app.tick();
+        assert_eq!(app.flag, Some(true));
+        assert!(app.is_over());
+        assert_eq!(app.status(), "Black wins on time");
+        // Nothing more can be played
+        play(&mut app, &["e2e4"]);
+        assert!(app.sans.is_empty());
+
+        // A new game keeps the time control
+        app.new_game();
+        assert_eq!(app.flag, None);
+        assert_eq!(app.clock(false), Duration::from_millis(500));
+        assert!(!app.analysing);
+    }
+
+    #[test]
+    fn move_list_scrolls() {
+        let mut app = quiet_app(TIME, INCREMENT);
+        play(
+            &mut app,
+            &["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4"],
+        );
+        assert_eq!(
+            app.move_list(10),
+            ["  1. e4 e5", "  2. Nf3 Nc6", "  3. Bb5 a6", "  4. Ba4"]
+        );
+        assert_eq!(app.move_list(2), ["  3. Bb5 a6", "  4. Ba4"]);
+
+        app.scroll_back();
+        assert_eq!(app.move_list(2), ["  2. Nf3 Nc6", "  3. Bb5 a6"]);
+        // Back to the first move and no further
+        for _ in 0..10 {
+            app.scroll_back();
+        }
+        assert_eq!(app.scroll, 4);
+        assert!(app.move_list(2).is_empty());
+        app.scroll_forward();
+        assert_eq!(app.move_list(2), ["  1. e4 e5"]);
+
+        // A move shows the latest lines again
+        play(&mut app, &["g8f6"]);
+        assert_eq!(app.scroll, 0);
+        assert_eq!(app.move_list(1), ["  4. Ba4 Nf6"]);
+        app.scroll_forward();
+        assert_eq!(app.scroll, 0);
+    }
+
+    #[test]
+    fn engine_panel_follows_the_analysis() {
+        let game = App::new().game;
+        let text = Arc::new(Mutex::new(String::new()));
+        let mut observer = AnalysisText {
+            game: game.clone(),
+            text: text.clone(),
+        };
+        let e4 = game
+            .legal_moves()
+            .into_iter()
+            .find(|m| move_to_san(&game, m) == "e4")
+            .unwrap();
+        let mut info = SearchInfo {
+            depth: 3,
+            multi_pv: 1,
+            score: 35,
+            pv: vec![e4],
+            nodes: 1234,
+        };
+        observer.progress(&info);
+        assert_eq!(
+            *text.lock().unwrap(),
+            "depth 3  score +0.35  nodes 1234\ne4"
+        );
+        info.score = MATE - 3;
+        observer.progress(&info);
+        assert!(text.lock().unwrap().contains("score #2"));
+
+        // The app starts analysing and stops when told to
+        let mut app = App::new();
+        let mut waited = 0;
+        while app.analysis_text.lock().unwrap().is_empty() && waited < 200 {
+            thread::sleep(Duration::from_millis(25));
+            waited += 1;
+        }
+        assert!(app.analysis_text.lock().unwrap().starts_with("depth "));
+        app.toggle_analysis();
+        assert!(app.analysis_text.lock().unwrap().is_empty());
+        play(&mut app, &["e2e4"]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(app.analysis_text.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+mod app;
+use app::App;
+
+use chackad::display::figurine;
+use chackad::fen;
+use chackad::piece::{Color, Piece};
+use chackad::{File, Rank, Square};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEventKind};
+use crossterm::style::{self, Attribute, Stylize};
+use crossterm::{cursor, execute, queue, terminal};
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+// Where the board starts on the screen, every square is three columns wide
+const BOARD_LEFT: u16 = 4;
+const BOARD_TOP: u16 = 1;
+const SQUARE_WIDTH: u16 = 3;
+// Clocks and captures, the move list is to the right of them
+const PANEL_WIDTH: u16 = 24;
+
+const LIGHT_SQUARE: style::Color = style::Color::Rgb {
+    r: 0xee,
+    g: 0xee,
+    b: 0xd2,
+};
+const DARK_SQUARE: style::Color = style::Color::Rgb {
+    r: 0x76,
+    g: 0x96,
+    b: 0x56,
+};
+const HIDDEN_SQUARE: style::Color = style::Color::Rgb {
+    r: 0x40,
+    g: 0x40,
+    b: 0x40,
+};
+const SELECTED_SQUARE: style::Color = style::Color::Rgb {
+    r: 0x89,
+    g: 0xbe,
+    b: 0xb3,
+};
+const TARGET_SQUARE: style::Color = style::Color::Rgb {
+    r: 0xba,
+    g: 0xca,
+    b: 0x44,
+};
+const LAST_MOVE_SQUARE: style::Color = style::Color::Rgb {
+    r: 0xf6,
+    g: 0xf6,
+    b: 0x69,
+};
+const CURSOR_SQUARE: style::Color = style::Color::Rgb {
+    r: 0x64,
+    g: 0x95,
+    b: 0xed,
+};
+
+const HELP: &str =
+    "arrows/mouse select  enter play  esc cancel  u undo  g engine  a analysis  f flip  n new  pgup/pgdn scroll  q quit";
+
+// Puts the terminal back the way it was, also when drawing panics
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            io::stdout(),
+            terminal::EnterAlternateScreen,
+            event::EnableMouseCapture,
+            cursor::Hide
+        )?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(
+            io::stdout(),
+            cursor::Show,
+            event::DisableMouseCapture,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Screen position of the left edge of a square
+fn square_position(app: &App, square: Square) -> (u16, u16) {
+    let board = app.game.board();
+    let (column, row) = if app.flipped {
+        (board.size_x - 1 - square.file.0, square.rank.0)
+    } else {
+        (square.file.0, board.size_y - 1 - square.rank.0)
+    };
+    (
+        BOARD_LEFT + column as u16 * SQUARE_WIDTH,
+        BOARD_TOP + row as u16,
+    )
+}
+
+// The square under a screen position, if the board is there
+fn square_at(app: &App, x: u16, y: u16) -> Option<Square> {
+    let board = app.game.board();
+    if x < BOARD_LEFT || y < BOARD_TOP {
+        return None;
+    }
+    let column = ((x - BOARD_LEFT) / SQUARE_WIDTH) as usize;
+    let row = (y - BOARD_TOP) as usize;
+    if column >= board.size_x || row >= board.size_y {
+        return None;
+    }
+    let square = if app.flipped {
+        Square::at(board.size_x - 1 - column, row)
+    } else {
+        Square::at(column, board.size_y - 1 - row)
+    };
+    Some(square).filter(|s| board.is_valid_tile(*s))
+}
+
+// Solid figurines coloured for their side read better than outlined ones
+fn piece_symbol(app: &App, piece: &Piece) -> char {
+    match (piece.color, figurine(piece.piece_type, false)) {
+        (Color::White, Some(c)) | (Color::Black, Some(c)) => c,
+        _ => fen::piece_type_char(app.game.board(), piece.piece_type).to_ascii_uppercase(),
+    }
+}
+
+fn piece_color(color: Color) -> style::Color {
+    match color {
+        Color::White => style::Color::White,
+        Color::Black => style::Color::Black,
+        Color::Neutral => style::Color::DarkYellow,
+        Color::Player(_) => style::Color::DarkMagenta,
+    }
+}
+
+fn draw_board<W: Write>(out: &mut W, app: &App) -> io::Result<()> {
+    let board = app.game.board();
+    // Only what the side to move can see is drawn
+    let view = app.game.view_for(app.game.side_to_move());
+    let last_move = app.moves.last();
+    for x in 0..board.size_x {
+        for y in 0..board.size_y {
+            let square = Square::at(x, y);
+            let (column, row) = square_position(app, square);
+            queue!(out, cursor::MoveTo(column, row))?;
+            if !board.is_valid_tile(square) {
+                queue!(out, style::Print("   "))?;
+                continue;
+            }
+            let is_target = app.targets.iter().any(|m| m.to == square);
+            let is_last_move =
+                last_move.is_some_and(|m| (!m.is_drop() && m.from == square) || m.to == square);
+            let background = if square == app.cursor {
+                CURSOR_SQUARE
+            } else if Some(square) == app.selected {
+                SELECTED_SQUARE
+            } else if is_target {
+                TARGET_SQUARE
+            } else if !view.is_visible(square) {
+                HIDDEN_SQUARE
+            } else if is_last_move {
+                LAST_MOVE_SQUARE
+            } else if (x + y) % 2 == 0 {
+                DARK_SQUARE
+            } else {
+                LIGHT_SQUARE
+            };
+            let cell = match view.piece_at(square) {
+                Some(piece) => format!(" {} ", piece_symbol(app, piece))
+                    .with(piece_color(piece.color))
+                    .attribute(Attribute::Bold),
+                None if is_target => " · ".to_string().with(style::Color::Black),
+                None => "   ".to_string().stylize(),
+            };
+            queue!(out, style::PrintStyledContent(cell.on(background)))?;
+        }
+    }
+
+    // Coordinates along the left and bottom edge
+    for y in 0..board.size_y {
+        let (_, row) = square_position(app, Square::at(0, y));
+        queue!(
+            out,
+            cursor::MoveTo(0, row),
+            style::Print(format!("{:>3}", Rank(y).to_string()))
+        )?;
+    }
+    for x in 0..board.size_x {
+        let (column, _) = square_position(app, Square::at(x, 0));
+        queue!(
+            out,
+            cursor::MoveTo(column, BOARD_TOP + board.size_y as u16),
+            style::Print(format!(" {:<2}", File(x).to_string()))
+        )?;
+    }
+    Ok(())
+}
+
+// Clocks and captures for both sides next to their end of the board and the
+// state of the game between them
+fn draw_panel<W: Write>(out: &mut W, app: &App, left: u16) -> io::Result<()> {
+    let board = app.game.board();
+    let bottom = BOARD_TOP + board.size_y as u16 - 1;
+    let (top_side, bottom_side) = if app.flipped {
+        (true, false)
+    } else {
+        (false, true)
+    };
+    for (white, row) in [(top_side, BOARD_TOP), (bottom_side, bottom - 1)].iter() {
+        let time = app.clock(*white);
+        let name = if *white { "White" } else { "Black" };
+        let mut clock = format!(
+            " {} {:02}:{:02} ",
+            name,
+            time.as_secs() / 60,
+            time.as_secs() % 60
+        )
+        .stylize();
+        if *white == app.game.is_whites_turn() && !app.is_over() {
+            clock = clock.reverse();
+        }
+        let captured: String = app
+            .captured_by(*white)
+            .iter()
+            .map(|piece_type| {
+                figurine(*piece_type, !*white)
+                    .unwrap_or_else(|| fen::piece_type_char(board, *piece_type))
+            })
+            .collect();
+        queue!(
+            out,
+            cursor::MoveTo(left, *row),
+            style::PrintStyledContent(clock),
+            cursor::MoveTo(left, row + 1),
+            style::Print(captured)
+        )?;
+    }
+
+    queue!(
+        out,
+        cursor::MoveTo(left, BOARD_TOP + 3),
+        style::PrintStyledContent(app.status().bold())
+    )
+}
+
+// The latest moves that fit the screen, or earlier ones when scrolled back
+fn draw_moves<W: Write>(out: &mut W, app: &App, left: u16, height: u16) -> io::Result<()> {
+    queue!(
+        out,
+        cursor::MoveTo(left, BOARD_TOP),
+        style::PrintStyledContent("Moves".bold())
+    )?;
+    let rows = height.saturating_sub(BOARD_TOP + 2) as usize;
+    for (i, line) in app.move_list(rows).iter().enumerate() {
+        queue!(
+            out,
+            cursor::MoveTo(left, BOARD_TOP + 1 + i as u16),
+            style::Print(line)
+        )?;
+    }
+    Ok(())
+}
+
+fn draw_analysis<W: Write>(out: &mut W, app: &App, top: u16, width: u16) -> io::Result<()> {
+    let title = if app.analysing {
+        "Engine"
+    } else {
+        "Engine (off)"
+    };
+    queue!(
+        out,
+        cursor::MoveTo(0, top),
+        style::PrintStyledContent(title.bold())
+    )?;
+    let text = app.analysis_text.lock().unwrap().clone();
+    for (i, line) in text.lines().enumerate() {
+        let line: String = line.chars().take(width as usize).collect();
+        queue!(
+            out,
+            cursor::MoveTo(0, top + 1 + i as u16),
+            style::Print(line)
+        )?;
+    }
+    Ok(())
+}
+
+fn draw<W: Write>(out: &mut W, app: &App) -> io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let board = app.game.board();
+    queue!(
+        out,
+        terminal::BeginSynchronizedUpdate,
+        terminal::Clear(terminal::ClearType::All)
+    )?;
+    draw_board(out, app)?;
+    let panel = BOARD_LEFT + board.size_x as u16 * SQUARE_WIDTH + 3;
+    draw_panel(out, app, panel)?;
+    draw_moves(out, app, panel + PANEL_WIDTH, height)?;
+    let below = BOARD_TOP + board.size_y as u16 + 2;
+    draw_analysis(out, app, below, (panel + PANEL_WIDTH).min(width))?;
+    let help: String = HELP.chars().take(width as usize).collect();
+    queue!(
+        out,
+        cursor::MoveTo(0, below + 4),
+        style::PrintStyledContent(help.dim()),
+        terminal::EndSynchronizedUpdate
+    )?;
+    out.flush()
+}
+
+// Returns false when the user quits
+fn handle_key(app: &mut App, key: KeyEvent) -> bool {
+    if key.kind == KeyEventKind::Release {
+        return true;
+    }
+    match key.code {
+        KeyCode::Char('q') => return false,
+        KeyCode::Left | KeyCode::Char('h') => app.move_cursor(-1, 0),
+        KeyCode::Right | KeyCode::Char('l') => app.move_cursor(1, 0),
+        KeyCode::Up | KeyCode::Char('k') => app.move_cursor(0, 1),
+        KeyCode::Down | KeyCode::Char('j') => app.move_cursor(0, -1),
+        KeyCode::Enter | KeyCode::Char(' ') => app.select(),
+        KeyCode::Esc => app.clear_selection(),
+        KeyCode::Char('u') => app.undo(),
+        KeyCode::Char('g') => app.engine_move(),
+        KeyCode::Char('a') => app.toggle_analysis(),
+        KeyCode::Char('f') => app.flipped = !app.flipped,
+        KeyCode::Char('n') => app.new_game(),
+        KeyCode::PageUp => app.scroll_back(),
+        KeyCode::PageDown => app.scroll_forward(),
+        _ => {}
+    }
+    true
+}
+
+fn run(app: &mut App) -> io::Result<()> {
+    let _terminal = Terminal::enter()?;
+    let mut out = io::stdout();
+    loop {
+        draw(&mut out, app)?;
+        // Wakes up for the clocks and the analysis even without input
+        if !event::poll(Duration::from_millis(200))? {
+            app.tick();
+            continue;
+        }
+        let quit = match event::read()? {
+            Event::Key(key) => !handle_key(app, key),
+            Event::Mouse(mouse) if mouse.kind == MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(square) = square_at(app, mouse.column, mouse.row) {
+                    app.click(square);
+                }
+                false
+            }
+            _ => false,
+        };
+        if quit {
+            return Ok(());
+        }
+        app.tick();
+    }
+}
+
+fn main() {
+    let mut app = App::new();
+    if let Err(e) = run(&mut app) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}